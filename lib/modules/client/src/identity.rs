@@ -0,0 +1,55 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use rsa::pkcs1v15::SigningKey;
+use rsa::pkcs8::{DecodePrivateKey, EncodePublicKey, LineEnding};
+use rsa::signature::{SignatureEncoding, Signer};
+use rsa::{RsaPrivateKey, RsaPublicKey};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+
+const KEY_BITS: usize = 2048;
+
+pub struct Identity {
+    key: SigningKey<Sha256>,
+    public_pem: String,
+    fingerprint: String,
+}
+
+impl Identity {
+    /// Loads the launcher's private key, or generates an ephemeral one when
+    /// the client is started on its own. The flag reports which happened.
+    pub fn load(path: &str) -> anyhow::Result<(Self, bool)> {
+        let (key, generated) = if Path::new(path).exists() {
+            (RsaPrivateKey::read_pkcs8_pem_file(path)?, false)
+        } else {
+            (RsaPrivateKey::new(&mut rand::thread_rng(), KEY_BITS)?, true)
+        };
+        let public = RsaPublicKey::from(&key);
+        let public_pem = public.to_public_key_pem(LineEnding::LF)?;
+        let fingerprint = Sha256::digest(public.to_public_key_der()?.as_bytes())
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+        Ok((
+            Self {
+                key: SigningKey::new(key),
+                public_pem,
+                fingerprint,
+            },
+            generated,
+        ))
+    }
+
+    pub fn public_pem(&self) -> &str {
+        &self.public_pem
+    }
+
+    /// What the server calls this key in bans and its moderator list.
+    pub fn fingerprint(&self) -> &str {
+        &self.fingerprint
+    }
+
+    pub fn sign(&self, nonce: &str) -> Option<String> {
+        let nonce = STANDARD.decode(nonce).ok()?;
+        Some(STANDARD.encode(self.key.sign(&nonce).to_bytes()))
+    }
+}
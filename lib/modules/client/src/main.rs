@@ -1,21 +1,32 @@
+use chrono::{DateTime, Local};
 use clap::Parser;
 use crossterm::event::{self, Event, KeyCode, KeyEventKind};
-use serde_json::json;
+use futures::{SinkExt, StreamExt};
+use identity::Identity;
+use protocol::{
+    codec, Away, Ban, BanKind, ChannelRequest, Frame, FrameCodec, FrameLimits, Header, Identify,
+    Kick, Login, Message, Mute, Oper, Presence, PrivateRequest, Quit, Register, Status, Unban,
+    Unmute, Verify, DEFAULT_CHANNEL,
+};
+use sessions::Sessions;
+use std::collections::BTreeMap;
 use std::sync::Arc;
 use textwrap::wrap;
-use tokio::net::tcp::OwnedWriteHalf;
+use tokio::io::WriteHalf;
 use tokio::select;
 use tokio::sync::Mutex;
-use tokio::{
-    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
-    net::TcpStream,
-};
+use tokio_util::codec::{FramedRead, FramedWrite};
+use transport::{Stream, Trust};
 use tui::layout::{Constraint, Direction, Layout};
 use tui::style::{Color, Style};
 use tui::text::{Span, Spans};
 use tui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph};
 use tui::{backend::CrosstermBackend, Terminal};
 
+mod identity;
+mod sessions;
+mod transport;
+
 #[derive(Parser)]
 #[command(name = "rust-irc client")]
 #[command(author = "i358")]
@@ -28,13 +39,44 @@ struct Args {
     host: String,
     #[arg(short = 'p', long = "port", default_value = "0")]
     port: u16,
+    #[arg(short = 'k', long = "key", default_value = "config/identity.pem")]
+    key: String,
+    #[arg(long = "tls")]
+    tls: bool,
+    #[arg(long = "fingerprint")]
+    fingerprint: Option<String>,
+    #[arg(long = "known-hosts", default_value = "config/known_hosts")]
+    known_hosts: String,
+    /// Where resume tokens are kept between runs.
+    #[arg(long = "sessions", default_value = "config/sessions")]
+    sessions: String,
+    /// Logs into the account registered on the username, if there is one.
+    #[arg(long = "password", default_value = "")]
+    password: String,
 }
 
+const SIDEBAR_WIDTH: u16 = 26;
+/// Rosters and history replays come in as single lines, so the server may
+/// send far more than it accepts.
+const SERVER_FRAME_LIMITS: FrameLimits = FrameLimits {
+    line: 1024 * 1024,
+    body: 1024 * 1024,
+};
+
 struct App {
     list_state: ListState,
     items: Vec<String>,
     input: String,
+    channel: String,
+    users: BTreeMap<String, Presence>,
+    sidebar: bool,
+    listing: bool,
+    moderating: bool,
+    muted: bool,
+    /// Largest message body the server takes, from its hello.
+    max_body: usize,
     max_wrap_lines: usize,
+    full_width: usize,
     width: usize,
 }
 
@@ -47,11 +89,62 @@ impl App {
             list_state: state,
             items,
             input: String::new(),
+            channel: String::from(DEFAULT_CHANNEL),
+            users: BTreeMap::new(),
+            sidebar: true,
+            listing: false,
+            moderating: false,
+            muted: false,
+            max_body: codec::MAX_BODY_LENGTH,
             max_wrap_lines: 4,
-            width,
+            full_width: width,
+            width: width.saturating_sub(SIDEBAR_WIDTH as usize),
+        }
+    }
+
+    fn toggle_sidebar(&mut self) {
+        self.sidebar = !self.sidebar;
+        self.width = if self.sidebar {
+            self.full_width.saturating_sub(SIDEBAR_WIDTH as usize)
+        } else {
+            self.full_width
+        };
+    }
+
+    /// Adds or refreshes a user in the sidebar. Join events only carry the
+    /// nickname, so an unknown user starts out online.
+    fn seen(&mut self, presence: Presence) {
+        if !presence.uuid.is_empty() {
+            self.users.retain(|_, user| user.uuid != presence.uuid);
+        }
+        self.users
+            .insert(presence.username.to_lowercase(), presence);
+    }
+
+    fn joined(&mut self, username: &str) {
+        if !self.users.contains_key(&username.to_lowercase()) {
+            self.seen(Presence {
+                username: username.to_string(),
+                uuid: String::new(),
+                status: Status::Online,
+                away: String::new(),
+                operator: false,
+                previous: String::new(),
+            });
         }
     }
 
+    /// Whether the user is in the sidebar, under this or an earlier nickname.
+    fn knows(&self, presence: &Presence) -> bool {
+        self.users.contains_key(&presence.username.to_lowercase())
+            || (!presence.uuid.is_empty()
+                && self.users.values().any(|user| user.uuid == presence.uuid))
+    }
+
+    fn left(&mut self, username: &str) {
+        self.users.remove(&username.to_lowercase());
+    }
+
     fn insert(&mut self, item: &str) {
         let wrapped_lines = wrap(item, self.width);
         for line in wrapped_lines {
@@ -63,8 +156,7 @@ impl App {
     async fn handle_input(
         &mut self,
         key: KeyCode,
-        writer_clone: &Arc<Mutex<OwnedWriteHalf>>,
-        username: &String,
+        writer_clone: &Arc<Mutex<FramedWrite<WriteHalf<Stream>, FrameCodec>>>,
     ) {
         let wrapped_input = wrap(&self.input, self.width);
 
@@ -83,35 +175,226 @@ impl App {
                     }
                 }
             }
-            KeyCode::Char(c) => {
-                if wrapped_input.len() < self.max_wrap_lines {
-                    self.input.push(c);
-                }
+            KeyCode::Char(c) if wrapped_input.len() < self.max_wrap_lines => {
+                self.input.push(c);
             }
             KeyCode::Backspace => {
                 self.input.pop();
             }
-            KeyCode::Enter => {
-                if !self.input.is_empty() {
-                    let data = json!({
-                        "uuid": username,
-                        "content": format!("{}", self.input.clone())
-                    })
-                    .to_string();
-                    let mut writer = writer_clone.lock().await;
-                    writer
-                        .write_all(format!("FN<>::Message {data}\r\n").as_bytes())
-                        .await
-                        .unwrap();
-                    writer.flush().await.unwrap();
-                    self.input.clear();
+            KeyCode::F(2) => self.toggle_sidebar(),
+            KeyCode::Enter if self.input.len() > self.max_body => {
+                self.insert(&format!(
+                    "error: Mesaj çok uzun, sunucu en fazla {} bayt kabul ediyor.",
+                    self.max_body
+                ));
+            }
+            KeyCode::Enter if !self.input.is_empty() => {
+                let previous = self.channel.clone();
+                let frame = if let Some(channel) = self.input.strip_prefix("/join ") {
+                    self.channel = channel.trim().to_lowercase();
+                    Frame::JoinChannel(Header::Fn, ChannelRequest::new(&self.channel))
+                } else if let Some(channel) = self.input.strip_prefix("/part") {
+                    let channel = match channel.trim() {
+                        "" => self.channel.clone(),
+                        channel => channel.to_lowercase(),
+                    };
+                    if channel == self.channel {
+                        self.channel = String::from(DEFAULT_CHANNEL);
+                    }
+                    Frame::PartChannel(Header::Fn, ChannelRequest::new(&channel))
+                } else if let Some(channel) = self.input.strip_prefix("/names") {
+                    self.listing = true;
+                    Frame::Names(
+                        Header::Fn,
+                        ChannelRequest::new(&channel.trim().to_lowercase()),
+                    )
+                } else if let Some(message) = self.input.strip_prefix("/away") {
+                    Frame::Away(
+                        Header::Fn,
+                        Away {
+                            message: message.trim().to_string(),
+                        },
+                    )
+                } else if let Some(command) = moderation(&self.input) {
+                    match command {
+                        Ok(frame) => {
+                            self.moderating = true;
+                            frame
+                        }
+                        Err(usage) => {
+                            self.insert(&format!("error: Kullanım: {usage}"));
+                            self.input.clear();
+                            return;
+                        }
+                    }
+                } else if let Some((target, content)) = self
+                    .input
+                    .strip_prefix("/msg ")
+                    .and_then(|rest| rest.trim().split_once(' '))
+                {
+                    Frame::Private(Header::Fn, PrivateRequest::new(target, content.trim()))
+                } else {
+                    Frame::Message(Header::Fn, Message::new(&self.channel, &self.input))
+                };
+                let mut writer = writer_clone.lock().await;
+                writer.send(frame).await.unwrap();
+                if self.channel != previous {
+                    self.users.clear();
+                    let names = ChannelRequest::new(&self.channel);
+                    writer.send(Frame::Names(Header::Fn, names)).await.unwrap();
                 }
+                self.input.clear();
             }
             _ => {}
         }
     }
 }
 
+/// Parses `/register`, `/login`, `/oper`, `/kick`, `/ban`, `/unban`, `/mute`, `/unmute` and
+/// `/die`. Durations are
+/// written like `90s`, `30m`, `12h` or `7d`; without one a ban or mute lasts
+/// until it is lifted.
+fn moderation(input: &str) -> Option<Result<Frame, &'static str>> {
+    let (command, rest) = input.split_once(' ').unwrap_or((input, ""));
+    let mut words = rest.split_whitespace();
+    let header = Header::Fn;
+    let frame = match command {
+        "/register" => Some(Frame::Register(
+            header,
+            Register {
+                password: words.next().unwrap_or_default().to_string(),
+            },
+        )),
+        "/login" => match (words.next(), words.next()) {
+            (Some(name), Some(password)) => Some(Frame::Login(
+                header,
+                Login {
+                    name: name.to_string(),
+                    password: password.to_string(),
+                },
+            )),
+            (Some(password), None) => Some(Frame::Login(
+                header,
+                Login {
+                    name: String::new(),
+                    password: password.to_string(),
+                },
+            )),
+            _ => None,
+        },
+        "/oper" => match (words.next(), words.next()) {
+            (Some(name), Some(password)) => Some(Frame::Oper(
+                header,
+                Oper {
+                    name: name.to_string(),
+                    password: password.to_string(),
+                },
+            )),
+            _ => None,
+        },
+        "/die" => Some(Frame::Die(
+            header,
+            Quit {
+                reason: words.collect::<Vec<_>>().join(" "),
+            },
+        )),
+        "/kick" => words.next().map(|target| {
+            Frame::Kick(
+                header,
+                Kick {
+                    target: target.to_string(),
+                    reason: words.collect::<Vec<_>>().join(" "),
+                },
+            )
+        }),
+        "/ban" => match (words.next().and_then(ban_kind), words.next()) {
+            (Some(kind), Some(mask)) => {
+                let (duration, reason) = duration_and_reason(words);
+                Some(Frame::Ban(
+                    header,
+                    Ban {
+                        kind,
+                        mask: mask.to_string(),
+                        duration,
+                        reason,
+                    },
+                ))
+            }
+            _ => None,
+        },
+        "/unban" => match (words.next().and_then(ban_kind), words.next()) {
+            (Some(kind), Some(mask)) => Some(Frame::Unban(
+                header,
+                Unban {
+                    kind,
+                    mask: mask.to_string(),
+                },
+            )),
+            _ => None,
+        },
+        "/mute" => words.next().map(|target| {
+            let (duration, reason) = duration_and_reason(words);
+            Frame::Mute(
+                header,
+                Mute {
+                    target: target.to_string(),
+                    duration,
+                    reason,
+                },
+            )
+        }),
+        "/unmute" => words.next().map(|target| {
+            Frame::Unmute(
+                header,
+                Unmute {
+                    target: target.to_string(),
+                },
+            )
+        }),
+        _ => return None,
+    };
+    Some(frame.ok_or(match command {
+        "/login" => "/login [isim] <parola>",
+        "/oper" => "/oper <isim> <parola>",
+        "/kick" => "/kick <kullanıcı> [sebep]",
+        "/ban" => "/ban <uuid|nick|ip|key> <maske> [süre] [sebep]",
+        "/unban" => "/unban <uuid|nick|ip|key> <maske>",
+        "/mute" => "/mute <kullanıcı> [süre] [sebep]",
+        _ => "/unmute <kullanıcı>",
+    }))
+}
+
+fn ban_kind(word: &str) -> Option<BanKind> {
+    match word {
+        "uuid" => Some(BanKind::Uuid),
+        "nick" => Some(BanKind::Nick),
+        "ip" => Some(BanKind::Ip),
+        "key" => Some(BanKind::Key),
+        _ => None,
+    }
+}
+
+/// Takes an optional leading duration off the remaining words; the rest is
+/// the reason.
+fn duration_and_reason<'a>(words: impl Iterator<Item = &'a str>) -> (Option<u64>, String) {
+    let mut words = words.peekable();
+    let duration = words.peek().and_then(|word| {
+        let (count, unit) = word.split_at(word.len().saturating_sub(1));
+        let seconds = match unit {
+            "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            "d" => 24 * 60 * 60,
+            _ => return None,
+        };
+        count.parse::<u64>().ok().map(|count| count * seconds)
+    });
+    if duration.is_some() {
+        words.next();
+    }
+    (duration, words.collect::<Vec<_>>().join(" "))
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let messages = vec![];
@@ -128,88 +411,233 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let Args {
         mut host,
         mut port,
-        username,
+        mut username,
+        key,
+        tls,
+        fingerprint,
+        known_hosts,
+        sessions,
+        password,
     } = args;
 
+    let (identity, generated) = Identity::load(&key)?;
+    if generated {
+        app.insert(
+            format!("log: {key} bulunamadı, bu bağlantı için geçici bir anahtar çifti üretildi.")
+                .as_str(),
+        );
+    }
+    app.insert(format!("log: Anahtar parmak izin: {}", identity.fingerprint()).as_str());
+
     if host == "default" || port == 0 {
         app.insert("log: Herhangi bir sunucu ve port belirtmediniz, varsayılan değerler olan '127.0.0.1:33363' kullanılacak.");
         host = String::from("127.0.0.1");
         port = 33363;
     }
     let addr = format!("{host}:{port}");
+    // A token is tied to the key it was issued for; a throwaway key can't
+    // use one.
+    let sessions = (!generated).then(|| Sessions::new(&sessions));
+    let resume = sessions
+        .as_ref()
+        .and_then(|sessions| sessions.token(&addr))
+        .unwrap_or_default();
     app.insert(format!("log: {addr} sunucusuna bağlanılıyor...").as_str());
-    let stream = TcpStream::connect(&addr).await?;
+    let (stream, trust) = transport::connect(&host, port, tls, fingerprint, &known_hosts).await?;
+    match trust {
+        Trust::Plain => {}
+        Trust::Pinned => {
+            app.insert("log: Sunucu sertifikası kayıtlı parmak iziyle eşleşti, bağlantı şifreli.");
+        }
+        Trust::FirstUse(fingerprint) => app.insert(
+            format!(
+                "log: {addr} ilk kez görülüyor, sertifika parmak izi kaydedildi: {fingerprint}"
+            )
+            .as_str(),
+        ),
+    }
     app.insert(format!("log: {addr} ile bağlantı kuruldu. Sunucu yanıtı bekleniyor..").as_str());
-    let (reader, writer) = stream.into_split();
+    let (reader, writer) = tokio::io::split(stream);
 
-    let reader = BufReader::new(reader);
-    let writer = Arc::new(Mutex::new(writer));
+    let mut reader = FramedRead::new(reader, FrameCodec::with_limits(SERVER_FRAME_LIMITS));
+    let writer = Arc::new(Mutex::new(FramedWrite::new(writer, FrameCodec::new())));
 
     let writer_clone = Arc::clone(&writer);
     let username_clone = username.clone();
-    let mut lines = String::new();
-    let mut reader = reader;
+    let mut uuid = String::new();
 
     loop {
         select! {
-            result = reader.read_line(&mut lines) => {
+            result = reader.next() => {
                 match result {
-                    Ok(0) => {
+                    None => {
                         break;
                     },
-                    Ok(_) => {
-                        if let Some((header, body)) = lines.split_once("::") {
-                            let body = body.trim();
-
-                            match header {
-                                "MSG" => {
-                                    app.insert("log: Sunucudan yanıt alındı. Kimlik doğrulama için başvuru yapılıyor.. Sunucu kimliğiniz doğrulandıktan sonra işleme devam edilecek.");
-                                    let data = json!({
-                                        "username": username_clone,
-                                        "pem": "x"
-                                    })
-                                    .to_string();
-                                    let mut writer = writer_clone.lock().await;
-                                    writer
-                                        .write_all(format!("FN<>::Identify {data}\r\n").as_bytes())
-                                        .await?;
-                                    writer.flush().await?;
-                                }
-                                "OK" => {
-                                   
-                                    if body.contains("Connection Established") {
-                                        app.insert("log: Sunucu tarafından kimlik doğrulama işlemi onaylandı. Bağlantı kuruldu, sunucu tarafından kullanıcı ID'si atanması bekleniyor.");
-                                    }
-                                }
-                                "UUID" => {
-                                    app.insert(format!("log: Sunucu tarafından kullanıcı ID'si atandı: {}", body).as_str());
-                                    app.insert(format!("log: Artık mesajlaşmaya hazırsın, {}!", username).as_str());
-                                }
-                                "UMSG" => {
-                                    if body.starts_with("JOIN"){
-                                        app.insert(format!("join: {}", &body[6..]).as_str());
-                                    } else {
-                                        app.insert(format!("{body}").as_str());
-                                    }
+                    Some(Ok(Ok(frame))) => match frame {
+                        Frame::Hello(hello) => {
+                            app.max_body = hello.max_body_length;
+                            app.insert(format!("log: Sunucu: {} (sürüm {}, özellikler: {})", hello.name, hello.version, hello.capabilities.join(", ")).as_str());
+                            app.insert("log: Sunucudan yanıt alındı. Kimlik doğrulama için başvuru yapılıyor.. Sunucu kimliğiniz doğrulandıktan sonra işleme devam edilecek.");
+                            let identify = Identify {
+                                username: username_clone.clone(),
+                                pem: identity.public_pem().to_string(),
+                                resume: resume.clone(),
+                                password: password.clone(),
+                            };
+                            let mut writer = writer_clone.lock().await;
+                            writer.send(Frame::Identify(Header::Fn, identify)).await?;
+                        }
+                        Frame::Challenge(nonce) => {
+                            let Some(signature) = identity.sign(&nonce) else {
+                                app.insert("error: Sunucunun gönderdiği doğrulama kodu çözülemedi.");
+                                continue;
+                            };
+                            app.insert("log: Sunucu anahtar doğrulaması istedi, doğrulama kodu imzalanıp gönderiliyor..");
+                            let mut writer = writer_clone.lock().await;
+                            writer.send(Frame::Verify(Header::Fn, Verify { signature })).await?;
+                        }
+                        Frame::Ok(body) if body.contains("Connection Established") => {
+                            app.insert("log: Sunucu tarafından kimlik doğrulama işlemi onaylandı. Bağlantı kuruldu, sunucu tarafından kullanıcı ID'si atanması bekleniyor.");
+                        }
+                        Frame::Ok(body) if std::mem::take(&mut app.moderating) || std::mem::take(&mut app.muted) => {
+                            app.insert(format!("log: {body}").as_str());
+                        }
+                        Frame::Err(body) => {
+                            app.moderating = false;
+                            app.insert(format!("error: {body}").as_str());
+                        }
+                        Frame::Resume(token) => {
+                            if let Some(Err(e)) = sessions.as_ref().map(|sessions| sessions.save(&addr, &token)) {
+                                app.insert(format!("error: Oturum anahtarı kaydedilemedi: {e}").as_str());
+                            }
+                        }
+                        Frame::Ok(body) if body.starts_with("You are now logged in") => {
+                            app.insert(format!("log: {body}").as_str());
+                        }
+                        Frame::Ok(body) if body.starts_with("Session resumed") => {
+                            app.insert("log: Önceki oturum devralındı, kaçırılan mesajlar aktarılıyor..");
+                        }
+                        Frame::Motd(motd) => {
+                            for line in &motd.lines {
+                                app.insert(format!("motd: {line}").as_str());
+                            }
+                        }
+                        Frame::Uuid(assigned) => {
+                            app.insert(format!("log: Sunucu tarafından kullanıcı ID'si atandı: {}", assigned).as_str());
+                            uuid = assigned;
+                            app.insert(format!("log: Artık mesajlaşmaya hazırsın, {}!", username).as_str());
+                            let mut writer = writer_clone.lock().await;
+                            writer.send(Frame::Names(Header::Fn, ChannelRequest::new(&app.channel))).await?;
+                        }
+                        Frame::Umsg(message) => {
+                            app.insert(format!("{}: [{}] {}", message.username, message.channel, message.content).as_str());
+                        }
+                        Frame::Join(membership) => {
+                            if membership.channel == app.channel {
+                                app.joined(&membership.username);
+                            }
+                            app.insert(format!("join: {} joined {} just now.", membership.username, membership.channel).as_str());
+                        }
+                        Frame::Pmsg(message) => {
+                            app.insert(format!("dm: [{} → {}] {}", message.from, message.to, message.content).as_str());
+                        }
+                        Frame::Part(membership) if membership.channel == app.channel && membership.username != username => {
+                            app.left(&membership.username);
+                            let reason = if membership.reason.is_empty() { String::new() } else { format!(" ({})", membership.reason) };
+                            app.insert(format!("part: {} left {}{reason}.", membership.username, membership.channel).as_str());
+                        }
+                        Frame::Part(membership) if membership.reason.is_empty() => {
+                            app.insert(format!("part: {} left {}.", membership.username, membership.channel).as_str());
+                        }
+                        Frame::Part(membership) => {
+                            app.insert(format!("part: {} left {} ({}).", membership.username, membership.channel, membership.reason).as_str());
+                        }
+                        Frame::Uquit(departure) if departure.message.is_empty() => {
+                            app.left(&departure.username);
+                            app.insert(format!("part: {} quit ({}).", departure.username, departure.reason).as_str());
+                        }
+                        Frame::Uquit(departure) => {
+                            app.left(&departure.username);
+                            app.insert(format!("part: {} quit ({}: {}).", departure.username, departure.reason, departure.message).as_str());
+                        }
+                        Frame::Roster(roster) => {
+                            if roster.channel == app.channel {
+                                app.users.clear();
+                                for presence in roster.users.iter().cloned() {
+                                    app.seen(presence);
                                 }
-                                _ => {}
                             }
+                            if std::mem::take(&mut app.listing) {
+                                let scope = if roster.channel.is_empty() { "online" } else { roster.channel.as_str() };
+                                let users: Vec<String> = roster.users.iter().map(|u| format!("{} ({})", u.username, u.uuid)).collect();
+                                app.insert(format!("log: {scope} [{}]: {}", users.len(), users.join(", ")).as_str());
+                            }
+                        }
+                        Frame::Gap(dropped) => {
+                            app.insert(format!("error: Bağlantın yeterince hızlı olmadığı için {dropped} mesaj kaçırıldı.").as_str());
+                        }
+                        Frame::History(message) => {
+                            let time = message
+                                .time
+                                .as_deref()
+                                .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+                                .map(|time| time.with_timezone(&Local).format("%d.%m %H:%M").to_string())
+                                .unwrap_or_default();
+                            app.insert(format!("history: [{time}] {} [{}] {}", message.username, message.channel, message.content).as_str());
+                        }
+                        Frame::Shutdown(reason) => {
+                            app.insert(format!("error: Sunucu kapanıyor: {reason}").as_str());
+                            app.users.clear();
                         }
-                        lines.clear();
+                        Frame::Kicked(sanction) => {
+                            let what = if sanction.ban { "yasaklandın" } else { "atıldın" };
+                            let until = sanction.until.as_deref().map(|until| format!(" ({until} tarihine kadar)")).unwrap_or_default();
+                            let reason = if sanction.reason.is_empty() { String::new() } else { format!(": {}", sanction.reason) };
+                            app.insert(format!("error: {} tarafından sunucudan {what}{until}{reason}", sanction.by).as_str());
+                            app.users.clear();
+                        }
+                        Frame::Muted(sanction) => {
+                            app.muted = true;
+                            let until = sanction.until.as_deref().map(|until| format!(" ({until} tarihine kadar)")).unwrap_or_default();
+                            let reason = if sanction.reason.is_empty() { String::new() } else { format!(": {}", sanction.reason) };
+                            app.insert(format!("error: {} tarafından susturuldun{until}{reason}", sanction.by).as_str());
+                        }
+                        Frame::Presence(presence) => {
+                            if presence.uuid == uuid && presence.username != username {
+                                app.insert(format!("log: Artık {} olarak biliniyorsun.", presence.username).as_str());
+                                username = presence.username.clone();
+                            } else if presence.uuid != uuid && !presence.previous.is_empty() {
+                                app.insert(format!("log: {} artık {} olarak biliniyor.", presence.previous, presence.username).as_str());
+                            }
+                            if presence.status == Status::Away && presence.username == username {
+                                app.insert(format!("log: Uzakta olarak işaretlendin: {}", presence.away).as_str());
+                            }
+                            if app.knows(&presence) {
+                                app.seen(presence);
+                            }
+                        }
+                        _ => {}
                     },
-                    Err(e) => {
+                    Some(Ok(Err(e))) => {
+                        app.insert(format!("error: Sunucudan gelen mesaj çözülemedi: {e}").as_str());
+                    }
+                    Some(Err(e)) => {
                         app.insert(format!("err: Sunucu kaynaklı bir hatadan dolayı bağlantı koptu: {e}").as_str());
                         break;
                     }
                 }
             },
-            _ = tokio::task::spawn_blocking(move || event::poll(std::time::Duration::from_millis(100))) => {
-                if let Ok(Event::Key(key)) = event::read() {
+            ready = tokio::task::spawn_blocking(move || event::poll(std::time::Duration::from_millis(100))) => {
+                if !matches!(ready, Ok(Ok(true))) {
+                    // Nothing typed; fall through so the screen still redraws.
+                } else if let Ok(Event::Key(key)) = event::read() {
                     if key.kind == KeyEventKind::Press {
                         if key.code == KeyCode::Esc {
+                            let mut writer = writer_clone.lock().await;
+                            let _ = writer.send(Frame::Quit(Header::FnFin, Quit::default())).await;
                             break;
                         } else {
-                            app.handle_input(key.code, &writer_clone, &username).await;
+                            app.handle_input(key.code, &writer_clone).await;
                         }
                     }
                 }
@@ -222,6 +650,16 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .direction(Direction::Vertical)
                 .constraints([Constraint::Percentage(80), Constraint::Percentage(20)].as_ref())
                 .split(size);
+            let panes = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints(
+                    [
+                        Constraint::Min(0),
+                        Constraint::Length(if app.sidebar { SIDEBAR_WIDTH } else { 0 }),
+                    ]
+                    .as_ref(),
+                )
+                .split(chunks[0]);
 
             let wrapped_items: Vec<ListItem> = app
                 .items
@@ -237,18 +675,33 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                                         "log" => Color::LightYellow,
                                         "error" => Color::LightRed,
                                         "join" => Color::LightGreen,
+                                        "part" => Color::LightMagenta,
+                                        "dm" => Color::LightBlue,
+                                        "history" => Color::DarkGray,
+                                        "motd" => Color::Green,
                                         _ => {
                                             if parts[0] == username {
                                                 Color::LightCyan
                                             } else {
                                                 Color::LightRed
                                             }
-                                        },
+                                        }
                                     })
                                     .add_modifier(tui::style::Modifier::BOLD),
                             ),
                             Span::raw(": "),
-                            Span::raw(parts[1]),
+                            if parts[0] == "dm" {
+                                Span::styled(
+                                    parts[1],
+                                    Style::default()
+                                        .fg(Color::LightBlue)
+                                        .add_modifier(tui::style::Modifier::ITALIC),
+                                )
+                            } else if parts[0] == "history" {
+                                Span::styled(parts[1], Style::default().fg(Color::DarkGray))
+                            } else {
+                                Span::raw(parts[1])
+                            },
                         ])
                     } else {
                         Spans::from(vec![Span::raw(item)])
@@ -270,7 +723,48 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .highlight_style(Style::default().bg(Color::Rgb(0, 50, 100)))
                 .highlight_symbol(" ");
 
-            f.render_stateful_widget(list, chunks[0], &mut app.list_state);
+            f.render_stateful_widget(list, panes[0], &mut app.list_state);
+
+            if app.sidebar {
+                let users: Vec<ListItem> = app
+                    .users
+                    .values()
+                    .map(|user| {
+                        let away = user.status == Status::Away;
+                        let style = if away {
+                            Style::default().fg(Color::DarkGray)
+                        } else if user.username == username {
+                            Style::default().fg(Color::LightCyan)
+                        } else {
+                            Style::default()
+                        };
+                        ListItem::new(Spans::from(vec![
+                            Span::styled(
+                                if user.operator { "@" } else { " " },
+                                Style::default()
+                                    .fg(Color::LightGreen)
+                                    .add_modifier(tui::style::Modifier::BOLD),
+                            ),
+                            Span::styled(user.username.as_str(), style),
+                            Span::styled(
+                                if away { " (uzakta)" } else { "" },
+                                Style::default()
+                                    .fg(Color::DarkGray)
+                                    .add_modifier(tui::style::Modifier::ITALIC),
+                            ),
+                        ]))
+                    })
+                    .collect();
+                let sidebar = List::new(users).block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .title(format!("-• {} [{}] •-", app.channel, app.users.len()))
+                        .title_alignment(tui::layout::Alignment::Center)
+                        .border_type(tui::widgets::BorderType::Rounded)
+                        .border_style(Style::default().fg(Color::Rgb(150, 200, 255))),
+                );
+                f.render_widget(sidebar, panes[1]);
+            }
 
             let wrapped_input = wrap(&app.input, app.width);
             let input_paragraph = Paragraph::new(
@@ -282,7 +776,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             .block(
                 Block::default()
                     .borders(Borders::ALL)
-                    .title(format!("-• Bir mesaj yaz ~ {username}@[{addr}] •-"))
+                    .title(format!(
+                        "-• Bir mesaj yaz ~ {username}@[{addr}] {} •-",
+                        app.channel
+                    ))
                     .title_alignment(tui::layout::Alignment::Left)
                     .border_type(tui::widgets::BorderType::Rounded)
                     .border_style(Style::default().fg(Color::Rgb(150, 200, 255))),
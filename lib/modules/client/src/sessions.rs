@@ -0,0 +1,39 @@
+use std::fs;
+use std::path::Path;
+
+/// Resume tokens handed out by servers, one `host:port token` per line, so
+/// a restarted client can take its session back.
+pub struct Sessions {
+    path: String,
+}
+
+impl Sessions {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: path.to_string(),
+        }
+    }
+
+    pub fn token(&self, addr: &str) -> Option<String> {
+        let content = fs::read_to_string(&self.path).ok()?;
+        content.lines().find_map(|line| {
+            let (host, token) = line.split_once(' ')?;
+            (host == addr).then(|| token.trim().to_string())
+        })
+    }
+
+    /// Stores the server's latest token in place of the previous one.
+    pub fn save(&self, addr: &str, token: &str) -> std::io::Result<()> {
+        if let Some(parent) = Path::new(&self.path).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let content = fs::read_to_string(&self.path).unwrap_or_default();
+        let mut lines: Vec<&str> = content
+            .lines()
+            .filter(|line| line.split_once(' ').map(|(host, _)| host) != Some(addr))
+            .collect();
+        let entry = format!("{addr} {token}");
+        lines.push(&entry);
+        fs::write(&self.path, lines.join("\n") + "\n")
+    }
+}
@@ -0,0 +1,158 @@
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::client::danger::{
+    HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
+};
+use tokio_rustls::rustls::crypto::{
+    ring, verify_tls12_signature, verify_tls13_signature, CryptoProvider,
+};
+use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use tokio_rustls::rustls::{ClientConfig, DigitallySignedStruct, Error, SignatureScheme};
+use tokio_rustls::TlsConnector;
+
+pub trait Transport: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Transport for T {}
+
+pub type Stream = Box<dyn Transport>;
+
+/// How the server certificate came to be trusted.
+pub enum Trust {
+    Plain,
+    Pinned,
+    FirstUse(String),
+}
+
+/// Accepts exactly one certificate per server, identified by the SHA-256 of
+/// its DER encoding. With nothing pinned yet, whatever the server presents is
+/// remembered and trusted on first use.
+#[derive(Debug)]
+struct Pinning {
+    expected: Option<String>,
+    seen: Mutex<Option<String>>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for Pinning {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        let fingerprint = fingerprint(end_entity);
+        *self.seen.lock().unwrap() = Some(fingerprint.clone());
+        match &self.expected {
+            Some(expected) if !expected.eq_ignore_ascii_case(&fingerprint) => Err(Error::General(
+                format!("certificate fingerprint mismatch: expected {expected}, got {fingerprint}"),
+            )),
+            _ => Ok(ServerCertVerified::assertion()),
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+fn fingerprint(cert: &CertificateDer<'_>) -> String {
+    Sha256::digest(cert.as_ref())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Looks the server up in a `host:port fingerprint` per line file.
+fn known(known_hosts: &str, addr: &str) -> Option<String> {
+    let content = fs::read_to_string(known_hosts).ok()?;
+    content.lines().find_map(|line| {
+        let (host, fingerprint) = line.split_once(' ')?;
+        (host == addr).then(|| fingerprint.trim().to_string())
+    })
+}
+
+fn remember(known_hosts: &str, addr: &str, fingerprint: &str) -> std::io::Result<()> {
+    if let Some(parent) = Path::new(known_hosts).parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut content = fs::read_to_string(known_hosts).unwrap_or_default();
+    content.push_str(&format!("{addr} {fingerprint}\n"));
+    fs::write(known_hosts, content)
+}
+
+/// Opens the connection, wrapping it in TLS when asked to. An explicit
+/// fingerprint takes precedence over the one stored in `known_hosts`.
+pub async fn connect(
+    host: &str,
+    port: u16,
+    tls: bool,
+    pin: Option<String>,
+    known_hosts: &str,
+) -> anyhow::Result<(Stream, Trust)> {
+    let addr = format!("{host}:{port}");
+    let socket = TcpStream::connect(&addr).await?;
+    if !tls {
+        return Ok((Box::new(socket), Trust::Plain));
+    }
+
+    let expected = pin.or_else(|| known(known_hosts, &addr));
+    let first_use = expected.is_none();
+    let provider = Arc::new(ring::default_provider());
+    let verifier = Arc::new(Pinning {
+        expected,
+        seen: Mutex::new(None),
+        provider: Arc::clone(&provider),
+    });
+    let config = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        .dangerous()
+        .with_custom_certificate_verifier(verifier.clone())
+        .with_no_client_auth();
+    let name = ServerName::try_from(host.to_string())?;
+    let stream = TlsConnector::from(Arc::new(config))
+        .connect(name, socket)
+        .await?;
+
+    if !first_use {
+        return Ok((Box::new(stream), Trust::Pinned));
+    }
+    let fingerprint = verifier.seen.lock().unwrap().clone().unwrap_or_default();
+    remember(known_hosts, &addr, &fingerprint)?;
+    Ok((Box::new(stream), Trust::FirstUse(fingerprint)))
+}
@@ -0,0 +1,299 @@
+use crate::error::ProtocolError;
+use crate::frame::Frame;
+use bytes::{BufMut, BytesMut};
+use std::sync::Arc;
+use tokio_util::codec::{Decoder, Encoder};
+
+pub const MAX_LINE_LENGTH: usize = 8 * 1024;
+pub const MAX_BODY_LENGTH: usize = 4 * 1024;
+
+/// How large an incoming line, and the JSON body inside it, may get.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameLimits {
+    pub line: usize,
+    pub body: usize,
+}
+
+impl Default for FrameLimits {
+    fn default() -> Self {
+        Self {
+            line: MAX_LINE_LENGTH,
+            body: MAX_BODY_LENGTH,
+        }
+    }
+}
+
+/// Line based codec for [`Frame`]s. A malformed line is yielded as an inner
+/// `Err` so the connection can answer it and keep reading; the outer error
+/// is reserved for failures of the underlying stream. A line past the limit
+/// is reported once and skipped up to the next line break.
+#[derive(Debug, Default)]
+pub struct FrameCodec {
+    next_index: usize,
+    limits: FrameLimits,
+    discarding: bool,
+}
+
+impl FrameCodec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_limits(limits: FrameLimits) -> Self {
+        Self {
+            limits,
+            ..Self::default()
+        }
+    }
+
+    fn parse(&self, line: &[u8]) -> Option<Result<Frame, ProtocolError>> {
+        let line = match std::str::from_utf8(line) {
+            Ok(line) => line.trim_end_matches(['\r', '\n']),
+            Err(_) => return Some(Err(ProtocolError::InvalidUtf8)),
+        };
+        if line.trim().is_empty() {
+            return None;
+        }
+        if line
+            .find('{')
+            .is_some_and(|start| line.len() - start > self.limits.body)
+        {
+            return Some(Err(ProtocolError::BodyTooLarge(self.limits.body)));
+        }
+        Some(line.parse())
+    }
+}
+
+impl Decoder for FrameCodec {
+    type Item = Result<Frame, ProtocolError>;
+    type Error = ProtocolError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        loop {
+            let Some(offset) = src[self.next_index..].iter().position(|b| *b == b'\n') else {
+                if self.discarding {
+                    src.clear();
+                    self.next_index = 0;
+                } else if src.len() > self.limits.line {
+                    src.clear();
+                    self.next_index = 0;
+                    self.discarding = true;
+                    return Ok(Some(Err(ProtocolError::LineTooLong(self.limits.line))));
+                } else {
+                    self.next_index = src.len();
+                }
+                return Ok(None);
+            };
+            let line = src.split_to(self.next_index + offset + 1);
+            self.next_index = 0;
+            if std::mem::take(&mut self.discarding) {
+                continue;
+            }
+            if line.len() > self.limits.line + 2 {
+                return Ok(Some(Err(ProtocolError::LineTooLong(self.limits.line))));
+            }
+            if let Some(frame) = self.parse(&line) {
+                return Ok(Some(frame));
+            }
+        }
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if let Some(frame) = self.decode(src)? {
+            return Ok(Some(frame));
+        }
+        self.next_index = 0;
+        let line = src.split();
+        if std::mem::take(&mut self.discarding) {
+            return Ok(None);
+        }
+        Ok(self.parse(&line))
+    }
+}
+
+fn put_line(line: &str, dst: &mut BytesMut) -> Result<(), ProtocolError> {
+    if line.contains(['\r', '\n']) {
+        return Err(ProtocolError::Unframeable);
+    }
+    dst.reserve(line.len() + 2);
+    dst.put(line.as_bytes());
+    dst.put(&b"\r\n"[..]);
+    Ok(())
+}
+
+impl Encoder<Frame> for FrameCodec {
+    type Error = ProtocolError;
+
+    fn encode(&mut self, frame: Frame, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        put_line(&frame.to_string(), dst)
+    }
+}
+
+/// Writes a frame that was already rendered, so a line fanned out to many
+/// connections is only formatted once.
+impl Encoder<Arc<str>> for FrameCodec {
+    type Error = ProtocolError;
+
+    fn encode(&mut self, line: Arc<str>, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        put_line(&line, dst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Everything the codec yields for the bytes, in order, as rendered
+    /// frames or errors.
+    fn decode_all(codec: &mut FrameCodec, src: &mut BytesMut) -> Vec<Result<String, String>> {
+        let mut out = Vec::new();
+        while let Some(item) = codec.decode(src).unwrap() {
+            out.push(
+                item.map(|frame| frame.to_string())
+                    .map_err(|e| e.to_string()),
+            );
+        }
+        out
+    }
+
+    fn small() -> FrameCodec {
+        FrameCodec::with_limits(FrameLimits { line: 32, body: 16 })
+    }
+
+    #[test]
+    fn lines_end_in_crlf_or_lf() {
+        let mut codec = FrameCodec::new();
+        let mut src = BytesMut::from("OK::one\r\nOK::two\n\r\n\nOK::three\r\n");
+        assert_eq!(
+            decode_all(&mut codec, &mut src),
+            [Ok("OK::one"), Ok("OK::two"), Ok("OK::three")].map(|line| line.map(String::from))
+        );
+        assert!(src.is_empty());
+    }
+
+    #[test]
+    fn a_line_split_across_reads_is_joined() {
+        let mut codec = FrameCodec::new();
+        let mut src = BytesMut::from("FN<>::Join {\"chan");
+        assert!(codec.decode(&mut src).unwrap().is_none());
+        src.extend_from_slice(b"nel\":\"#rust\"}\r\n");
+        let frame = codec.decode(&mut src).unwrap().unwrap().unwrap();
+        assert_eq!(frame.to_string(), "FN<>::Join {\"channel\":\"#rust\"}");
+    }
+
+    #[test]
+    fn malformed_lines_do_not_end_the_stream() {
+        let mut codec = FrameCodec::new();
+        let mut src = BytesMut::from(&b"FN<>::Dance\r\n\xff\xfe\r\nOK::still here\r\n"[..]);
+        let out = decode_all(&mut codec, &mut src);
+        assert_eq!(out.len(), 3);
+        assert!(out[0].is_err());
+        assert_eq!(out[1], Err(ProtocolError::InvalidUtf8.to_string()));
+        assert_eq!(out[2], Ok(String::from("OK::still here")));
+    }
+
+    #[test]
+    fn an_unterminated_long_line_is_reported_once_and_skipped() {
+        let mut codec = small();
+        let mut src = BytesMut::from("MSG::".repeat(10).as_str());
+        assert!(matches!(
+            codec.decode(&mut src).unwrap(),
+            Some(Err(ProtocolError::LineTooLong(32)))
+        ));
+        assert!(src.is_empty());
+        src.extend_from_slice("still the same line".repeat(5).as_bytes());
+        assert!(codec.decode(&mut src).unwrap().is_none());
+        src.extend_from_slice(b" end\r\nOK::next\r\n");
+        assert_eq!(
+            decode_all(&mut codec, &mut src),
+            [Ok(String::from("OK::next"))]
+        );
+    }
+
+    #[test]
+    fn a_terminated_long_line_is_rejected() {
+        let mut codec = small();
+        let mut src = BytesMut::from(format!("MSG::{}\r\nOK::next\r\n", "x".repeat(30)).as_str());
+        let out = decode_all(&mut codec, &mut src);
+        assert_eq!(
+            out,
+            [
+                Err(ProtocolError::LineTooLong(32).to_string()),
+                Ok(String::from("OK::next"))
+            ]
+        );
+    }
+
+    #[test]
+    fn a_large_body_is_rejected() {
+        let mut codec = small();
+        let mut src = BytesMut::from("FN<>::Quit {\"reason\":\"long\"}\r\nOK::next\r\n");
+        let out = decode_all(&mut codec, &mut src);
+        assert_eq!(
+            out,
+            [
+                Err(ProtocolError::BodyTooLarge(16).to_string()),
+                Ok(String::from("OK::next"))
+            ]
+        );
+    }
+
+    #[test]
+    fn the_last_line_may_lack_a_line_break() {
+        let mut codec = FrameCodec::new();
+        let mut src = BytesMut::from("OK::one\r\nOK::two");
+        assert_eq!(
+            codec
+                .decode_eof(&mut src)
+                .unwrap()
+                .unwrap()
+                .unwrap()
+                .to_string(),
+            "OK::one"
+        );
+        assert_eq!(
+            codec
+                .decode_eof(&mut src)
+                .unwrap()
+                .unwrap()
+                .unwrap()
+                .to_string(),
+            "OK::two"
+        );
+        assert!(codec.decode_eof(&mut src).unwrap().is_none());
+    }
+
+    #[test]
+    fn a_long_line_cut_off_by_the_end_of_the_stream_is_dropped() {
+        let mut codec = small();
+        let mut src = BytesMut::from("MSG::".repeat(10).as_str());
+        assert!(codec.decode_eof(&mut src).unwrap().unwrap().is_err());
+        src.extend_from_slice(b"rest of it");
+        assert!(codec.decode_eof(&mut src).unwrap().is_none());
+    }
+
+    #[test]
+    fn frames_are_written_with_crlf() {
+        let mut codec = FrameCodec::new();
+        let mut dst = BytesMut::new();
+        codec
+            .encode(Frame::Ok(String::from("Done.")), &mut dst)
+            .unwrap();
+        codec
+            .encode(Arc::<str>::from("UUID::uuid"), &mut dst)
+            .unwrap();
+        assert_eq!(&dst[..], b"OK::Done.\r\nUUID::uuid\r\n");
+    }
+
+    #[test]
+    fn line_breaks_cannot_be_smuggled_into_a_frame() {
+        let mut codec = FrameCodec::new();
+        let mut dst = BytesMut::new();
+        let frame = Frame::Msg(String::from("one\r\nOK::forged"));
+        assert!(matches!(
+            codec.encode(frame, &mut dst),
+            Err(ProtocolError::Unframeable)
+        ));
+        assert!(dst.is_empty());
+    }
+}
@@ -0,0 +1,78 @@
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum ProtocolError {
+    MissingIdentifier,
+    UnsupportedHeader(String),
+    UnsupportedCommand(String),
+    InvalidBody(serde_json::Error),
+    InvalidUtf8,
+    Unframeable,
+    /// A line ran past the limit, in bytes, without a line break.
+    LineTooLong(usize),
+    /// A request's JSON body was larger than the limit, in bytes.
+    BodyTooLarge(usize),
+    Io(io::Error),
+}
+
+impl ProtocolError {
+    /// Whether the peer is clearly not speaking the protocol, as opposed to
+    /// sending a single request that could not be understood.
+    pub fn is_fatal(&self) -> bool {
+        matches!(
+            self,
+            ProtocolError::MissingIdentifier
+                | ProtocolError::UnsupportedHeader(_)
+                | ProtocolError::InvalidUtf8
+                | ProtocolError::LineTooLong(_)
+                | ProtocolError::Io(_)
+        )
+    }
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::MissingIdentifier => {
+                write!(f, "The message doesn't contain an identifier.")
+            }
+            ProtocolError::UnsupportedHeader(_) => {
+                write!(f, "Your ACK is not supported or broken.")
+            }
+            ProtocolError::UnsupportedCommand(_) => write!(f, "Your identifier is not supported."),
+            ProtocolError::InvalidBody(_) => write!(f, "The data is broken or unsupported."),
+            ProtocolError::InvalidUtf8 => write!(f, "The message is not valid UTF-8."),
+            ProtocolError::Unframeable => write!(f, "The frame contains a line break."),
+            ProtocolError::LineTooLong(max) => {
+                write!(f, "The line is longer than {max} bytes.")
+            }
+            ProtocolError::BodyTooLarge(max) => {
+                write!(f, "The message body is larger than {max} bytes.")
+            }
+            ProtocolError::Io(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ProtocolError::InvalidBody(e) => Some(e),
+            ProtocolError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ProtocolError {
+    fn from(e: io::Error) -> Self {
+        ProtocolError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for ProtocolError {
+    fn from(e: serde_json::Error) -> Self {
+        ProtocolError::InvalidBody(e)
+    }
+}
@@ -0,0 +1,815 @@
+use crate::error::ProtocolError;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+pub const DEFAULT_CHANNEL: &str = "#general";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Header {
+    Fn,
+    FnReset,
+    FnFin,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Identify {
+    pub username: String,
+    pub pem: String,
+    /// A token from an earlier `RESUME::` frame, to take that session back.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub resume: String,
+    /// Logs into the account registered on `username`, if there is one.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub password: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Verify {
+    pub signature: String,
+}
+
+/// A channel message. Clients only fill `channel` and `content`; the server
+/// attributes it to the sending connection, stamps it with an id and an RFC
+/// 3339 time and records it before fanning it out.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Message {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time: Option<String>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub uuid: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub username: String,
+    pub channel: String,
+    pub content: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Quit {
+    #[serde(default)]
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ChannelRequest {
+    #[serde(default)]
+    pub channel: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Membership {
+    pub channel: String,
+    pub username: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QuitReason {
+    Quit,
+    Closed,
+    Timeout,
+    Kicked,
+    Banned,
+    Netsplit,
+}
+
+/// Sent once to every user who shared at least one channel with the one
+/// that left.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Departure {
+    pub username: String,
+    pub uuid: String,
+    pub reason: QuitReason,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Online,
+    Away,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Presence {
+    pub username: String,
+    pub uuid: String,
+    pub status: Status,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub away: String,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub operator: bool,
+    /// The nickname the user went by before, set only when it just changed.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub previous: String,
+}
+
+/// Marks the sender as away with the given message; an empty message marks
+/// it as back.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Away {
+    #[serde(default)]
+    pub message: String,
+}
+
+/// Answer to a `Names` request: the members of `channel`, or every connected
+/// user when no channel was given.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Roster {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub channel: String,
+    pub users: Vec<Presence>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrivateRequest {
+    pub target: String,
+    pub content: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrivateMessage {
+    pub from: String,
+    pub uuid: String,
+    pub to: String,
+    pub content: String,
+}
+
+/// What a ban is matched against. Key bans name the SHA-256 fingerprint of
+/// the public key, in hex.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BanKind {
+    Uuid,
+    Nick,
+    Ip,
+    Key,
+}
+
+/// Disconnects an online user, found by UUID or nickname.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Kick {
+    pub target: String,
+    #[serde(default)]
+    pub reason: String,
+}
+
+/// Bans a mask for `duration` seconds, or for good without one. Nickname and
+/// IP masks may use `*` and `?`; UUID, IP and key masks may also name an
+/// online user to ban whatever identifies them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Ban {
+    pub kind: BanKind,
+    pub mask: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<u64>,
+    #[serde(default)]
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Unban {
+    pub kind: BanKind,
+    pub mask: String,
+}
+
+/// Makes an online user read-only for `duration` seconds, or until unmuted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Mute {
+    pub target: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<u64>,
+    #[serde(default)]
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Unmute {
+    pub target: String,
+}
+
+/// Asks for the privileges tied to an operator name and password.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Oper {
+    pub name: String,
+    pub password: String,
+}
+
+/// Registers the sender's current nickname. Without a password the account
+/// can only be logged into with the key used to register it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Register {
+    #[serde(default)]
+    pub password: String,
+}
+
+/// Logs into an account, the one on the sender's current nickname unless
+/// `name` says otherwise.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Login {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub name: String,
+    pub password: String,
+}
+
+/// The first thing a server sends, so clients know what they are talking to
+/// before identifying.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Hello {
+    pub name: String,
+    pub version: String,
+    /// Optional features the server supports, such as `resume`.
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+    /// Longest line, in bytes, the server accepts.
+    pub max_line_length: usize,
+    /// Largest JSON body, in bytes, the server accepts.
+    pub max_body_length: usize,
+}
+
+/// The message of the day, one entry per line.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Motd {
+    pub lines: Vec<String>,
+}
+
+/// Tells a user who moderated them and why. `until` is an RFC 3339 time,
+/// absent for permanent sanctions.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sanction {
+    pub by: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub reason: String,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub ban: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until: Option<String>,
+}
+
+/// A single line of the protocol. Requests are sent by the client as
+/// `HEADER<>::Command {json}`, replies by the server as `KIND::payload`.
+#[derive(Debug, Clone)]
+pub enum Frame {
+    Identify(Header, Identify),
+    Verify(Header, Verify),
+    Message(Header, Message),
+    JoinChannel(Header, ChannelRequest),
+    PartChannel(Header, ChannelRequest),
+    Private(Header, PrivateRequest),
+    Quit(Header, Quit),
+    Names(Header, ChannelRequest),
+    Away(Header, Away),
+    Kick(Header, Kick),
+    Ban(Header, Ban),
+    Unban(Header, Unban),
+    Mute(Header, Mute),
+    Unmute(Header, Unmute),
+    Oper(Header, Oper),
+    Register(Header, Register),
+    Login(Header, Login),
+    /// Shuts the server down, for owners only.
+    Die(Header, Quit),
+    Hello(Hello),
+    /// Free text for people typing the protocol by hand.
+    Msg(String),
+    Motd(Motd),
+    Ok(String),
+    Err(String),
+    Challenge(String),
+    Uuid(String),
+    /// The token that lets a reconnecting client reclaim this session.
+    Resume(String),
+    Umsg(Message),
+    Join(Membership),
+    Part(Membership),
+    Pmsg(PrivateMessage),
+    Uquit(Departure),
+    Roster(Roster),
+    Presence(Presence),
+    Gap(usize),
+    History(Message),
+    Shutdown(String),
+    Kicked(Sanction),
+    Muted(Sanction),
+}
+
+impl Message {
+    pub fn new(channel: &str, content: &str) -> Self {
+        Self {
+            id: None,
+            time: None,
+            uuid: String::new(),
+            username: String::new(),
+            channel: channel.to_string(),
+            content: content.to_string(),
+        }
+    }
+
+    pub fn attributed(self, uuid: &str, username: &str) -> Self {
+        Self {
+            uuid: uuid.to_string(),
+            username: username.to_string(),
+            ..self
+        }
+    }
+}
+
+impl ChannelRequest {
+    pub fn new(channel: &str) -> Self {
+        Self {
+            channel: channel.to_string(),
+        }
+    }
+}
+
+impl Membership {
+    pub fn new(channel: &str, username: &str) -> Self {
+        Self {
+            channel: channel.to_string(),
+            username: username.to_string(),
+            reason: String::new(),
+        }
+    }
+
+    pub fn because(self, reason: &str) -> Self {
+        Self {
+            reason: reason.to_string(),
+            ..self
+        }
+    }
+}
+
+impl fmt::Display for QuitReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            QuitReason::Quit => "Quit",
+            QuitReason::Closed => "Connection closed",
+            QuitReason::Timeout => "Ping timeout",
+            QuitReason::Kicked => "Kicked",
+            QuitReason::Banned => "Banned",
+            QuitReason::Netsplit => "Netsplit",
+        })
+    }
+}
+
+impl fmt::Display for BanKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BanKind::Uuid => "uuid",
+            BanKind::Nick => "nick",
+            BanKind::Ip => "ip",
+            BanKind::Key => "key",
+        })
+    }
+}
+
+impl PrivateRequest {
+    pub fn new(target: &str, content: &str) -> Self {
+        Self {
+            target: target.to_string(),
+            content: content.to_string(),
+        }
+    }
+}
+
+impl Frame {
+    /// The protocol header of a client request, `None` for server replies.
+    pub fn header(&self) -> Option<Header> {
+        match self {
+            Frame::Identify(header, _)
+            | Frame::Verify(header, _)
+            | Frame::Message(header, _)
+            | Frame::JoinChannel(header, _)
+            | Frame::PartChannel(header, _)
+            | Frame::Private(header, _)
+            | Frame::Quit(header, _)
+            | Frame::Names(header, _)
+            | Frame::Away(header, _)
+            | Frame::Kick(header, _)
+            | Frame::Ban(header, _)
+            | Frame::Unban(header, _)
+            | Frame::Mute(header, _)
+            | Frame::Unmute(header, _)
+            | Frame::Oper(header, _)
+            | Frame::Register(header, _)
+            | Frame::Login(header, _)
+            | Frame::Die(header, _) => Some(*header),
+            _ => None,
+        }
+    }
+}
+
+impl Header {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Header::Fn => "FN",
+            Header::FnReset => "FN_RESET",
+            Header::FnFin => "FN_FIN",
+        }
+    }
+}
+
+impl fmt::Display for Header {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Header {
+    type Err = ProtocolError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "FN" => Ok(Header::Fn),
+            "FN_RESET" => Ok(Header::FnReset),
+            "FN_FIN" => Ok(Header::FnFin),
+            _ => Err(ProtocolError::UnsupportedHeader(s.to_string())),
+        }
+    }
+}
+
+fn json<T: Serialize>(value: &T) -> Result<String, fmt::Error> {
+    serde_json::to_string(value).map_err(|_| fmt::Error)
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Frame::Identify(header, identify) => {
+                write!(f, "{header}<>::Identify {}", json(identify)?)
+            }
+            Frame::Verify(header, verify) => {
+                write!(f, "{header}<>::Verify {}", json(verify)?)
+            }
+            Frame::Message(header, message) => {
+                write!(f, "{header}<>::Message {}", json(message)?)
+            }
+            Frame::JoinChannel(header, request) => {
+                write!(f, "{header}<>::Join {}", json(request)?)
+            }
+            Frame::PartChannel(header, request) => {
+                write!(f, "{header}<>::Part {}", json(request)?)
+            }
+            Frame::Private(header, request) => {
+                write!(f, "{header}<>::Private {}", json(request)?)
+            }
+            Frame::Quit(header, quit) => write!(f, "{header}<>::Quit {}", json(quit)?),
+            Frame::Names(header, request) => {
+                write!(f, "{header}<>::Names {}", json(request)?)
+            }
+            Frame::Away(header, away) => write!(f, "{header}<>::Away {}", json(away)?),
+            Frame::Kick(header, kick) => write!(f, "{header}<>::Kick {}", json(kick)?),
+            Frame::Ban(header, ban) => write!(f, "{header}<>::Ban {}", json(ban)?),
+            Frame::Unban(header, unban) => write!(f, "{header}<>::Unban {}", json(unban)?),
+            Frame::Mute(header, mute) => write!(f, "{header}<>::Mute {}", json(mute)?),
+            Frame::Unmute(header, unmute) => {
+                write!(f, "{header}<>::Unmute {}", json(unmute)?)
+            }
+            Frame::Oper(header, oper) => write!(f, "{header}<>::Oper {}", json(oper)?),
+            Frame::Register(header, register) => {
+                write!(f, "{header}<>::Register {}", json(register)?)
+            }
+            Frame::Login(header, login) => write!(f, "{header}<>::Login {}", json(login)?),
+            Frame::Die(header, quit) => write!(f, "{header}<>::Die {}", json(quit)?),
+            Frame::Hello(hello) => write!(f, "HELLO::{}", json(hello)?),
+            Frame::Msg(text) => write!(f, "MSG::{text}"),
+            Frame::Motd(motd) => write!(f, "MOTD::{}", json(motd)?),
+            Frame::Ok(text) => write!(f, "OK::{text}"),
+            Frame::Err(text) => write!(f, "ERR::{text}"),
+            Frame::Challenge(nonce) => write!(f, "CHALLENGE::{nonce}"),
+            Frame::Uuid(uuid) => write!(f, "UUID::{uuid}"),
+            Frame::Resume(token) => write!(f, "RESUME::{token}"),
+            Frame::Umsg(message) => write!(f, "UMSG::{}", json(message)?),
+            Frame::Join(membership) => write!(f, "JOIN::{}", json(membership)?),
+            Frame::Part(membership) => write!(f, "PART::{}", json(membership)?),
+            Frame::Pmsg(message) => write!(f, "PMSG::{}", json(message)?),
+            Frame::Uquit(departure) => write!(f, "QUIT::{}", json(departure)?),
+            Frame::Roster(roster) => write!(f, "NAMES::{}", json(roster)?),
+            Frame::Presence(presence) => write!(f, "PRESENCE::{}", json(presence)?),
+            Frame::Gap(dropped) => write!(f, "GAP::{dropped}"),
+            Frame::History(message) => write!(f, "HISTORY::{}", json(message)?),
+            Frame::Shutdown(text) => write!(f, "SHUTDOWN::{text}"),
+            Frame::Kicked(sanction) => write!(f, "KICKED::{}", json(sanction)?),
+            Frame::Muted(sanction) => write!(f, "MUTED::{}", json(sanction)?),
+        }
+    }
+}
+
+impl FromStr for Frame {
+    type Err = ProtocolError;
+
+    fn from_str(line: &str) -> Result<Self, ProtocolError> {
+        let (head, body) = line
+            .split_once("::")
+            .ok_or(ProtocolError::MissingIdentifier)?;
+        let body = body.trim();
+
+        let header = head.split("<>").next().unwrap_or_default();
+        if let Ok(header) = header.parse::<Header>() {
+            let (command, data) = body.split_once(char::is_whitespace).unwrap_or((body, ""));
+            let data = data.trim();
+            return match command {
+                "Identify" => Ok(Frame::Identify(header, serde_json::from_str(data)?)),
+                "Verify" => Ok(Frame::Verify(header, serde_json::from_str(data)?)),
+                "Message" => Ok(Frame::Message(header, serde_json::from_str(data)?)),
+                "Join" => Ok(Frame::JoinChannel(header, serde_json::from_str(data)?)),
+                "Part" => Ok(Frame::PartChannel(header, serde_json::from_str(data)?)),
+                "Private" => Ok(Frame::Private(header, serde_json::from_str(data)?)),
+                "Quit" if data.is_empty() => Ok(Frame::Quit(header, Quit::default())),
+                "Quit" => Ok(Frame::Quit(header, serde_json::from_str(data)?)),
+                "Names" if data.is_empty() => Ok(Frame::Names(header, ChannelRequest::default())),
+                "Names" => Ok(Frame::Names(header, serde_json::from_str(data)?)),
+                "Away" if data.is_empty() => Ok(Frame::Away(header, Away::default())),
+                "Away" => Ok(Frame::Away(header, serde_json::from_str(data)?)),
+                "Kick" => Ok(Frame::Kick(header, serde_json::from_str(data)?)),
+                "Ban" => Ok(Frame::Ban(header, serde_json::from_str(data)?)),
+                "Unban" => Ok(Frame::Unban(header, serde_json::from_str(data)?)),
+                "Mute" => Ok(Frame::Mute(header, serde_json::from_str(data)?)),
+                "Unmute" => Ok(Frame::Unmute(header, serde_json::from_str(data)?)),
+                "Oper" => Ok(Frame::Oper(header, serde_json::from_str(data)?)),
+                "Register" => Ok(Frame::Register(header, serde_json::from_str(data)?)),
+                "Login" => Ok(Frame::Login(header, serde_json::from_str(data)?)),
+                "Die" if data.is_empty() => Ok(Frame::Die(header, Quit::default())),
+                "Die" => Ok(Frame::Die(header, serde_json::from_str(data)?)),
+                _ => Err(ProtocolError::UnsupportedCommand(command.to_string())),
+            };
+        }
+
+        match head {
+            "HELLO" => Ok(Frame::Hello(serde_json::from_str(body)?)),
+            "MSG" => Ok(Frame::Msg(body.to_string())),
+            "MOTD" => Ok(Frame::Motd(serde_json::from_str(body)?)),
+            "OK" => Ok(Frame::Ok(body.to_string())),
+            "ERR" => Ok(Frame::Err(body.to_string())),
+            "CHALLENGE" => Ok(Frame::Challenge(body.to_string())),
+            "UUID" => Ok(Frame::Uuid(body.to_string())),
+            "RESUME" => Ok(Frame::Resume(body.to_string())),
+            "UMSG" => Ok(Frame::Umsg(serde_json::from_str(body)?)),
+            "JOIN" => Ok(Frame::Join(serde_json::from_str(body)?)),
+            "PART" => Ok(Frame::Part(serde_json::from_str(body)?)),
+            "PMSG" => Ok(Frame::Pmsg(serde_json::from_str(body)?)),
+            "QUIT" => Ok(Frame::Uquit(serde_json::from_str(body)?)),
+            "NAMES" => Ok(Frame::Roster(serde_json::from_str(body)?)),
+            "PRESENCE" => Ok(Frame::Presence(serde_json::from_str(body)?)),
+            "GAP" => Ok(Frame::Gap(serde_json::from_str(body)?)),
+            "HISTORY" => Ok(Frame::History(serde_json::from_str(body)?)),
+            "SHUTDOWN" => Ok(Frame::Shutdown(body.to_string())),
+            "KICKED" => Ok(Frame::Kicked(serde_json::from_str(body)?)),
+            "MUTED" => Ok(Frame::Muted(serde_json::from_str(body)?)),
+            _ => Err(ProtocolError::UnsupportedHeader(head.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{MAX_BODY_LENGTH, MAX_LINE_LENGTH};
+
+    fn message() -> Message {
+        Message {
+            id: Some(7),
+            time: Some(String::from("2024-01-01T00:00:00Z")),
+            ..Message::new(DEFAULT_CHANNEL, "hello: world").attributed("uuid", "doğan")
+        }
+    }
+
+    fn presence() -> Presence {
+        Presence {
+            username: String::from("doğan"),
+            uuid: String::from("uuid"),
+            status: Status::Away,
+            away: String::from("lunch"),
+            operator: true,
+            previous: String::from("dogan"),
+        }
+    }
+
+    fn sanction() -> Sanction {
+        Sanction {
+            by: String::from("root"),
+            reason: String::from("spam"),
+            ban: true,
+            until: Some(String::from("2024-01-01T00:00:00Z")),
+        }
+    }
+
+    /// One of every frame, requests under each header.
+    fn frames() -> Vec<Frame> {
+        let mut frames = Vec::new();
+        for header in [Header::Fn, Header::FnReset, Header::FnFin] {
+            frames.extend([
+                Frame::Identify(
+                    header,
+                    Identify {
+                        username: String::from("doğan"),
+                        pem: String::from("-----BEGIN PUBLIC KEY-----\n...\n"),
+                        resume: String::from("uuid.secret"),
+                        password: String::from("hunter22"),
+                    },
+                ),
+                Frame::Verify(
+                    header,
+                    Verify {
+                        signature: String::from("c2lnbmF0dXJl"),
+                    },
+                ),
+                Frame::Message(header, Message::new(DEFAULT_CHANNEL, "hi :: there")),
+                Frame::JoinChannel(header, ChannelRequest::new("#rust")),
+                Frame::PartChannel(header, ChannelRequest::new("#rust")),
+                Frame::Private(header, PrivateRequest::new("doğan", "psst")),
+                Frame::Quit(
+                    header,
+                    Quit {
+                        reason: String::from("bye"),
+                    },
+                ),
+                Frame::Names(header, ChannelRequest::default()),
+                Frame::Away(
+                    header,
+                    Away {
+                        message: String::from("lunch"),
+                    },
+                ),
+                Frame::Kick(
+                    header,
+                    Kick {
+                        target: String::from("doğan"),
+                        reason: String::from("spam"),
+                    },
+                ),
+                Frame::Ban(
+                    header,
+                    Ban {
+                        kind: BanKind::Ip,
+                        mask: String::from("10.0.*"),
+                        duration: Some(60),
+                        reason: String::from("spam"),
+                    },
+                ),
+                Frame::Unban(
+                    header,
+                    Unban {
+                        kind: BanKind::Key,
+                        mask: String::from("ab12"),
+                    },
+                ),
+                Frame::Mute(
+                    header,
+                    Mute {
+                        target: String::from("doğan"),
+                        duration: None,
+                        reason: String::new(),
+                    },
+                ),
+                Frame::Unmute(
+                    header,
+                    Unmute {
+                        target: String::from("doğan"),
+                    },
+                ),
+                Frame::Oper(
+                    header,
+                    Oper {
+                        name: String::from("root"),
+                        password: String::from("secret"),
+                    },
+                ),
+                Frame::Register(
+                    header,
+                    Register {
+                        password: String::from("hunter22"),
+                    },
+                ),
+                Frame::Login(
+                    header,
+                    Login {
+                        name: String::from("doğan"),
+                        password: String::from("hunter22"),
+                    },
+                ),
+                Frame::Die(header, Quit::default()),
+            ]);
+        }
+        frames.extend([
+            Frame::Hello(Hello {
+                name: String::from("rustirc"),
+                version: String::from("0.1.0"),
+                capabilities: vec![String::from("resume")],
+                max_line_length: MAX_LINE_LENGTH,
+                max_body_length: MAX_BODY_LENGTH,
+            }),
+            Frame::Msg(String::from("Hello, stranger! :: welcome")),
+            Frame::Motd(Motd {
+                lines: vec![String::from("Welcome"), String::new()],
+            }),
+            Frame::Ok(String::from("Done.")),
+            Frame::Err(String::from("Nope.")),
+            Frame::Challenge(String::from("bm9uY2U=")),
+            Frame::Uuid(String::from("uuid")),
+            Frame::Resume(String::from("uuid.secret")),
+            Frame::Umsg(message()),
+            Frame::Join(Membership::new("#rust", "doğan")),
+            Frame::Part(Membership::new("#rust", "doğan").because("bye")),
+            Frame::Pmsg(PrivateMessage {
+                from: String::from("doğan"),
+                uuid: String::from("uuid"),
+                to: String::from("ayşe"),
+                content: String::from("psst"),
+            }),
+            Frame::Uquit(Departure {
+                username: String::from("doğan"),
+                uuid: String::from("uuid"),
+                reason: QuitReason::Netsplit,
+                message: String::from("a b"),
+            }),
+            Frame::Roster(Roster {
+                channel: String::from("#rust"),
+                users: vec![presence()],
+            }),
+            Frame::Presence(presence()),
+            Frame::Gap(3),
+            Frame::History(message()),
+            Frame::Shutdown(String::from("Server shutting down")),
+            Frame::Kicked(sanction()),
+            Frame::Muted(Sanction {
+                ban: false,
+                until: None,
+                ..sanction()
+            }),
+        ]);
+        frames
+    }
+
+    #[test]
+    fn every_frame_survives_a_round_trip() {
+        for frame in frames() {
+            let line = frame.to_string();
+            let parsed: Frame = line.parse().unwrap_or_else(|e| panic!("{line}: {e:?}"));
+            assert_eq!(
+                std::mem::discriminant(&parsed),
+                std::mem::discriminant(&frame),
+                "{line}"
+            );
+            assert_eq!(parsed.header(), frame.header(), "{line}");
+            assert_eq!(parsed.to_string(), line);
+        }
+    }
+
+    #[test]
+    fn requests_without_a_body_get_defaults() {
+        for (line, expected) in [
+            ("FN<>::Quit", "FN<>::Quit {\"reason\":\"\"}"),
+            ("FN<>::Names", "FN<>::Names {\"channel\":\"\"}"),
+            ("FN<>::Away", "FN<>::Away {\"message\":\"\"}"),
+            ("FN_FIN<>::Die", "FN_FIN<>::Die {\"reason\":\"\"}"),
+        ] {
+            assert_eq!(line.parse::<Frame>().unwrap().to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn surrounding_whitespace_is_ignored() {
+        let frame: Frame = "FN<>::Join   {\"channel\":\"#rust\"}  ".parse().unwrap();
+        assert_eq!(frame.to_string(), "FN<>::Join {\"channel\":\"#rust\"}");
+        let frame: Frame = "OK:: Done. ".parse().unwrap();
+        assert_eq!(frame.to_string(), "OK::Done.");
+    }
+
+    #[test]
+    fn malformed_lines_are_rejected() {
+        assert!(matches!(
+            "hello".parse::<Frame>(),
+            Err(ProtocolError::MissingIdentifier)
+        ));
+        assert!(matches!(
+            "XX<>::Identify {}".parse::<Frame>(),
+            Err(ProtocolError::UnsupportedHeader(head)) if head == "XX<>"
+        ));
+        assert!(matches!(
+            "NOPE::text".parse::<Frame>(),
+            Err(ProtocolError::UnsupportedHeader(head)) if head == "NOPE"
+        ));
+        assert!(matches!(
+            "FN<>::Dance {}".parse::<Frame>(),
+            Err(ProtocolError::UnsupportedCommand(command)) if command == "Dance"
+        ));
+        assert!(matches!(
+            "FN<>::Join {\"channel\":".parse::<Frame>(),
+            Err(ProtocolError::InvalidBody(_))
+        ));
+        assert!(matches!(
+            "FN<>::Verify {}".parse::<Frame>(),
+            Err(ProtocolError::InvalidBody(_))
+        ));
+        assert!(matches!(
+            "GAP::many".parse::<Frame>(),
+            Err(ProtocolError::InvalidBody(_))
+        ));
+    }
+}
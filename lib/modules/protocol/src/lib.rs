@@ -0,0 +1,11 @@
+pub mod codec;
+pub mod error;
+pub mod frame;
+
+pub use codec::{FrameCodec, FrameLimits};
+pub use error::ProtocolError;
+pub use frame::{
+    Away, Ban, BanKind, ChannelRequest, Departure, Frame, Header, Hello, Identify, Kick, Login,
+    Membership, Message, Motd, Mute, Oper, Presence, PrivateMessage, PrivateRequest, Quit,
+    QuitReason, Register, Roster, Sanction, Status, Unban, Unmute, Verify, DEFAULT_CHANNEL,
+};
@@ -0,0 +1,128 @@
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use chrono::{Local, SecondsFormat};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+pub const DEFAULT_NICK_GRACE: u64 = 60;
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+const GUEST_PREFIX: &str = "Guest";
+/// How long a rename off a registered nickname waits when no guest one was
+/// free.
+pub const GUEST_RETRY: Duration = Duration::from_secs(5);
+
+/// A nickname claimed by a user. It can be logged into with the password,
+/// if one was set, or by proving the key it was registered with.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Account {
+    /// The nickname, lowercased.
+    pub name: String,
+    /// Argon2 hash in PHC format, empty for key-only accounts.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub password: String,
+    /// Fingerprint of the key that registered the account, if any.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub key: String,
+    /// UUID of the profile that registered it.
+    pub owner: String,
+    pub registered: String,
+}
+
+impl Account {
+    /// Hashes the password off the async threads; it is slow on purpose.
+    pub async fn new(name: &str, password: &str, key: &str, owner: &str) -> Result<Self, String> {
+        let password = match password.is_empty() {
+            true => String::new(),
+            false => {
+                let password = password.to_string();
+                tokio::task::spawn_blocking(move || {
+                    let salt = SaltString::generate(&mut OsRng);
+                    Argon2::default()
+                        .hash_password(password.as_bytes(), &salt)
+                        .map(|hash| hash.to_string())
+                        .map_err(|e| e.to_string())
+                })
+                .await
+                .map_err(|e| e.to_string())??
+            }
+        };
+        Ok(Self {
+            name: name.to_lowercase(),
+            password,
+            key: key.to_string(),
+            owner: owner.to_string(),
+            registered: Local::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+        })
+    }
+
+    pub fn owns_key(&self, key: &str) -> bool {
+        !key.is_empty() && self.key == key
+    }
+
+    pub async fn check_password(&self, password: &str) -> bool {
+        if self.password.is_empty() || password.is_empty() {
+            return false;
+        }
+        let (hash, password) = (self.password.clone(), password.to_string());
+        tokio::task::spawn_blocking(move || {
+            PasswordHash::new(&hash).is_ok_and(|hash| {
+                Argon2::default()
+                    .verify_password(password.as_bytes(), &hash)
+                    .is_ok()
+            })
+        })
+        .await
+        .unwrap_or(false)
+    }
+}
+
+/// A throwaway nickname for users moved off a registered one.
+pub fn guest_name() -> String {
+    format!(
+        "{GUEST_PREFIX}{:05}",
+        rand::thread_rng().gen_range(0..100_000)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::valid_nick;
+
+    #[tokio::test]
+    async fn passwords_are_hashed_and_checked() {
+        let account = Account::new("Alice", "correct horse", "", "owner")
+            .await
+            .unwrap();
+        assert_eq!(account.name, "alice");
+        assert!(account.password.starts_with("$argon2"));
+        assert!(account.check_password("correct horse").await);
+        assert!(!account.check_password("wrong horse").await);
+        assert!(!account.check_password("").await);
+        assert!(!account.owns_key(""));
+    }
+
+    #[tokio::test]
+    async fn key_only_accounts_need_the_key() {
+        let account = Account::new("bob", "", "fingerprint", "owner")
+            .await
+            .unwrap();
+        assert!(account.password.is_empty());
+        assert!(!account.check_password("").await);
+        assert!(!account.check_password("anything").await);
+        assert!(account.owns_key("fingerprint"));
+        assert!(!account.owns_key("other"));
+        assert!(!account.owns_key(""));
+    }
+
+    #[test]
+    fn guest_names_are_valid_nicknames() {
+        for _ in 0..100 {
+            let guest = guest_name();
+            assert!(guest.starts_with(GUEST_PREFIX));
+            assert!(valid_nick(&guest), "{guest} should be valid");
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+pub const DEFAULT_MAX_CONNECTIONS: usize = 1024;
+pub const DEFAULT_MAX_PER_ADDRESS: usize = 16;
+pub const DEFAULT_HANDSHAKE_TIMEOUT: u64 = 30;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    /// Open sockets the listener accepts at once, 0 for no limit.
+    pub connections: usize,
+    /// Open sockets a single address may hold, 0 for no limit.
+    pub per_address: usize,
+    /// How long a connection may take to identify or register.
+    pub handshake: Duration,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            connections: DEFAULT_MAX_CONNECTIONS,
+            per_address: DEFAULT_MAX_PER_ADDRESS,
+            handshake: Duration::from_secs(DEFAULT_HANDSHAKE_TIMEOUT),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Counts {
+    total: usize,
+    addresses: HashMap<IpAddr, usize>,
+}
+
+/// Why a socket was turned away.
+#[derive(Debug)]
+pub enum Refusal {
+    Full,
+    Address,
+}
+
+impl fmt::Display for Refusal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Refusal::Full => "Too many connections",
+            Refusal::Address => "Too many connections from your address",
+        })
+    }
+}
+
+/// Counts the open sockets, overall and per address.
+#[derive(Default)]
+pub struct Admission {
+    limits: Mutex<Limits>,
+    counts: Arc<Mutex<Counts>>,
+}
+
+impl Admission {
+    pub fn limits(&self) -> Limits {
+        *self.limits.lock().unwrap()
+    }
+
+    /// Applies new limits. Sockets already over them are left open.
+    pub fn reconfigure(&self, limits: Limits) {
+        *self.limits.lock().unwrap() = limits;
+    }
+
+    /// Takes a slot for a freshly accepted socket. The slot is given back
+    /// when the returned permit is dropped.
+    pub fn admit(&self, ip: IpAddr) -> Result<Permit, Refusal> {
+        let limits = self.limits();
+        let mut counts = self.counts.lock().unwrap();
+        if limits.connections > 0 && counts.total >= limits.connections {
+            return Err(Refusal::Full);
+        }
+        let open = counts.addresses.get(&ip).copied().unwrap_or_default();
+        if limits.per_address > 0 && open >= limits.per_address {
+            return Err(Refusal::Address);
+        }
+        counts.total += 1;
+        counts.addresses.insert(ip, open + 1);
+        Ok(Permit {
+            ip,
+            counts: Arc::clone(&self.counts),
+        })
+    }
+
+    pub fn open(&self) -> usize {
+        self.counts.lock().unwrap().total
+    }
+}
+
+pub struct Permit {
+    ip: IpAddr,
+    counts: Arc<Mutex<Counts>>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let mut counts = self.counts.lock().unwrap();
+        counts.total -= 1;
+        if let Some(open) = counts.addresses.get_mut(&self.ip) {
+            *open -= 1;
+            if *open == 0 {
+                counts.addresses.remove(&self.ip);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn permits_are_given_back_when_dropped() {
+        let admission = Admission::default();
+        admission.reconfigure(Limits {
+            connections: 3,
+            per_address: 2,
+            ..Limits::default()
+        });
+        let (a, b): (IpAddr, IpAddr) = ("10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap());
+        let first = admission.admit(a).unwrap();
+        let second = admission.admit(a).unwrap();
+        assert!(matches!(admission.admit(a), Err(Refusal::Address)));
+        let third = admission.admit(b).unwrap();
+        assert!(matches!(admission.admit(b), Err(Refusal::Full)));
+        assert_eq!(admission.open(), 3);
+
+        drop(first);
+        assert_eq!(admission.open(), 2);
+        let again = admission.admit(a).unwrap();
+        drop((again, second, third));
+        assert_eq!(admission.open(), 0);
+        assert!(admission.counts.lock().unwrap().addresses.is_empty());
+    }
+
+    #[test]
+    fn zero_means_no_limit() {
+        let admission = Admission::default();
+        admission.reconfigure(Limits {
+            connections: 0,
+            per_address: 0,
+            ..Limits::default()
+        });
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let permits: Vec<Permit> = (0..100).map(|_| admission.admit(ip).unwrap()).collect();
+        assert_eq!(admission.open(), 100);
+        drop(permits);
+        assert_eq!(admission.open(), 0);
+    }
+}
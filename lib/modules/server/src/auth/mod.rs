@@ -0,0 +1,64 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use rand::RngCore;
+use rsa::pkcs1v15::{Signature, VerifyingKey};
+use rsa::pkcs8::{DecodePublicKey, EncodePublicKey};
+use rsa::signature::Verifier;
+use rsa::RsaPublicKey;
+use sha2::{Digest, Sha256};
+
+const NONCE_LENGTH: usize = 32;
+
+/// A pending proof of possession: the client must sign `nonce` with the
+/// private half of the public key it sent in `Identify.pem`.
+pub struct Challenge {
+    key: VerifyingKey<Sha256>,
+    nonce: Vec<u8>,
+}
+
+impl Challenge {
+    pub fn new(pem: &str) -> Result<Self, String> {
+        let key = RsaPublicKey::from_public_key_pem(pem).map_err(|e| e.to_string())?;
+        let mut nonce = vec![0u8; NONCE_LENGTH];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        Ok(Self {
+            key: VerifyingKey::new(key),
+            nonce,
+        })
+    }
+
+    /// The SHA-256 of the key's DER encoding, in hex. Key bans and the
+    /// moderator list refer to keys by it.
+    pub fn fingerprint(&self) -> String {
+        digest(self.key.as_ref())
+    }
+
+    pub fn nonce(&self) -> String {
+        STANDARD.encode(&self.nonce)
+    }
+
+    pub fn verify(&self, signature: &str) -> bool {
+        let Ok(bytes) = STANDARD.decode(signature) else {
+            return false;
+        };
+        let Ok(signature) = Signature::try_from(bytes.as_slice()) else {
+            return false;
+        };
+        self.key.verify(&self.nonce, &signature).is_ok()
+    }
+}
+
+/// The fingerprint of a PEM encoded public key, like
+/// [`Challenge::fingerprint`].
+pub fn fingerprint(pem: &str) -> Option<String> {
+    RsaPublicKey::from_public_key_pem(pem)
+        .ok()
+        .map(|key| digest(&key))
+}
+
+fn digest(key: &RsaPublicKey) -> String {
+    let der = key
+        .to_public_key_der()
+        .map(|der| der.into_vec())
+        .unwrap_or_default();
+    hex::encode(Sha256::digest(der))
+}
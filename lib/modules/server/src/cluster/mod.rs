@@ -0,0 +1,356 @@
+use crate::moderation::Ban;
+use crate::oper::Role;
+use crate::state::Server;
+use crate::util::generate_uuid::generate_session_key;
+use crate::util::log::{log, LogType};
+use futures::StreamExt;
+use protocol::{BanKind, Message, QuitReason, Sanction};
+use redis::aio::{MultiplexedConnection, PubSub};
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
+
+pub const EVENTS_CHANNEL: &str = "rustirc:events";
+/// How often a node lets the others know it is still there.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+/// How long a node may stay silent before its users are dropped.
+const NODE_TIMEOUT: Duration = Duration::from_secs(35);
+const RECONNECT_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_MAX: Duration = Duration::from_secs(60);
+
+/// A change to the shared state, made on one node and replayed on the others.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Event {
+    /// A node came up and wants to hear about everyone already online.
+    Hello,
+    /// Sent regularly so the other nodes notice when this one is gone.
+    Heartbeat,
+    Register {
+        uuid: String,
+        username: String,
+        away: String,
+        /// When the user registered, in milliseconds since the epoch. The
+        /// earlier of two users with the same nickname keeps it.
+        #[serde(default)]
+        since: i64,
+    },
+    Unregister {
+        uuid: String,
+        reason: QuitReason,
+        message: String,
+    },
+    Rename {
+        uuid: String,
+        username: String,
+    },
+    Away {
+        uuid: String,
+        message: String,
+    },
+    Oper {
+        uuid: String,
+        role: Role,
+    },
+    Join {
+        channel: String,
+        uuid: String,
+        username: String,
+    },
+    Part {
+        channel: String,
+        uuid: String,
+        reason: String,
+    },
+    /// A channel message, already stamped by the node it was sent to.
+    Message {
+        message: Message,
+    },
+    /// A server became reachable through a server link.
+    Server {
+        name: String,
+    },
+    /// A server is no longer reachable.
+    Squit {
+        name: String,
+    },
+    /// A moderator disconnected a user; its own node closes the connection.
+    Kick {
+        uuid: String,
+        sanction: Sanction,
+    },
+    Ban {
+        ban: Ban,
+    },
+    Unban {
+        kind: BanKind,
+        mask: String,
+    },
+    Mute {
+        uuid: String,
+        sanction: Sanction,
+    },
+    Unmute {
+        uuid: String,
+    },
+    /// A rendered frame for a single user connected to another node.
+    Deliver {
+        uuid: String,
+        frame: String,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    node: String,
+    event: Event,
+}
+
+enum Command {
+    Publish(Event),
+    Flush(oneshot::Sender<()>),
+}
+
+/// The server's handle for announcing local changes. Publishing happens on a
+/// separate task so state changes never wait on Redis.
+pub struct Relay {
+    commands: mpsc::UnboundedSender<Command>,
+}
+
+impl Relay {
+    pub fn send(&self, event: Event) {
+        let _ = self.commands.send(Command::Publish(event));
+    }
+}
+
+/// A running link to the other nodes.
+pub struct Cluster {
+    pub node: String,
+    commands: mpsc::UnboundedSender<Command>,
+}
+
+impl Cluster {
+    /// Waits until every event announced so far has been published.
+    pub async fn flush(&self) {
+        let (done, published) = oneshot::channel();
+        if self.commands.send(Command::Flush(done)).is_ok() {
+            let _ = published.await;
+        }
+    }
+}
+
+/// Subscribes to the shared event channel, links the server to it and asks
+/// the nodes already running for their users.
+pub async fn join(url: &str, server: Arc<Server>) -> Result<Cluster, String> {
+    let client = redis::Client::open(url).map_err(|e| format!("{url}: {e}"))?;
+    let publisher = client
+        .get_multiplexed_tokio_connection()
+        .await
+        .map_err(|e| format!("{url}: {e}"))?;
+    let subscriber = subscribe(&client)
+        .await
+        .map_err(|e| format!("{url}: {e}"))?;
+
+    let node = generate_session_key();
+    let (commands, queued) = mpsc::unbounded_channel();
+    server.link(Relay {
+        commands: commands.clone(),
+    });
+
+    tokio::spawn(publish(client.clone(), publisher, node.clone(), queued));
+    let listener = Listener {
+        client,
+        local: node.clone(),
+        commands: commands.clone(),
+        server,
+        nodes: HashMap::new(),
+        lost: HashSet::new(),
+    };
+    tokio::spawn(listener.run(subscriber));
+
+    let _ = commands.send(Command::Publish(Event::Hello));
+    Ok(Cluster { node, commands })
+}
+
+async fn subscribe(client: &redis::Client) -> redis::RedisResult<PubSub> {
+    let mut subscriber = client.get_async_pubsub().await?;
+    subscriber.subscribe(EVENTS_CHANNEL).await?;
+    Ok(subscriber)
+}
+
+/// Publishes what the server announces, with a heartbeat in between. A
+/// publish that fails is tried once more on a fresh connection.
+async fn publish(
+    client: redis::Client,
+    mut connection: MultiplexedConnection,
+    node: String,
+    mut queued: mpsc::UnboundedReceiver<Command>,
+) {
+    let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+    loop {
+        let event = tokio::select! {
+            command = queued.recv() => match command {
+                Some(Command::Publish(event)) => event,
+                Some(Command::Flush(done)) => {
+                    let _ = done.send(());
+                    continue;
+                }
+                None => return,
+            },
+            _ = heartbeat.tick() => Event::Heartbeat,
+        };
+        let envelope = Envelope {
+            node: node.clone(),
+            event,
+        };
+        let payload = serde_json::to_string(&envelope).unwrap();
+        if connection
+            .publish::<_, _, ()>(EVENTS_CHANNEL, &payload)
+            .await
+            .is_ok()
+        {
+            continue;
+        }
+        let retried = match client.get_multiplexed_tokio_connection().await {
+            Ok(fresh) => {
+                connection = fresh;
+                connection
+                    .publish::<_, _, ()>(EVENTS_CHANNEL, &payload)
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = retried {
+            log(
+                format!("Küme olayı yayınlanırken bir hata oluştu: {e}"),
+                LogType::ERROR,
+            );
+        }
+    }
+}
+
+/// Applies what the other nodes announce and keeps track of which of them
+/// are still alive.
+struct Listener {
+    client: redis::Client,
+    local: String,
+    commands: mpsc::UnboundedSender<Command>,
+    server: Arc<Server>,
+    /// When each node was last heard from.
+    nodes: HashMap<String, Instant>,
+    /// Nodes that went silent and had their users dropped.
+    lost: HashSet<String>,
+}
+
+impl Listener {
+    /// Listens until the subscription drops, then lets every other node go
+    /// and subscribes again with growing pauses. Once back, this node
+    /// announces its users again and asks for everyone else's.
+    async fn run(mut self, mut subscriber: PubSub) {
+        loop {
+            self.receive(subscriber).await;
+            log(
+                String::from(
+                    "Küme bağlantısı koptu, diğer sunuculardaki kullanıcılar ayrılmış sayılıyor.",
+                ),
+                LogType::ERROR,
+            );
+            for (node, _) in std::mem::take(&mut self.nodes) {
+                self.server.drop_node(&node);
+            }
+            self.lost.clear();
+            subscriber = self.resubscribe().await;
+            log(
+                String::from("Küme bağlantısı yeniden kuruldu."),
+                LogType::OK,
+            );
+            self.server.reannounce();
+            let _ = self.commands.send(Command::Publish(Event::Hello));
+        }
+    }
+
+    async fn receive(&mut self, subscriber: PubSub) {
+        let mut messages = subscriber.into_on_message();
+        let mut sweep = tokio::time::interval(HEARTBEAT_INTERVAL);
+        loop {
+            tokio::select! {
+                message = messages.next() => {
+                    let Some(message) = message else {
+                        return;
+                    };
+                    let Ok(payload) = message.get_payload::<String>() else {
+                        continue;
+                    };
+                    match serde_json::from_str::<Envelope>(&payload) {
+                        Ok(envelope) if envelope.node != self.local => self.apply(envelope),
+                        Ok(_) => {}
+                        Err(e) => log(format!("Kümeden gelen olay çözülemedi: {e}"), LogType::WARN),
+                    }
+                }
+                _ = sweep.tick() => self.sweep(),
+            }
+        }
+    }
+
+    fn apply(&mut self, envelope: Envelope) {
+        let returning = self
+            .nodes
+            .insert(envelope.node.clone(), Instant::now())
+            .is_none()
+            && self.lost.remove(&envelope.node);
+        if returning {
+            log(
+                format!(
+                    "{} düğümünden yeniden haber alındı, kullanıcıları isteniyor.",
+                    envelope.node
+                ),
+                LogType::STATUS,
+            );
+            let _ = self.commands.send(Command::Publish(Event::Hello));
+        }
+        self.server.apply(&envelope.node, envelope.event);
+    }
+
+    /// Drops the users of every node that has been silent for too long.
+    fn sweep(&mut self) {
+        let silent: Vec<String> = self
+            .nodes
+            .iter()
+            .filter(|(_, seen)| seen.elapsed() > NODE_TIMEOUT)
+            .map(|(node, _)| node.clone())
+            .collect();
+        for node in silent {
+            self.nodes.remove(&node);
+            let dropped = self.server.drop_node(&node);
+            log(
+                format!(
+                    "{node} düğümünden {} saniyedir haber alınamadı, {dropped} kullanıcısı ayrılmış sayılıyor.",
+                    NODE_TIMEOUT.as_secs()
+                ),
+                LogType::WARN,
+            );
+            self.lost.insert(node);
+        }
+    }
+
+    async fn resubscribe(&self) -> PubSub {
+        let mut pause = RECONNECT_MIN;
+        loop {
+            tokio::time::sleep(pause).await;
+            match subscribe(&self.client).await {
+                Ok(subscriber) => return subscriber,
+                Err(e) => log(
+                    format!(
+                        "Kümeye yeniden bağlanılamadı, {} saniye sonra tekrar denenecek: {e}",
+                        pause.as_secs()
+                    ),
+                    LogType::WARN,
+                ),
+            }
+            pause = (pause * 2).min(RECONNECT_MAX);
+        }
+    }
+}
@@ -0,0 +1,240 @@
+use crate::oper::{KEY_PREFIX, PASSWORD_PREFIX};
+use clap::ValueEnum;
+use ini::Ini;
+use std::fmt::Display;
+use std::str::FromStr;
+
+pub const CONFIG_FILE_PATH: &str = "config/listener.ini";
+
+/// The sections the listener reads, and the keys each may hold. Keys are
+/// named after the command line flags they stand in for.
+const KEYS: &[(&str, &[&str])] = &[
+    ("server", &["name", "bind", "mode", "motd"]),
+    (
+        "limits",
+        &[
+            "max-line-length",
+            "max-body-length",
+            "max-connections",
+            "max-per-ip",
+            "handshake-timeout",
+            "queue-size",
+            "overflow",
+            "flood-burst",
+            "flood-rate",
+            "flood-ip-burst",
+            "flood-ip-rate",
+            "flood-strikes",
+            "resume-grace",
+            "nick-grace",
+        ],
+    ),
+    ("history", &["file", "size"]),
+    ("tls", &["enabled", "cert", "key"]),
+];
+/// `<name> = <role>:<password>`, the same as `RUSTIRC_OPER_<NAME>` in the
+/// env file.
+const OPERATORS: &str = "operators";
+/// `<name> = <key fingerprint>`, the same as `RUSTIRC_OPER_KEY_<NAME>`.
+const OPERATOR_KEYS: &str = "operator-keys";
+
+/// The listener's INI file. Anything it sets is overridden by the command
+/// line.
+pub struct Config {
+    path: String,
+    ini: Ini,
+}
+
+impl Config {
+    /// Reads the file and rejects unknown sections and keys. A missing file
+    /// is not an error, there is just nothing to apply.
+    pub fn load(path: &str) -> Result<Option<Self>, String> {
+        let ini = match Ini::load_from_file_noescape(path) {
+            Ok(ini) => ini,
+            Err(ini::Error::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("{path}: {e}")),
+        };
+        for (section, properties) in ini.iter() {
+            let Some(section) = section else {
+                if let Some((key, _)) = properties.iter().next() {
+                    return Err(format!("{path}: {key} is not in any section"));
+                }
+                continue;
+            };
+            if section == OPERATORS || section == OPERATOR_KEYS {
+                continue;
+            }
+            let keys = KEYS
+                .iter()
+                .find(|(name, _)| *name == section)
+                .map(|(_, keys)| *keys)
+                .ok_or_else(|| format!("{path}: unknown section [{section}]"))?;
+            if let Some((key, _)) = properties.iter().find(|(key, _)| !keys.contains(key)) {
+                return Err(format!("{path}: unknown key {key} in [{section}]"));
+            }
+        }
+        Ok(Some(Self {
+            path: path.to_string(),
+            ini,
+        }))
+    }
+
+    pub fn get<T>(&self, section: &str, key: &str) -> Result<Option<T>, String>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.value(section, key)
+            .map(|value| value.parse().map_err(|e| self.invalid(section, key, e)))
+            .transpose()
+    }
+
+    /// A value out of a fixed set, spelled like the matching flag's values.
+    pub fn choice<T: ValueEnum>(&self, section: &str, key: &str) -> Result<Option<T>, String> {
+        self.value(section, key)
+            .map(|value| T::from_str(value, true).map_err(|e| self.invalid(section, key, e)))
+            .transpose()
+    }
+
+    /// A comma separated list.
+    pub fn list(&self, section: &str, key: &str) -> Vec<String> {
+        self.value(section, key)
+            .map(|value| {
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|item| !item.is_empty())
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// The operator entries, named like their env file counterparts.
+    pub fn operators(&self) -> Vec<(String, String)> {
+        let mut entries = Vec::new();
+        for (section, prefix) in [(OPERATORS, PASSWORD_PREFIX), (OPERATOR_KEYS, KEY_PREFIX)] {
+            if let Some(properties) = self.ini.section(Some(section)) {
+                entries.extend(properties.iter().map(|(name, value)| {
+                    (
+                        format!("{prefix}{}", name.to_uppercase()),
+                        value.trim().to_string(),
+                    )
+                }));
+            }
+        }
+        entries
+    }
+
+    fn value(&self, section: &str, key: &str) -> Option<&str> {
+        self.ini.get_from(Some(section), key).map(str::trim)
+    }
+
+    fn invalid(&self, section: &str, key: &str, e: impl Display) -> String {
+        format!("{}: [{section}] {key}: {e}", self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::queue::Overflow;
+
+    fn load(content: &str) -> Result<Option<Config>, String> {
+        let dir = std::env::temp_dir().join(format!("rustirc-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("listener.ini");
+        std::fs::write(&path, content).unwrap();
+        Config::load(&path.to_string_lossy())
+    }
+
+    fn error(content: &str) -> String {
+        match load(content) {
+            Err(e) => e,
+            Ok(_) => panic!("{content:?} should be refused"),
+        }
+    }
+
+    #[test]
+    fn a_missing_file_applies_nothing() {
+        let path = std::env::temp_dir()
+            .join(format!("rustirc-{}", uuid::Uuid::new_v4()))
+            .join("listener.ini");
+        assert!(Config::load(&path.to_string_lossy()).unwrap().is_none());
+    }
+
+    #[test]
+    fn unknown_sections_and_keys_are_refused() {
+        assert!(error("[colours]\nname = red\n").ends_with("unknown section [colours]"));
+        assert!(error("[server]\ncolour = red\n").ends_with("unknown key colour in [server]"));
+        assert!(error("name = alpha\n").ends_with("name is not in any section"));
+        // A key of one section is not accepted in another.
+        assert!(error("[tls]\nname = alpha\n").ends_with("unknown key name in [tls]"));
+    }
+
+    #[test]
+    fn values_are_parsed_like_the_flags() {
+        let config = load(
+            "[server]\nname = alpha\nbind = 0.0.0.0:6667, [::]:6667,\n\n\
+             [limits]\nmax-per-ip = 4\nmax-connections = lots\n",
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            config.get::<String>("server", "name").unwrap().as_deref(),
+            Some("alpha")
+        );
+        assert_eq!(
+            config.get::<usize>("limits", "max-per-ip").unwrap(),
+            Some(4)
+        );
+        assert_eq!(config.get::<usize>("limits", "queue-size").unwrap(), None);
+        let e = config
+            .get::<usize>("limits", "max-connections")
+            .unwrap_err();
+        assert!(e.contains("[limits] max-connections"));
+        assert_eq!(config.list("server", "bind"), ["0.0.0.0:6667", "[::]:6667"]);
+        assert!(config.list("server", "motd").is_empty());
+    }
+
+    #[test]
+    fn choices_are_spelled_like_the_flag_values() {
+        let config = load("[limits]\noverflow = Drop-Oldest\n").unwrap().unwrap();
+        assert_eq!(
+            config.choice::<Overflow>("limits", "overflow").unwrap(),
+            Some(Overflow::DropOldest)
+        );
+        let config = load("[limits]\noverflow = disconnect\n").unwrap().unwrap();
+        assert_eq!(
+            config.choice::<Overflow>("limits", "overflow").unwrap(),
+            Some(Overflow::Disconnect)
+        );
+        let config = load("[limits]\noverflow = sometimes\n").unwrap().unwrap();
+        assert!(config
+            .choice::<Overflow>("limits", "overflow")
+            .unwrap_err()
+            .contains("[limits] overflow"));
+        let config = load("[server]\nname = alpha\n").unwrap().unwrap();
+        assert_eq!(
+            config.choice::<Overflow>("limits", "overflow").unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn operators_are_named_like_the_env_file() {
+        let config = load("[operators]\nalice = owner:hunter22\n\n[operator-keys]\nbob = 0a1b2c\n")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            config.operators(),
+            [
+                (
+                    String::from("RUSTIRC_OPER_ALICE"),
+                    String::from("owner:hunter22")
+                ),
+                (String::from("RUSTIRC_OPER_KEY_BOB"), String::from("0a1b2c")),
+            ]
+        );
+    }
+}
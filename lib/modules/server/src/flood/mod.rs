@@ -0,0 +1,264 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_BURST: f64 = 10.0;
+pub const DEFAULT_RATE: f64 = 2.0;
+pub const DEFAULT_IP_BURST: f64 = 30.0;
+pub const DEFAULT_IP_RATE: f64 = 6.0;
+pub const DEFAULT_STRIKES: u32 = 30;
+/// Idle addresses are forgotten once this many are being tracked.
+const MAX_TRACKED_ADDRESSES: usize = 4096;
+
+/// How many requests can arrive at once, and how many more per second after
+/// that.
+#[derive(Debug, Clone, Copy)]
+pub struct Rate {
+    pub burst: f64,
+    pub refill: f64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    pub connection: Rate,
+    pub address: Rate,
+    /// Throttled requests in a row after which the connection is dropped.
+    pub strikes: u32,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            connection: Rate {
+                burst: DEFAULT_BURST,
+                refill: DEFAULT_RATE,
+            },
+            address: Rate {
+                burst: DEFAULT_IP_BURST,
+                refill: DEFAULT_IP_RATE,
+            },
+            strikes: DEFAULT_STRIKES,
+        }
+    }
+}
+
+/// A token bucket. Requests may overdraw it; the debt is how long the next
+/// request has to wait.
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(rate: Rate) -> Self {
+        Self {
+            tokens: rate.burst,
+            updated: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, rate: Rate) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate.refill).min(rate.burst);
+        self.updated = now;
+    }
+
+    /// Takes a token and returns how long to wait before acting on it.
+    fn take(&mut self, rate: Rate) -> Duration {
+        self.refill(rate);
+        self.tokens -= 1.0;
+        if self.tokens >= 0.0 || rate.refill <= 0.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64(-self.tokens / rate.refill)
+    }
+
+    fn full(&mut self, rate: Rate) -> bool {
+        self.refill(rate);
+        self.tokens >= rate.burst
+    }
+}
+
+/// The limits every connection is held to, plus one bucket per address
+/// shared by all of its connections.
+pub struct Flood {
+    limits: Mutex<Limits>,
+    addresses: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+impl Flood {
+    pub fn new(limits: Limits) -> Self {
+        Self {
+            limits: Mutex::new(limits),
+            addresses: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn limits(&self) -> Limits {
+        *self.limits.lock().unwrap()
+    }
+
+    /// Applies new limits. Connections pick them up with their next request.
+    pub fn reconfigure(&self, limits: Limits) {
+        *self.limits.lock().unwrap() = limits;
+    }
+
+    fn take(&self, ip: IpAddr) -> Duration {
+        let rate = self.limits().address;
+        let mut addresses = self.addresses.lock().unwrap();
+        if addresses.len() >= MAX_TRACKED_ADDRESSES {
+            addresses.retain(|_, bucket| !bucket.full(rate));
+        }
+        addresses
+            .entry(ip)
+            .or_insert_with(|| Bucket::new(rate))
+            .take(rate)
+    }
+}
+
+impl Default for Flood {
+    fn default() -> Self {
+        Self::new(Limits::default())
+    }
+}
+
+pub enum Verdict {
+    Allow,
+    /// Wait this long before handling the request. `warn` is set the first
+    /// time a connection goes over its limit.
+    Throttle {
+        wait: Duration,
+        warn: bool,
+    },
+    Disconnect,
+}
+
+/// One connection's standing against the flood limits.
+pub struct Limiter {
+    ip: IpAddr,
+    bucket: Bucket,
+    strikes: u32,
+}
+
+impl Limiter {
+    pub fn new(ip: IpAddr, flood: &Flood) -> Self {
+        Self {
+            ip,
+            bucket: Bucket::new(flood.limits().connection),
+            strikes: 0,
+        }
+    }
+
+    /// Accounts for one incoming request.
+    pub fn check(&mut self, flood: &Flood) -> Verdict {
+        let limits = flood.limits();
+        let wait = self.bucket.take(limits.connection).max(flood.take(self.ip));
+        if wait.is_zero() {
+            self.strikes = 0;
+            return Verdict::Allow;
+        }
+        self.strikes += 1;
+        if self.strikes > limits.strikes {
+            return Verdict::Disconnect;
+        }
+        Verdict::Throttle {
+            wait,
+            warn: self.strikes == 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    const IP: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    fn flood(burst: f64, refill: f64, strikes: u32) -> Flood {
+        let rate = Rate { burst, refill };
+        Flood::new(Limits {
+            connection: rate,
+            address: Rate {
+                burst: burst * 10.0,
+                refill: refill * 10.0,
+            },
+            strikes,
+        })
+    }
+
+    #[test]
+    fn a_full_bucket_lets_a_burst_through() {
+        let flood = flood(3.0, 1.0, 5);
+        let mut limiter = Limiter::new(IP, &flood);
+        for _ in 0..3 {
+            assert!(matches!(limiter.check(&flood), Verdict::Allow));
+        }
+        let Verdict::Throttle { wait, warn } = limiter.check(&flood) else {
+            panic!("the fourth request should be throttled");
+        };
+        assert!(warn);
+        assert!(wait > Duration::from_millis(900) && wait <= Duration::from_secs(1));
+        let Verdict::Throttle { wait, warn } = limiter.check(&flood) else {
+            panic!("the fifth request should be throttled");
+        };
+        assert!(!warn);
+        assert!(wait > Duration::from_millis(1900) && wait <= Duration::from_secs(2));
+    }
+
+    #[test]
+    fn the_bucket_refills_over_time() {
+        let flood = flood(1.0, 100.0, 5);
+        let mut limiter = Limiter::new(IP, &flood);
+        assert!(matches!(limiter.check(&flood), Verdict::Allow));
+        assert!(matches!(limiter.check(&flood), Verdict::Throttle { .. }));
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(matches!(limiter.check(&flood), Verdict::Allow));
+    }
+
+    #[test]
+    fn too_many_strikes_disconnect() {
+        let flood = flood(1.0, 0.001, 2);
+        let mut limiter = Limiter::new(IP, &flood);
+        assert!(matches!(limiter.check(&flood), Verdict::Allow));
+        assert!(matches!(limiter.check(&flood), Verdict::Throttle { .. }));
+        assert!(matches!(limiter.check(&flood), Verdict::Throttle { .. }));
+        assert!(matches!(limiter.check(&flood), Verdict::Disconnect));
+    }
+
+    #[test]
+    fn connections_from_one_address_share_its_bucket() {
+        let flood = Flood::new(Limits {
+            connection: Rate {
+                burst: 10.0,
+                refill: 1.0,
+            },
+            address: Rate {
+                burst: 2.0,
+                refill: 1.0,
+            },
+            strikes: 5,
+        });
+        let mut first = Limiter::new(IP, &flood);
+        let mut second = Limiter::new(IP, &flood);
+        let mut other = Limiter::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), &flood);
+        assert!(matches!(first.check(&flood), Verdict::Allow));
+        assert!(matches!(second.check(&flood), Verdict::Allow));
+        assert!(matches!(first.check(&flood), Verdict::Throttle { .. }));
+        assert!(matches!(other.check(&flood), Verdict::Allow));
+    }
+
+    #[test]
+    fn new_limits_apply_to_open_connections() {
+        let flood = flood(1.0, 0.001, 5);
+        let mut limiter = Limiter::new(IP, &flood);
+        assert!(matches!(limiter.check(&flood), Verdict::Allow));
+        flood.reconfigure(Limits {
+            strikes: 0,
+            ..flood.limits()
+        });
+        assert!(matches!(limiter.check(&flood), Verdict::Disconnect));
+    }
+}
@@ -0,0 +1,212 @@
+use chrono::{Local, SecondsFormat};
+use protocol::Message;
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+
+pub const HISTORY_FILE_PATH: &str = "history/messages.jsonl";
+pub const DEFAULT_REPLAY: usize = 50;
+
+struct Inner {
+    next_id: u64,
+    channels: HashMap<String, VecDeque<Message>>,
+    file: Option<File>,
+    replay: usize,
+}
+
+/// Every channel message, appended to a JSON-lines file as it is sent. The
+/// last `replay` messages of each channel are also kept in memory so they can
+/// be handed to whoever joins next.
+pub struct History {
+    inner: Mutex<Inner>,
+}
+
+impl History {
+    /// Loads what earlier runs recorded and keeps appending to the same file.
+    pub fn open(path: &str, replay: usize) -> Result<Self, String> {
+        let history = Self::memory(replay);
+        if let Some(parent) = Path::new(path).parent() {
+            fs::create_dir_all(parent).map_err(|e| format!("{path}: {e}"))?;
+        }
+        let mut inner = history.inner.lock().unwrap();
+        if let Ok(content) = fs::read_to_string(path) {
+            for line in content.lines().filter(|line| !line.trim().is_empty()) {
+                let message: Message =
+                    serde_json::from_str(line).map_err(|e| format!("{path}: {e}"))?;
+                inner.next_id = inner.next_id.max(message.id.unwrap_or_default() + 1);
+                keep(&mut inner, message);
+            }
+        }
+        inner.file = Some(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| format!("{path}: {e}"))?,
+        );
+        drop(inner);
+        Ok(history)
+    }
+
+    /// A history that is forgotten when the server stops.
+    pub fn memory(replay: usize) -> Self {
+        Self {
+            inner: Mutex::new(Inner {
+                next_id: 1,
+                channels: HashMap::new(),
+                file: None,
+                replay,
+            }),
+        }
+    }
+
+    /// Changes how many messages per channel are kept for replay, dropping
+    /// the oldest ones if there are now too many.
+    pub fn resize(&self, replay: usize) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.replay = replay;
+        for messages in inner.channels.values_mut() {
+            while messages.len() > replay {
+                messages.pop_front();
+            }
+        }
+    }
+
+    /// Stamps the message with the next id and the current time and stores
+    /// it. The stamped message is returned for delivery.
+    pub fn record(&self, mut message: Message) -> Result<Message, String> {
+        let mut inner = self.inner.lock().unwrap();
+        message.id = Some(inner.next_id);
+        message.time = Some(Local::now().to_rfc3339_opts(SecondsFormat::Secs, true));
+        message.channel = message.channel.to_lowercase();
+        self.write(&mut inner, &message)?;
+        Ok(message)
+    }
+
+    /// Stores a message that was already stamped by another node.
+    pub fn append(&self, message: &Message) -> Result<(), String> {
+        let mut inner = self.inner.lock().unwrap();
+        self.write(&mut inner, message)
+    }
+
+    fn write(&self, inner: &mut Inner, message: &Message) -> Result<(), String> {
+        if let Some(file) = inner.file.as_mut() {
+            let line = serde_json::to_string(message).map_err(|e| e.to_string())?;
+            writeln!(file, "{line}").map_err(|e| e.to_string())?;
+        }
+        inner.next_id = inner.next_id.max(message.id.unwrap_or_default() + 1);
+        keep(inner, message.clone());
+        Ok(())
+    }
+
+    /// The most recent messages of a channel, oldest first.
+    pub fn recent(&self, channel: &str) -> Vec<Message> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .channels
+            .get(&channel.to_lowercase())
+            .map(|messages| messages.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+fn keep(inner: &mut Inner, message: Message) {
+    let replay = inner.replay;
+    let messages = inner.channels.entry(message.channel.clone()).or_default();
+    messages.push_back(message);
+    while messages.len() > replay {
+        messages.pop_front();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(channel: &str, content: &str) -> Message {
+        Message {
+            id: None,
+            time: None,
+            uuid: String::from("u1"),
+            username: String::from("alice"),
+            channel: channel.to_string(),
+            content: content.to_string(),
+        }
+    }
+
+    fn contents(history: &History, channel: &str) -> Vec<String> {
+        history
+            .recent(channel)
+            .into_iter()
+            .map(|message| message.content)
+            .collect()
+    }
+
+    fn scratch() -> String {
+        std::env::temp_dir()
+            .join(format!("rustirc-{}", uuid::Uuid::new_v4()))
+            .join("messages.jsonl")
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn recorded_messages_are_replayed_after_a_restart() {
+        let path = scratch();
+        {
+            let history = History::open(&path, 2).unwrap();
+            for content in ["one", "two", "three"] {
+                history.record(message("#Rust", content)).unwrap();
+            }
+            let other = history.record(message("#go", "four")).unwrap();
+            assert_eq!(other.id, Some(4));
+            assert!(other.time.is_some());
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 4);
+
+        let history = History::open(&path, 2).unwrap();
+        assert_eq!(contents(&history, "#RUST"), ["two", "three"]);
+        assert_eq!(contents(&history, "#go"), ["four"]);
+        // Ids carry on from the last run, and from what other nodes stamped.
+        let five = history.record(message("#rust", "five")).unwrap();
+        assert_eq!(five.id, Some(5));
+        let mut remote = message("#rust", "six");
+        remote.id = Some(10);
+        history.append(&remote).unwrap();
+        assert_eq!(
+            history.record(message("#rust", "seven")).unwrap().id,
+            Some(11)
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 7);
+    }
+
+    #[test]
+    fn a_broken_line_is_reported() {
+        let path = scratch();
+        fs::create_dir_all(Path::new(&path).parent().unwrap()).unwrap();
+        fs::write(
+            &path,
+            "{\"channel\":\"#rust\",\"content\":\"hi\"}\n\nnot json\n",
+        )
+        .unwrap();
+        let Err(e) = History::open(&path, 2) else {
+            panic!("the broken line should be reported");
+        };
+        assert!(e.starts_with(&path));
+    }
+
+    #[test]
+    fn resizing_drops_the_oldest_messages() {
+        let history = History::memory(3);
+        for content in ["a", "b", "c"] {
+            history.record(message("#rust", content)).unwrap();
+        }
+        history.resize(1);
+        assert_eq!(contents(&history, "#rust"), ["c"]);
+        history.record(message("#rust", "d")).unwrap();
+        assert_eq!(contents(&history, "#rust"), ["d"]);
+        assert!(history.recent("#go").is_empty());
+    }
+}
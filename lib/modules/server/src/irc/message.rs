@@ -0,0 +1,226 @@
+use bytes::{BufMut, BytesMut};
+use protocol::ProtocolError;
+use std::fmt;
+use tokio_util::codec::{Decoder, Encoder, LinesCodec, LinesCodecError};
+
+/// Parameters a message can carry; the last one takes the rest of the line.
+const MAX_PARAMS: usize = 15;
+
+#[derive(Debug, Clone)]
+pub struct IrcMessage {
+    pub prefix: Option<String>,
+    pub command: String,
+    pub params: Vec<String>,
+}
+
+impl IrcMessage {
+    pub fn new(prefix: Option<&str>, command: &str, params: &[&str]) -> Self {
+        Self {
+            prefix: prefix.map(str::to_string),
+            command: command.to_string(),
+            params: params.iter().map(|p| p.to_string()).collect(),
+        }
+    }
+
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut rest = line.trim_end_matches(['\r', '\n']).trim_start();
+        let mut prefix = None;
+        if let Some(stripped) = rest.strip_prefix(':') {
+            let (p, r) = stripped.split_once(' ')?;
+            prefix = Some(p.to_string());
+            rest = r.trim_start();
+        }
+
+        let (command, mut rest) = rest.split_once(' ').unwrap_or((rest, ""));
+        if command.is_empty() {
+            return None;
+        }
+
+        let mut params = Vec::new();
+        loop {
+            rest = rest.trim_start_matches(' ');
+            if rest.is_empty() {
+                break;
+            }
+            if let Some(trailing) = rest.strip_prefix(':') {
+                params.push(trailing.to_string());
+                break;
+            }
+            if params.len() == MAX_PARAMS - 1 {
+                params.push(rest.to_string());
+                break;
+            }
+            let (param, r) = rest.split_once(' ').unwrap_or((rest, ""));
+            params.push(param.to_string());
+            rest = r;
+        }
+
+        Some(Self {
+            prefix,
+            command: command.to_ascii_uppercase(),
+            params,
+        })
+    }
+
+    pub fn param(&self, index: usize) -> Option<&str> {
+        self.params.get(index).map(String::as_str)
+    }
+}
+
+impl fmt::Display for IrcMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(prefix) = &self.prefix {
+            write!(f, ":{prefix} ")?;
+        }
+        f.write_str(&self.command)?;
+        if let Some((last, middle)) = self.params.split_last() {
+            for param in middle {
+                write!(f, " {param}")?;
+            }
+            if last.is_empty() || last.contains(' ') || last.starts_with(':') {
+                write!(f, " :{last}")?;
+            } else {
+                write!(f, " {last}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct IrcCodec {
+    lines: LinesCodec,
+}
+
+impl IrcCodec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fails the stream with `MaxLineLengthExceeded` on longer lines.
+    pub fn with_max_length(max_length: usize) -> Self {
+        Self {
+            lines: LinesCodec::new_with_max_length(max_length),
+        }
+    }
+}
+
+impl Decoder for IrcCodec {
+    type Item = IrcMessage;
+    type Error = LinesCodecError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        while let Some(line) = self.lines.decode(src)? {
+            if let Some(message) = IrcMessage::parse(&line) {
+                return Ok(Some(message));
+            }
+        }
+        Ok(None)
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        while let Some(line) = self.lines.decode_eof(src)? {
+            if let Some(message) = IrcMessage::parse(&line) {
+                return Ok(Some(message));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Refuses a message with a line break in it, so text relayed from native
+/// clients cannot smuggle in lines of its own.
+impl Encoder<IrcMessage> for IrcCodec {
+    type Error = ProtocolError;
+
+    fn encode(&mut self, message: IrcMessage, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let line = message.to_string();
+        if line.contains(['\r', '\n']) {
+            return Err(ProtocolError::Unframeable);
+        }
+        dst.reserve(line.len() + 2);
+        dst.put(line.as_bytes());
+        dst.put(&b"\r\n"[..]);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_breaks_cannot_be_smuggled_into_a_message() {
+        let mut codec = IrcCodec::new();
+        let mut dst = BytesMut::new();
+        let message = IrcMessage::new(
+            Some("alice"),
+            "PRIVMSG",
+            &["#rust", "hi\r\n:server KILL bob :forged"],
+        );
+        assert!(matches!(
+            codec.encode(message, &mut dst),
+            Err(ProtocolError::Unframeable)
+        ));
+        let message = IrcMessage::new(Some("bob\nQUIT"), "NICK", &["carol"]);
+        assert!(codec.encode(message, &mut dst).is_err());
+        assert!(dst.is_empty());
+
+        let message = IrcMessage::new(Some("alice"), "PRIVMSG", &["#rust", "hi there"]);
+        codec.encode(message, &mut dst).unwrap();
+        assert_eq!(&dst[..], b":alice PRIVMSG #rust :hi there\r\n");
+    }
+
+    fn parse(line: &str) -> IrcMessage {
+        IrcMessage::parse(line).unwrap()
+    }
+
+    #[test]
+    fn prefixes_and_commands_are_split_off() {
+        let message = parse(":alice!a@host privmsg #rust hello\r\n");
+        assert_eq!(message.prefix.as_deref(), Some("alice!a@host"));
+        assert_eq!(message.command, "PRIVMSG");
+        assert_eq!(message.params, ["#rust", "hello"]);
+
+        let message = parse("PING");
+        assert_eq!(message.prefix, None);
+        assert!(message.params.is_empty());
+        assert!(IrcMessage::parse("").is_none());
+        assert!(IrcMessage::parse(":alice").is_none());
+        assert!(IrcMessage::parse(":alice ").is_none());
+    }
+
+    #[test]
+    fn the_trailing_parameter_keeps_its_spaces() {
+        let message = parse("PRIVMSG #rust :hi  there :)");
+        assert_eq!(message.params, ["#rust", "hi  there :)"]);
+        let message = parse("PRIVMSG #rust :");
+        assert_eq!(message.params, ["#rust", ""]);
+        let message = parse("MODE  #rust   +o   bob ");
+        assert_eq!(message.params, ["#rust", "+o", "bob"]);
+    }
+
+    #[test]
+    fn the_fifteenth_parameter_takes_the_rest() {
+        let words: Vec<String> = (1..=20).map(|n| n.to_string()).collect();
+        let message = parse(&format!("CMD {}", words.join(" ")));
+        assert_eq!(message.params.len(), MAX_PARAMS);
+        assert_eq!(message.params[..14], words[..14]);
+        assert_eq!(message.params[14], "15 16 17 18 19 20");
+
+        let message = parse(&format!("CMD {} :last one", words[..14].join(" ")));
+        assert_eq!(message.params.len(), MAX_PARAMS);
+        assert_eq!(message.params[14], "last one");
+    }
+
+    #[test]
+    fn over_long_lines_fail_the_stream() {
+        let mut codec = IrcCodec::with_max_length(16);
+        let mut src = BytesMut::from("NICK alice\r\nPRIVMSG #rust :far too long\r\n");
+        assert_eq!(codec.decode(&mut src).unwrap().unwrap().command, "NICK");
+        assert!(matches!(
+            codec.decode(&mut src),
+            Err(LinesCodecError::MaxLineLengthExceeded)
+        ));
+    }
+}
@@ -0,0 +1,1077 @@
+mod message;
+mod nickserv;
+
+use crate::flood::{Limiter, Verdict};
+use crate::moderation::Subject;
+use crate::oper::Role;
+use crate::session::create_session;
+use crate::state::queue::{Delivery, Outbox, Packet};
+use crate::state::{valid_channel, valid_nick, Server, SERVER_VERSION, SHUTDOWN_MESSAGE};
+use crate::tls::Stream;
+use crate::util::log::{log, LogType};
+use chrono::{DateTime, Local};
+use futures::{SinkExt, StreamExt};
+use message::{IrcCodec, IrcMessage};
+pub use nickserv::NICKSERV;
+use protocol::{Frame, Message, PrivateMessage, QuitReason, Roster, Sanction, Status};
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, WriteHalf};
+use tokio::time::timeout;
+use tokio_util::codec::{FramedRead, FramedWrite, LinesCodecError};
+
+const DETECT_TIMEOUT: Duration = Duration::from_millis(500);
+const PING_INTERVAL: Duration = Duration::from_secs(120);
+const PING_TIMEOUT: Duration = Duration::from_secs(60);
+const REGISTRATION_COMMANDS: [&str; 4] = ["CAP", "PASS", "NICK", "USER"];
+
+/// What a fresh connection turned out to be.
+pub enum Front {
+    Native,
+    Irc,
+    Link,
+}
+
+/// Peeks at the first bytes of a fresh connection. IRC clients speak first
+/// with a registration command and linking servers with `SERVER`, while
+/// native clients wait for the greeting.
+pub async fn detect(socket: &mut Stream) -> Front {
+    match timeout(DETECT_TIMEOUT, socket.fill_buf()).await {
+        Ok(Ok(buf)) if !buf.is_empty() => {
+            let line = String::from_utf8_lossy(&buf[..buf.len().min(16)]);
+            let command = line
+                .split_whitespace()
+                .next()
+                .unwrap_or_default()
+                .to_ascii_uppercase();
+            if command == "SERVER" {
+                Front::Link
+            } else if REGISTRATION_COMMANDS.contains(&command.as_str()) {
+                Front::Irc
+            } else {
+                Front::Native
+            }
+        }
+        _ => Front::Native,
+    }
+}
+
+/// Tells an IRC client or a linking server it was turned away before
+/// closing the connection.
+pub async fn refuse(socket: Stream, addr: SocketAddr, reason: &str) {
+    let mut writer = FramedWrite::new(socket, IrcCodec::new());
+    let closing = format!("Closing Link: {} ({reason})", addr.ip());
+    let _ = writer
+        .send(IrcMessage::new(None, "ERROR", &[&closing]))
+        .await;
+    let _ = writer.get_mut().shutdown().await;
+}
+
+struct Writer<'a> {
+    addr: &'a SocketAddr,
+    server: &'a str,
+    writer: FramedWrite<WriteHalf<Stream>, IrcCodec>,
+}
+
+impl<'a> Writer<'a> {
+    fn new(addr: &'a SocketAddr, server: &'a str, writer: WriteHalf<Stream>) -> Self {
+        Self {
+            addr,
+            server,
+            writer: FramedWrite::new(writer, IrcCodec::new()),
+        }
+    }
+
+    async fn write(&mut self, message: IrcMessage) {
+        if let Err(e) = self.writer.send(message).await {
+            log(
+                format!(
+                    "{} makinesine yanıt gönderilirken bir hata oluştu: {}",
+                    self.addr, e
+                ),
+                LogType::ERROR,
+            );
+        }
+    }
+
+    async fn numeric(&mut self, code: &str, nick: &str, params: &[&str]) {
+        let mut all = vec![nick];
+        all.extend_from_slice(params);
+        self.write(IrcMessage::new(Some(self.server), code, &all))
+            .await;
+    }
+}
+
+struct Client {
+    addr: SocketAddr,
+    nick: Option<String>,
+    user: Option<String>,
+    uuid: Option<String>,
+    channels: HashSet<String>,
+    outbox: Outbox,
+    departure: (QuitReason, String),
+    /// Sent with PASS, tried against the account of the nickname.
+    password: String,
+    /// When a registered nickname must be identified for by.
+    deadline: Option<tokio::time::Instant>,
+}
+
+impl Client {
+    fn new(addr: SocketAddr, outbox: Outbox) -> Self {
+        Self {
+            addr,
+            nick: None,
+            user: None,
+            uuid: None,
+            channels: HashSet::new(),
+            outbox,
+            departure: (QuitReason::Closed, String::new()),
+            password: String::new(),
+            deadline: None,
+        }
+    }
+
+    fn nick(&self) -> &str {
+        self.nick.as_deref().unwrap_or("*")
+    }
+
+    fn prefix(&self) -> String {
+        format!(
+            "{}!{}@{}",
+            self.nick(),
+            self.user.as_deref().unwrap_or("*"),
+            self.addr.ip()
+        )
+    }
+
+    fn registered(&self) -> bool {
+        self.uuid.is_some()
+    }
+}
+
+enum Flow {
+    Continue,
+    Close,
+}
+
+pub async fn handle(socket: Stream, addr: SocketAddr, server: Arc<Server>) {
+    log(
+        format!("{addr} IRC uyumluluk kipinde bağlandı."),
+        LogType::STATUS,
+    );
+    let (reader, writer) = tokio::io::split(socket);
+    let mut reader = FramedRead::new(
+        reader,
+        IrcCodec::with_max_length(server.settings().limits.line),
+    );
+    let mut writer = Writer::new(&addr, &server.name, writer);
+    let outbox = server.outbox();
+    let mut client = Client::new(addr, Arc::clone(&outbox));
+    let mut limiter = Limiter::new(addr.ip(), &server.flood);
+    let idle = tokio::time::sleep(PING_INTERVAL);
+    tokio::pin!(idle);
+    let mut awaiting_pong = false;
+    let mut stopping = false;
+    let handshake = tokio::time::sleep(server.admission.limits().handshake);
+    tokio::pin!(handshake);
+    // A throttled message waits here; only reading is held up meanwhile.
+    let mut held = None;
+    let gate = tokio::time::sleep(Duration::ZERO);
+    tokio::pin!(gate);
+
+    loop {
+        tokio::select! {
+            _ = &mut handshake, if !client.registered() => {
+                log(format!("{addr} zamanında kayıt olmadı, bağlantı sonlandırılıyor."), LogType::WARN);
+                writer
+                    .write(IrcMessage::new(
+                        None,
+                        "ERROR",
+                        &[&format!("Closing Link: {} (Registration timed out)", addr.ip())],
+                    ))
+                    .await;
+                break;
+            }
+            _ = tokio::time::sleep_until(client.deadline.unwrap_or_else(tokio::time::Instant::now)), if client.deadline.is_some() => {
+                nickserv::enforce(&mut client, &server, &mut writer).await;
+            }
+            _ = server.stopping(), if !stopping => {
+                stopping = true;
+                client.departure = (QuitReason::Closed, String::from(SHUTDOWN_MESSAGE));
+                outbox.push(Packet::new(Frame::Shutdown(String::from(SHUTDOWN_MESSAGE))));
+                outbox.close();
+            }
+            _ = &mut gate, if held.is_some() => {
+                if let Some(message) = held.take() {
+                    if let Flow::Close = on_message(&mut client, message, &server, &mut writer).await {
+                        break;
+                    }
+                }
+            }
+            result = reader.next(), if held.is_none() => {
+                match result {
+                    None => break,
+                    Some(Ok(message)) => {
+                        awaiting_pong = false;
+                        idle.as_mut().reset(tokio::time::Instant::now() + PING_INTERVAL);
+                        if !matches!(message.command.as_str(), "PING" | "PONG") {
+                            match limiter.check(&server.flood) {
+                                Verdict::Allow => {}
+                                Verdict::Throttle { wait, warn } => {
+                                    if warn {
+                                        log(format!("{addr} mesaj sınırını aştı, istekleri yavaşlatılıyor."), LogType::WARN);
+                                        writer
+                                            .write(IrcMessage::new(
+                                                Some(&server.name),
+                                                "NOTICE",
+                                                &[client.nick(), "*** You are sending messages too fast. Slow down."],
+                                            ))
+                                            .await;
+                                    }
+                                    gate.as_mut().reset(tokio::time::Instant::now() + wait);
+                                    held = Some(message);
+                                    continue;
+                                }
+                                Verdict::Disconnect => {
+                                    log(format!("{addr} mesaj sınırını sürekli aştığı için bağlantısı sonlandırılıyor."), LogType::WARN);
+                                    client.departure = (QuitReason::Closed, String::from("Excess Flood"));
+                                    writer
+                                        .write(IrcMessage::new(
+                                            None,
+                                            "ERROR",
+                                            &[&format!("Closing Link: {} (Excess Flood)", addr.ip())],
+                                        ))
+                                        .await;
+                                    break;
+                                }
+                            }
+                        }
+                        if let Flow::Close = on_message(&mut client, message, &server, &mut writer).await {
+                            break;
+                        }
+                    }
+                    Some(Err(LinesCodecError::MaxLineLengthExceeded)) => {
+                        log(format!("{addr} çok uzun bir satır gönderdi, bağlantı sonlandırılıyor."), LogType::WARN);
+                        client.departure = (QuitReason::Closed, String::from("Input line was too long"));
+                        writer.numeric("417", client.nick(), &["Input line was too long"]).await;
+                        writer
+                            .write(IrcMessage::new(
+                                None,
+                                "ERROR",
+                                &[&format!("Closing Link: {} (Input line was too long)", addr.ip())],
+                            ))
+                            .await;
+                        break;
+                    }
+                    Some(Err(e)) => {
+                        log(format!("Bir hata oluştu: {e}"), LogType::ERROR);
+                        break;
+                    }
+                }
+            }
+            delivery = outbox.recv() => match delivery {
+                Delivery::Packet(packet) => on_frame(&client, &packet.frame, &mut writer).await,
+                Delivery::Gap(dropped) => {
+                    writer
+                        .write(IrcMessage::new(
+                            Some(&server.name),
+                            "NOTICE",
+                            &[client.nick(), &format!("*** {dropped} messages were dropped because your connection is too slow")],
+                        ))
+                        .await;
+                }
+                Delivery::Overflow => {
+                    log(format!("{addr} gönderilen mesajlara yetişemedi, bağlantı sonlandırılıyor."), LogType::WARN);
+                    client.departure = (QuitReason::Closed, String::from("SendQ exceeded"));
+                    writer
+                        .write(IrcMessage::new(
+                            None,
+                            "ERROR",
+                            &[&format!("Closing Link: {} (SendQ exceeded)", addr.ip())],
+                        ))
+                        .await;
+                    break;
+                }
+                Delivery::Closed => break,
+            },
+            _ = &mut idle => {
+                if awaiting_pong {
+                    log(format!("{addr} PING isteğine yanıt vermedi, bağlantı sonlandırılıyor."), LogType::WARN);
+                    client.departure = (QuitReason::Timeout, String::new());
+                    writer
+                        .write(IrcMessage::new(
+                            None,
+                            "ERROR",
+                            &[&format!("Closing Link: {} (Ping timeout)", addr.ip())],
+                        ))
+                        .await;
+                    break;
+                }
+                awaiting_pong = true;
+                writer
+                    .write(IrcMessage::new(None, "PING", &[&server.name]))
+                    .await;
+                idle.as_mut().reset(tokio::time::Instant::now() + PING_TIMEOUT);
+            }
+        }
+    }
+
+    if let Some(uuid) = &client.uuid {
+        let (reason, message) = &client.departure;
+        server.unregister(uuid, *reason, message);
+    }
+}
+
+async fn on_frame(client: &Client, frame: &Frame, writer: &mut Writer<'_>) {
+    let server = writer.server;
+    match frame {
+        Frame::Umsg(message) if Some(&message.uuid) != client.uuid.as_ref() => {
+            writer
+                .write(IrcMessage::new(
+                    Some(&message.username),
+                    "PRIVMSG",
+                    &[&message.channel, &message.content],
+                ))
+                .await;
+        }
+        Frame::Join(membership) if membership.username != client.nick() => {
+            writer
+                .write(IrcMessage::new(
+                    Some(&membership.username),
+                    "JOIN",
+                    &[&membership.channel],
+                ))
+                .await;
+        }
+        Frame::Part(membership) if membership.username != client.nick() => {
+            let mut params = vec![membership.channel.as_str()];
+            if !membership.reason.is_empty() {
+                params.push(&membership.reason);
+            }
+            writer
+                .write(IrcMessage::new(Some(&membership.username), "PART", &params))
+                .await;
+        }
+        Frame::Presence(presence)
+            if !presence.previous.is_empty() && Some(&presence.uuid) != client.uuid.as_ref() =>
+        {
+            writer
+                .write(IrcMessage::new(
+                    Some(&presence.previous),
+                    "NICK",
+                    &[&presence.username],
+                ))
+                .await;
+        }
+        Frame::Uquit(departure) => {
+            let reason = match (departure.reason, departure.message.is_empty()) {
+                (QuitReason::Quit, false) => format!("Quit: {}", departure.message),
+                (reason, true) => reason.to_string(),
+                (reason, false) => format!("{reason}: {}", departure.message),
+            };
+            writer
+                .write(IrcMessage::new(
+                    Some(&departure.username),
+                    "QUIT",
+                    &[&reason],
+                ))
+                .await;
+        }
+        Frame::Kicked(sanction) => {
+            writer
+                .write(IrcMessage::new(
+                    None,
+                    "ERROR",
+                    &[&format!(
+                        "Closing Link: {} ({})",
+                        client.addr.ip(),
+                        closing(sanction)
+                    )],
+                ))
+                .await;
+        }
+        Frame::Muted(sanction) => {
+            let until = sanction
+                .until
+                .as_deref()
+                .map(|until| format!(" until {until}"))
+                .unwrap_or_default();
+            let reason = if sanction.reason.is_empty() {
+                String::new()
+            } else {
+                format!(": {}", sanction.reason)
+            };
+            writer
+                .write(IrcMessage::new(
+                    Some(server),
+                    "NOTICE",
+                    &[
+                        client.nick(),
+                        &format!("*** You have been muted by {}{until}{reason}", sanction.by),
+                    ],
+                ))
+                .await;
+        }
+        Frame::Ok(text) => {
+            writer
+                .write(IrcMessage::new(
+                    Some(server),
+                    "NOTICE",
+                    &[client.nick(), &format!("*** {text}")],
+                ))
+                .await;
+        }
+        Frame::Shutdown(reason) => {
+            writer
+                .write(IrcMessage::new(
+                    None,
+                    "ERROR",
+                    &[&format!("Closing Link: {} ({reason})", client.addr.ip())],
+                ))
+                .await;
+        }
+        Frame::Pmsg(message) if Some(&message.uuid) != client.uuid.as_ref() => {
+            writer
+                .write(IrcMessage::new(
+                    Some(&message.from),
+                    "PRIVMSG",
+                    &[client.nick(), &message.content],
+                ))
+                .await;
+        }
+        _ => {}
+    }
+}
+
+async fn on_message(
+    client: &mut Client,
+    message: IrcMessage,
+    server: &Server,
+    writer: &mut Writer<'_>,
+) -> Flow {
+    match message.command.as_str() {
+        "CAP" => {
+            if message.param(0) == Some("LS") {
+                writer
+                    .write(IrcMessage::new(Some(&server.name), "CAP", &["*", "LS", ""]))
+                    .await;
+            }
+        }
+        "PASS" => {
+            if let Some(password) = message.param(0) {
+                client.password = password.to_string();
+            }
+        }
+        "PING" => {
+            let token = message.param(0).unwrap_or(&server.name);
+            writer
+                .write(IrcMessage::new(
+                    Some(&server.name),
+                    "PONG",
+                    &[&server.name, token],
+                ))
+                .await;
+        }
+        "PONG" => {}
+        "MOTD" if client.registered() => motd(client.nick(), server, writer).await,
+        "QUIT" => {
+            let reason = message.param(0).unwrap_or("Client Quit");
+            client.departure = (QuitReason::Quit, reason.to_string());
+            writer
+                .write(IrcMessage::new(
+                    None,
+                    "ERROR",
+                    &[&format!(
+                        "Closing Link: {} (Quit: {reason})",
+                        client.addr.ip()
+                    )],
+                ))
+                .await;
+            return Flow::Close;
+        }
+        "NICK" => {
+            let Some(nick) = message.param(0).filter(|n| valid_nick(n)) else {
+                writer
+                    .numeric("432", client.nick(), &["Erroneous nickname"])
+                    .await;
+                return Flow::Continue;
+            };
+            if let Some(uuid) = &client.uuid {
+                let ip = client.addr.ip().to_string();
+                let subject = Subject {
+                    uuid,
+                    nick,
+                    ip: &ip,
+                    key: "",
+                };
+                if server.banned(&subject).is_some() {
+                    writer
+                        .numeric("432", client.nick(), &[nick, "Nickname is banned"])
+                        .await;
+                    return Flow::Continue;
+                }
+                if !server.rename(uuid, nick) {
+                    writer
+                        .numeric("433", client.nick(), &[nick, "Nickname is already in use"])
+                        .await;
+                    return Flow::Continue;
+                }
+                let prefix = client.prefix();
+                writer
+                    .write(IrcMessage::new(Some(&prefix), "NICK", &[nick]))
+                    .await;
+                client.nick = Some(nick.to_string());
+                nickserv::guard(client, server, writer).await;
+                return Flow::Continue;
+            }
+            if server.taken(nick, "") {
+                writer
+                    .numeric("433", client.nick(), &[nick, "Nickname is already in use"])
+                    .await;
+                return Flow::Continue;
+            }
+            client.nick = Some(nick.to_string());
+            return register(client, server, writer).await;
+        }
+        "USER" => {
+            if client.registered() {
+                writer
+                    .numeric("462", client.nick(), &["You may not reregister"])
+                    .await;
+                return Flow::Continue;
+            }
+            let Some(user) = message.param(0) else {
+                writer
+                    .numeric("461", client.nick(), &["USER", "Not enough parameters"])
+                    .await;
+                return Flow::Continue;
+            };
+            client.user = Some(user.to_string());
+            return register(client, server, writer).await;
+        }
+        _ if !client.registered() => {
+            writer
+                .numeric("451", client.nick(), &["You have not registered"])
+                .await;
+        }
+        "JOIN" => {
+            let Some(channels) = message.param(0).filter(|c| !c.is_empty()) else {
+                writer
+                    .numeric("461", client.nick(), &["JOIN", "Not enough parameters"])
+                    .await;
+                return Flow::Continue;
+            };
+            let channels = channels.to_string();
+            for channel in channels.split(',').filter(|c| !c.is_empty()) {
+                if valid_channel(channel) {
+                    join(client, server, channel, writer).await;
+                } else {
+                    writer
+                        .numeric("403", client.nick(), &[channel, "No such channel"])
+                        .await;
+                }
+            }
+        }
+        "PART" => {
+            let channels = message.param(0).unwrap_or_default().to_string();
+            let reason = message.param(1).unwrap_or(client.nick()).to_string();
+            for channel in channels.split(',').filter(|c| !c.is_empty()) {
+                let key = channel.to_lowercase();
+                if client.channels.remove(&key) {
+                    if let Some(uuid) = &client.uuid {
+                        server.part(&key, uuid, &reason);
+                    }
+                    let prefix = client.prefix();
+                    writer
+                        .write(IrcMessage::new(Some(&prefix), "PART", &[&key, &reason]))
+                        .await;
+                } else {
+                    writer
+                        .numeric(
+                            "442",
+                            client.nick(),
+                            &[channel, "You're not on that channel"],
+                        )
+                        .await;
+                }
+            }
+        }
+        "NAMES" => {
+            let channels = message.param(0).unwrap_or_default().to_string();
+            if channels.is_empty() {
+                names(client, server.roster(""), writer).await;
+            }
+            for channel in channels.split(',').filter(|c| !c.is_empty()) {
+                names(client, server.roster(channel), writer).await;
+            }
+        }
+        "AWAY" => {
+            let away = message.param(0).unwrap_or_default().trim();
+            if let Some(uuid) = &client.uuid {
+                server.set_away(uuid, away);
+            }
+            let (code, text) = if away.is_empty() {
+                ("305", "You are no longer marked as being away")
+            } else {
+                ("306", "You have been marked as being away")
+            };
+            writer.numeric(code, client.nick(), &[text]).await;
+        }
+        "OPER" => {
+            let (Some(name), Some(password)) = (message.param(0), message.param(1)) else {
+                writer
+                    .numeric("461", client.nick(), &["OPER", "Not enough parameters"])
+                    .await;
+                return Flow::Continue;
+            };
+            let role = server.settings().operators.authenticate(name, password);
+            match role {
+                Some(role) => {
+                    log(
+                        format!(
+                            "{} ({}) {name} operatörü olarak giriş yaptı: {role}",
+                            client.addr,
+                            client.nick()
+                        ),
+                        LogType::STATUS,
+                    );
+                    if let Some(uuid) = &client.uuid {
+                        server.oper(uuid, role);
+                    }
+                    writer
+                        .numeric(
+                            "381",
+                            client.nick(),
+                            &[&format!("You are now a server {role}")],
+                        )
+                        .await;
+                }
+                None => {
+                    log(
+                        format!(
+                            "{} ({}) {name} operatörü olarak giriş yapamadı.",
+                            client.addr,
+                            client.nick()
+                        ),
+                        LogType::WARN,
+                    );
+                    writer
+                        .numeric("464", client.nick(), &["Password incorrect"])
+                        .await;
+                }
+            }
+        }
+        "KILL" => {
+            let Some(role) = privileged(client, server, Role::Moderator, writer).await else {
+                return Flow::Continue;
+            };
+            let Some(target) = message.param(0) else {
+                writer
+                    .numeric("461", client.nick(), &["KILL", "Not enough parameters"])
+                    .await;
+                return Flow::Continue;
+            };
+            let Some((uuid, username)) = server.lookup(target) else {
+                writer
+                    .numeric("401", client.nick(), &[target, "No such nick/channel"])
+                    .await;
+                return Flow::Continue;
+            };
+            if server.role(&uuid).is_some_and(|theirs| theirs >= role) {
+                writer
+                    .numeric(
+                        "481",
+                        client.nick(),
+                        &["Permission Denied- You cannot kill a user with an equal or higher role"],
+                    )
+                    .await;
+                return Flow::Continue;
+            }
+            let reason = message.param(1).unwrap_or_default();
+            log(
+                format!(
+                    "{}, {username} kullanıcısını sunucudan attı: {reason}",
+                    client.nick()
+                ),
+                LogType::WARN,
+            );
+            server.kick(
+                &uuid,
+                Sanction {
+                    by: client.nick().to_string(),
+                    reason: reason.to_string(),
+                    ban: false,
+                    until: None,
+                },
+            );
+        }
+        "DIE" => {
+            if privileged(client, server, Role::Owner, writer)
+                .await
+                .is_some()
+            {
+                log(
+                    format!("{} sunucuyu kapatıyor.", client.nick()),
+                    LogType::WARN,
+                );
+                server.shutdown();
+            }
+        }
+        "WHO" => {
+            let mask = message.param(0).unwrap_or("*");
+            let roster = if valid_channel(mask) {
+                server.roster(mask)
+            } else {
+                let mut roster = server.roster("");
+                if mask != "*" {
+                    roster
+                        .users
+                        .retain(|user| user.username.eq_ignore_ascii_case(mask));
+                }
+                roster
+            };
+            let channel = if roster.channel.is_empty() {
+                "*"
+            } else {
+                &roster.channel
+            };
+            let nick = client.nick().to_string();
+            for user in &roster.users {
+                writer
+                    .numeric(
+                        "352",
+                        &nick,
+                        &[
+                            channel,
+                            &user.username,
+                            &server.name,
+                            &server.name,
+                            &user.username,
+                            &format!(
+                                "{}{}",
+                                if user.status == Status::Away {
+                                    "G"
+                                } else {
+                                    "H"
+                                },
+                                if user.operator { "*" } else { "" }
+                            ),
+                            &format!("0 {}", user.uuid),
+                        ],
+                    )
+                    .await;
+            }
+            writer
+                .numeric("315", &nick, &[mask, "End of /WHO list"])
+                .await;
+        }
+        "PRIVMSG" | "NOTICE" => {
+            let notice = message.command == "NOTICE";
+            let (Some(target), Some(text)) = (message.param(0), message.param(1)) else {
+                if !notice {
+                    writer
+                        .numeric("412", client.nick(), &["No text to send"])
+                        .await;
+                }
+                return Flow::Continue;
+            };
+            let channel = target.to_lowercase();
+            if !notice && target.eq_ignore_ascii_case(nickserv::NICKSERV) {
+                nickserv::command(client, server, text, writer).await;
+            } else if client
+                .uuid
+                .as_deref()
+                .is_some_and(|uuid| server.muted(uuid))
+            {
+                if !notice {
+                    writer
+                        .numeric("404", client.nick(), &[target, "You are muted"])
+                        .await;
+                }
+            } else if client.channels.contains(&channel) {
+                server.publish(
+                    Message::new(&channel, text)
+                        .attributed(client.uuid.as_deref().unwrap_or_default(), client.nick()),
+                );
+            } else if let (false, Some((uuid, to))) = (valid_channel(target), server.lookup(target))
+            {
+                let message = PrivateMessage {
+                    from: client.nick().to_string(),
+                    uuid: client.uuid.clone().unwrap_or_default(),
+                    to,
+                    content: text.to_string(),
+                };
+                if let (false, Some(away)) = (notice, server.away(&uuid)) {
+                    writer
+                        .numeric("301", client.nick(), &[&message.to, &away])
+                        .await;
+                }
+                server.deliver(&uuid, Frame::Pmsg(message));
+            } else if !notice {
+                let (code, reason) = if server.exists(&channel) {
+                    ("404", "Cannot send to channel")
+                } else {
+                    ("401", "No such nick/channel")
+                };
+                writer.numeric(code, client.nick(), &[target, reason]).await;
+            }
+        }
+        command => {
+            writer
+                .numeric("421", client.nick(), &[command, "Unknown command"])
+                .await;
+        }
+    }
+    Flow::Continue
+}
+
+/// Turns away a registration whose nickname someone else took; the client
+/// can pick another with NICK.
+async fn nick_in_use(client: &mut Client, nick: &str, writer: &mut Writer<'_>) -> Flow {
+    client.nick = None;
+    writer
+        .numeric("433", "*", &[nick, "Nickname is already in use"])
+        .await;
+    Flow::Continue
+}
+
+async fn register(client: &mut Client, server: &Server, writer: &mut Writer<'_>) -> Flow {
+    if client.registered() {
+        return Flow::Continue;
+    }
+    let (Some(nick), Some(_)) = (client.nick.clone(), client.user.as_ref()) else {
+        return Flow::Continue;
+    };
+
+    let ip = client.addr.ip().to_string();
+    let subject = Subject {
+        uuid: "",
+        nick: &nick,
+        ip: &ip,
+        key: "",
+    };
+    if let Some(ban) = server.banned(&subject) {
+        log(
+            format!(
+                r#"{} "{nick}" olarak bağlanmaya çalıştı ancak {} yasağı bulunuyor. Bağlantı sonlandırılıyor.."#,
+                client.addr, ban.kind
+            ),
+            LogType::WARN,
+        );
+        writer
+            .numeric("465", &nick, &["You are banned from this server"])
+            .await;
+        writer
+            .write(IrcMessage::new(
+                None,
+                "ERROR",
+                &[&format!(
+                    "Closing Link: {ip} ({})",
+                    closing(&ban.sanction())
+                )],
+            ))
+            .await;
+        return Flow::Close;
+    }
+
+    if server.taken(&nick, "") {
+        return nick_in_use(client, &nick, writer).await;
+    }
+
+    log(
+        format!(
+            r#"{} IRC istemcisi olarak kaydoldu: "{nick}" isimli oturum dosyası oluşturuluyor.."#,
+            client.addr
+        ),
+        LogType::STATUS,
+    );
+    let user = match create_session(server.store(), &nick, "", &client.addr.to_string()).await {
+        Ok((user, _)) => user,
+        Err(e) => {
+            log(
+                format!("{nick} için oturum dosyası oluşturulurken bir hata oluştu: {e}"),
+                LogType::ERROR,
+            );
+            writer
+                .write(IrcMessage::new(
+                    None,
+                    "ERROR",
+                    &["The session creation process fails. The link will be terminated."],
+                ))
+                .await;
+            return Flow::Close;
+        }
+    };
+    if !server.register(
+        &user.uuid,
+        &nick,
+        &client.addr.ip().to_string(),
+        "",
+        Arc::clone(&client.outbox),
+    ) {
+        let _ = server.store().delete(&user.uuid).await;
+        return nick_in_use(client, &nick, writer).await;
+    }
+    client.uuid = Some(user.uuid);
+
+    let prefix = client.prefix();
+    let created = server.created.format("%Y-%m-%d %H:%M:%S").to_string();
+    writer
+        .numeric(
+            "001",
+            &nick,
+            &[&format!("Welcome to the RustIRC network, {prefix}")],
+        )
+        .await;
+    writer
+        .numeric(
+            "002",
+            &nick,
+            &[&format!(
+                "Your host is {}, running version {SERVER_VERSION}",
+                server.name
+            )],
+        )
+        .await;
+    writer
+        .numeric(
+            "003",
+            &nick,
+            &[&format!("This server was created {created}")],
+        )
+        .await;
+    writer
+        .numeric("004", &nick, &[&server.name, SERVER_VERSION, "o", "o"])
+        .await;
+    writer
+        .numeric(
+            "005",
+            &nick,
+            &[
+                "CHANTYPES=#",
+                "NICKLEN=32",
+                "NETWORK=RustIRC",
+                "are supported by this server",
+            ],
+        )
+        .await;
+    motd(&nick, server, writer).await;
+    nickserv::guard(client, server, writer).await;
+
+    Flow::Continue
+}
+
+async fn motd(nick: &str, server: &Server, writer: &mut Writer<'_>) {
+    let motd = server.settings().motd.clone();
+    if motd.is_empty() {
+        writer.numeric("422", nick, &["MOTD File is missing"]).await;
+        return;
+    }
+    writer
+        .numeric(
+            "375",
+            nick,
+            &[&format!("- {} Message of the day - ", server.name)],
+        )
+        .await;
+    for line in &motd {
+        writer.numeric("372", nick, &[&format!("- {line}")]).await;
+    }
+    writer
+        .numeric("376", nick, &["End of /MOTD command."])
+        .await;
+}
+
+async fn join(client: &mut Client, server: &Server, channel: &str, writer: &mut Writer<'_>) {
+    let channel = channel.to_lowercase();
+    let Some(uuid) = &client.uuid else {
+        return;
+    };
+    if !server.join(&channel, uuid, client.nick()) {
+        return;
+    }
+    client.channels.insert(channel.clone());
+    let prefix = client.prefix();
+    writer
+        .write(IrcMessage::new(Some(&prefix), "JOIN", &[&channel]))
+        .await;
+    names(client, server.roster(&channel), writer).await;
+    replay(server, &channel, writer).await;
+}
+
+/// Plays the channel's recent messages back as ordinary PRIVMSGs, each
+/// prefixed with the time it was originally sent.
+async fn replay(server: &Server, channel: &str, writer: &mut Writer<'_>) {
+    for message in server.history(channel) {
+        let time = message
+            .time
+            .as_deref()
+            .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+            .map(|time| time.with_timezone(&Local).format("%H:%M").to_string())
+            .unwrap_or_default();
+        writer
+            .write(IrcMessage::new(
+                Some(&message.username),
+                "PRIVMSG",
+                &[channel, &format!("[{time}] {}", message.content)],
+            ))
+            .await;
+    }
+}
+
+async fn names(client: &Client, roster: Roster, writer: &mut Writer<'_>) {
+    let channel = if roster.channel.is_empty() {
+        "*"
+    } else {
+        &roster.channel
+    };
+    let names: Vec<&str> = roster.users.iter().map(|u| u.username.as_str()).collect();
+    writer
+        .numeric("353", client.nick(), &["=", channel, &names.join(" ")])
+        .await;
+    writer
+        .numeric("366", client.nick(), &[channel, "End of /NAMES list"])
+        .await;
+}
+
+/// The client's role if it holds at least `needed`; otherwise it is told it
+/// lacks the privileges.
+async fn privileged(
+    client: &Client,
+    server: &Server,
+    needed: Role,
+    writer: &mut Writer<'_>,
+) -> Option<Role> {
+    let role = client.uuid.as_deref().and_then(|uuid| server.role(uuid));
+    if role.is_some_and(|role| role >= needed) {
+        return role;
+    }
+    writer
+        .numeric(
+            "481",
+            client.nick(),
+            &[&format!(
+                "Permission Denied- You need to be a server {needed}"
+            )],
+        )
+        .await;
+    None
+}
+
+/// How a kick or ban reads in the closing `ERROR` line.
+fn closing(sanction: &Sanction) -> String {
+    let mut text = format!(
+        "{} by {}",
+        if sanction.ban { "Banned" } else { "Kicked" },
+        sanction.by
+    );
+    if let Some(until) = &sanction.until {
+        text.push_str(&format!(" until {until}"));
+    }
+    if !sanction.reason.is_empty() {
+        text.push_str(&format!(": {}", sanction.reason));
+    }
+    text
+}
@@ -0,0 +1,243 @@
+use super::message::IrcMessage;
+use super::{Client, Writer};
+use crate::account::{Account, GUEST_RETRY, MIN_PASSWORD_LENGTH};
+use crate::state::Server;
+use crate::util::log::{log, LogType};
+use tokio::time::Instant;
+
+pub const NICKSERV: &str = "NickServ";
+
+async fn notice(client: &Client, writer: &mut Writer<'_>, text: &str) {
+    writer
+        .write(IrcMessage::new(
+            Some(NICKSERV),
+            "NOTICE",
+            &[client.nick(), text],
+        ))
+        .await;
+}
+
+async fn load(server: &Server, name: &str) -> Result<Option<Account>, ()> {
+    server.store().load_account(name).await.map_err(|e| {
+        log(
+            format!("{name} hesabı okunurken bir hata oluştu: {e}"),
+            LogType::ERROR,
+        );
+    })
+}
+
+/// Logs the client into its nickname with the PASS it sent, or gives it the
+/// grace period to identify before it is renamed.
+pub async fn guard(client: &mut Client, server: &Server, writer: &mut Writer<'_>) {
+    let (Some(uuid), Some(nick)) = (client.uuid.clone(), client.nick.clone()) else {
+        return;
+    };
+    client.deadline = None;
+    if server
+        .account(&uuid)
+        .is_some_and(|account| account.eq_ignore_ascii_case(&nick))
+    {
+        return;
+    }
+    let Ok(Some(account)) = load(server, &nick).await else {
+        return;
+    };
+    if account.check_password(&client.password).await {
+        log(
+            format!("{} ({nick}) hesabına giriş yaptı.", client.addr),
+            LogType::STATUS,
+        );
+        server.login(&uuid, &account.name);
+        notice(
+            client,
+            writer,
+            &format!("You are now identified for {nick}."),
+        )
+        .await;
+        return;
+    }
+    let grace = server.settings().nick_grace;
+    client.deadline = Some(Instant::now() + grace);
+    notice(
+        client,
+        writer,
+        &format!(
+            "This nickname is registered. Identify with /msg {NICKSERV} IDENTIFY <password> within {} seconds or you will be renamed.",
+            grace.as_secs()
+        ),
+    )
+    .await;
+}
+
+/// Moves the client off a registered nickname it did not identify for.
+pub async fn enforce(client: &mut Client, server: &Server, writer: &mut Writer<'_>) {
+    client.deadline = None;
+    let (Some(uuid), Some(nick)) = (client.uuid.clone(), client.nick.clone()) else {
+        return;
+    };
+    let Some(guest) = server.rename_guest(&uuid) else {
+        log(
+            format!(
+                "{} ({nick}) için boş bir misafir takma adı bulunamadı, yeniden denenecek.",
+                client.addr
+            ),
+            LogType::WARN,
+        );
+        client.deadline = Some(Instant::now() + GUEST_RETRY);
+        return;
+    };
+    log(
+        format!(
+            "{} ({nick}) kayıtlı takma ada giriş yapmadı, {guest} olarak yeniden adlandırıldı.",
+            client.addr
+        ),
+        LogType::WARN,
+    );
+    notice(
+        client,
+        writer,
+        &format!("You did not identify for {nick} in time."),
+    )
+    .await;
+    let prefix = client.prefix();
+    writer
+        .write(IrcMessage::new(Some(&prefix), "NICK", &[&guest]))
+        .await;
+    client.nick = Some(guest);
+}
+
+/// Answers a PRIVMSG sent to NickServ.
+pub async fn command(client: &mut Client, server: &Server, text: &str, writer: &mut Writer<'_>) {
+    let mut words = text.split_whitespace();
+    let command = words.next().unwrap_or_default().to_ascii_uppercase();
+    let words: Vec<&str> = words.collect();
+    match (command.as_str(), words.as_slice()) {
+        ("REGISTER", [password, ..]) => register(client, server, password, writer).await,
+        ("IDENTIFY", [password]) => {
+            let nick = client.nick().to_string();
+            identify(client, server, &nick, password, writer).await;
+        }
+        ("IDENTIFY", [name, password, ..]) => {
+            identify(client, server, name, password, writer).await
+        }
+        _ => {
+            notice(
+                client,
+                writer,
+                "Commands: REGISTER <password>, IDENTIFY [nickname] <password>",
+            )
+            .await
+        }
+    }
+}
+
+async fn register(client: &mut Client, server: &Server, password: &str, writer: &mut Writer<'_>) {
+    let (Some(uuid), Some(nick)) = (client.uuid.clone(), client.nick.clone()) else {
+        return;
+    };
+    if password.chars().count() < MIN_PASSWORD_LENGTH {
+        notice(
+            client,
+            writer,
+            &format!("Passwords must be at least {MIN_PASSWORD_LENGTH} characters long."),
+        )
+        .await;
+        return;
+    }
+    match load(server, &nick).await {
+        Ok(None) => {}
+        Ok(Some(_)) => {
+            notice(client, writer, &format!("{nick} is already registered.")).await;
+            return;
+        }
+        Err(()) => {
+            notice(client, writer, "The account could not be created.").await;
+            return;
+        }
+    }
+    let created = match Account::new(&nick, password, "", &uuid).await {
+        Ok(account) => server
+            .store()
+            .create_account(&account)
+            .await
+            .map(|_| account),
+        Err(e) => Err(e),
+    };
+    match created {
+        Ok(account) => {
+            log(
+                format!("{} ({nick}) takma adını kaydettirdi.", client.addr),
+                LogType::OK,
+            );
+            server.login(&uuid, &account.name);
+            client.deadline = None;
+            notice(client, writer, &format!("{nick} is now registered to you.")).await;
+        }
+        Err(e) => {
+            log(
+                format!("{nick} hesabı oluşturulurken bir hata oluştu: {e}"),
+                LogType::ERROR,
+            );
+            notice(client, writer, "The account could not be created.").await;
+        }
+    }
+}
+
+async fn identify(
+    client: &mut Client,
+    server: &Server,
+    name: &str,
+    password: &str,
+    writer: &mut Writer<'_>,
+) {
+    let (Some(uuid), Some(nick)) = (client.uuid.clone(), client.nick.clone()) else {
+        return;
+    };
+    let account = match load(server, name).await {
+        Ok(Some(account)) => account,
+        Ok(None) => {
+            notice(client, writer, &format!("{name} is not registered.")).await;
+            return;
+        }
+        Err(()) => {
+            notice(client, writer, "The account could not be read.").await;
+            return;
+        }
+    };
+    if !account.check_password(password).await {
+        log(
+            format!("{} ({nick}) {name} hesabına giriş yapamadı.", client.addr),
+            LogType::WARN,
+        );
+        notice(client, writer, "Invalid password.").await;
+        return;
+    }
+    if !name.eq_ignore_ascii_case(&nick) {
+        if !server.rename(&uuid, name) {
+            notice(
+                client,
+                writer,
+                &format!("{name} is in use right now. Identify again once it is free."),
+            )
+            .await;
+            return;
+        }
+        let prefix = client.prefix();
+        writer
+            .write(IrcMessage::new(Some(&prefix), "NICK", &[name]))
+            .await;
+        client.nick = Some(name.to_string());
+    }
+    log(
+        format!("{} ({nick}) {name} hesabına giriş yaptı.", client.addr),
+        LogType::STATUS,
+    );
+    server.login(&uuid, &account.name);
+    client.deadline = None;
+    notice(
+        client,
+        writer,
+        &format!("You are now identified for {name}."),
+    )
+    .await;
+}
@@ -1 +0,0 @@
-pub mod hex;
\ No newline at end of file
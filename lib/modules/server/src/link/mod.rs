@@ -0,0 +1,292 @@
+use crate::cluster::Event;
+use crate::state::Server;
+use crate::tls::{self, Stream};
+use crate::util::compare::same;
+use crate::util::log::{log, LogType};
+use futures::{SinkExt, StreamExt};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{BufReader, ReadHalf, WriteHalf};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::time::{timeout, Instant};
+use tokio_util::codec::{FramedRead, FramedWrite, LinesCodec};
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+const PING_TIMEOUT: Duration = Duration::from_secs(30);
+const RECONNECT_DELAY: Duration = Duration::from_secs(10);
+const MAX_LINE_LENGTH: usize = 64 * 1024;
+
+struct Writer<'a> {
+    peer: &'a str,
+    writer: FramedWrite<WriteHalf<Stream>, LinesCodec>,
+}
+
+impl<'a> Writer<'a> {
+    fn new(peer: &'a str, writer: WriteHalf<Stream>) -> Self {
+        Self {
+            peer,
+            writer: FramedWrite::new(writer, LinesCodec::new()),
+        }
+    }
+
+    async fn write(&mut self, line: String) -> bool {
+        if let Err(e) = self.writer.send(line).await {
+            log(
+                format!(
+                    "{} sunucusuna bağlantı üzerinden yazılırken bir hata oluştu: {e}",
+                    self.peer
+                ),
+                LogType::ERROR,
+            );
+            return false;
+        }
+        true
+    }
+
+    async fn event(&mut self, event: &Event) -> bool {
+        let json = serde_json::to_string(event).unwrap();
+        self.write(format!("EVENT {json}")).await
+    }
+
+    async fn error(&mut self, reason: &str) {
+        self.write(format!("ERROR :{reason}")).await;
+        let _ = SinkExt::<String>::close(&mut self.writer).await;
+    }
+}
+
+type Reader = FramedRead<ReadHalf<Stream>, LinesCodec>;
+
+/// Serves a server that connected to us. Links are refused unless a shared
+/// secret is configured.
+pub async fn handle(
+    socket: Stream,
+    addr: String,
+    server: Arc<Server>,
+    secret: Option<String>,
+    binding: Option<String>,
+) {
+    log(
+        format!("{addr} bir sunucu bağlantısı başlattı."),
+        LogType::STATUS,
+    );
+    let Some(secret) = secret else {
+        let (_, writer) = tokio::io::split(socket);
+        let mut writer = Writer::new(&addr, writer);
+        writer.error("Server links are not enabled").await;
+        return;
+    };
+    let binding = binding.unwrap_or_default();
+    run(socket, &addr, server, &secret, &binding, false).await;
+}
+
+/// Keeps a link to `target` up, dialling it again after every split until
+/// the server shuts down. A `tls://` prefix dials through TLS.
+pub async fn connect(target: String, server: Arc<Server>, secret: String) {
+    loop {
+        tokio::select! {
+            _ = server.stopping() => return,
+            result = dial(&target) => match result {
+                Ok((socket, binding)) => {
+                    let binding = binding.unwrap_or_default();
+                    run(socket, &target, Arc::clone(&server), &secret, &binding, true).await;
+                }
+                Err(e) => log(
+                    format!("{target} sunucusuna bağlanılamadı: {e}"),
+                    LogType::WARN,
+                ),
+            },
+        }
+        tokio::select! {
+            _ = server.stopping() => return,
+            _ = tokio::time::sleep(RECONNECT_DELAY) => {}
+        }
+    }
+}
+
+async fn dial(target: &str) -> Result<(Stream, Option<String>), String> {
+    match target.strip_prefix("tls://") {
+        Some(target) => tls::connect(target).await,
+        None => {
+            let socket = TcpStream::connect(target)
+                .await
+                .map_err(|e| e.to_string())?;
+            Ok((BufReader::new(Box::new(socket)), None))
+        }
+    }
+}
+
+/// Answers the receiver's nonce: only someone holding the secret can compute
+/// it. Naming both ends keeps a proof from being reflected back at its
+/// sender or replayed on another link, and the binding of the TLS session
+/// it runs in keeps it from being relayed into another session.
+fn proof(secret: &str, binding: &str, sender: &str, receiver: &str, nonce: &str) -> String {
+    hex::encode(Sha256::digest(format!(
+        "{secret}:{sender}:{receiver}:{nonce}:{binding}"
+    )))
+}
+
+/// Reads the next handshake line and splits off the expected command.
+async fn expect(reader: &mut Reader, command: &str) -> Result<String, String> {
+    let line = match timeout(HANDSHAKE_TIMEOUT, reader.next()).await {
+        Err(_) => return Err(String::from("Handshake timed out")),
+        Ok(None) => return Err(String::from("Connection closed during handshake")),
+        Ok(Some(Err(e))) => return Err(e.to_string()),
+        Ok(Some(Ok(line))) => line,
+    };
+    match line.split_once(' ') {
+        Some((verb, rest)) if verb == command => Ok(rest.trim().to_string()),
+        _ if line.starts_with("ERROR ") => Err(line.trim_start_matches("ERROR :").to_string()),
+        _ => Err(format!("Expected {command}")),
+    }
+}
+
+/// Both sides introduce themselves with a nonce and prove they know the
+/// secret by hashing the other side's nonce with it. The side that dialled
+/// proves itself first; the other only answers once that proof checks out.
+async fn handshake(
+    reader: &mut Reader,
+    writer: &mut Writer<'_>,
+    server: &Server,
+    secret: &str,
+    binding: &str,
+    dialled: bool,
+) -> Result<String, String> {
+    let mut bytes = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let nonce = hex::encode(bytes);
+    writer
+        .write(format!("SERVER {} {nonce}", server.name))
+        .await;
+
+    let introduction = expect(reader, "SERVER").await?;
+    let (name, their_nonce) = introduction
+        .split_once(' ')
+        .ok_or_else(|| String::from("Malformed SERVER line"))?;
+    if name.is_empty() || name.contains([' ', ':']) || name == server.name {
+        return Err(String::from("Invalid server name"));
+    }
+    if their_nonce.is_empty() || same(their_nonce, &nonce) {
+        return Err(String::from("Invalid nonce"));
+    }
+    let ours = proof(secret, binding, &server.name, name, their_nonce);
+    if dialled {
+        writer.write(format!("AUTH {ours}")).await;
+    }
+    let answer = expect(reader, "AUTH").await?;
+    if !same(&answer, &proof(secret, binding, name, &server.name, &nonce)) {
+        return Err(String::from("Bad link password"));
+    }
+    if !dialled {
+        writer.write(format!("AUTH {ours}")).await;
+    }
+    Ok(name.to_string())
+}
+
+async fn run(
+    socket: Stream,
+    peer: &str,
+    server: Arc<Server>,
+    secret: &str,
+    binding: &str,
+    dialled: bool,
+) {
+    let (reader, writer) = tokio::io::split(socket);
+    let mut reader = FramedRead::new(reader, LinesCodec::new_with_max_length(MAX_LINE_LENGTH));
+    let mut writer = Writer::new(peer, writer);
+
+    let name = match handshake(&mut reader, &mut writer, &server, secret, binding, dialled).await {
+        Ok(name) => name,
+        Err(e) => {
+            log(
+                format!("{peer} ile sunucu el sıkışması başarısız oldu: {e}"),
+                LogType::WARN,
+            );
+            writer.error(&e).await;
+            return;
+        }
+    };
+    let (link, mut events) = mpsc::unbounded_channel();
+    if let Err(e) = server.attach(&name, link) {
+        log(
+            format!("{peer} ({name}) bağlantısı reddedildi: {e}"),
+            LogType::WARN,
+        );
+        writer.error(&e).await;
+        return;
+    }
+    log(
+        format!("{name} sunucusuyla bağlantı kuruldu ({peer})."),
+        LogType::OK,
+    );
+    for event in server.burst(&name) {
+        writer.event(&event).await;
+    }
+
+    let idle = tokio::time::sleep(PING_INTERVAL);
+    tokio::pin!(idle);
+    let mut awaiting_pong = false;
+    let reason = loop {
+        tokio::select! {
+            line = reader.next() => {
+                let line = match line {
+                    Some(Ok(line)) => line,
+                    Some(Err(e)) => break e.to_string(),
+                    None => break String::from("Connection closed"),
+                };
+                awaiting_pong = false;
+                idle.as_mut().reset(Instant::now() + PING_INTERVAL);
+                let (verb, rest) = line.split_once(' ').unwrap_or((&line, ""));
+                match verb {
+                    "PING" => {
+                        writer.write(String::from("PONG")).await;
+                    }
+                    "PONG" => {}
+                    "ERROR" => break rest.trim_start_matches(':').to_string(),
+                    "EVENT" => {
+                        let result = serde_json::from_str::<Event>(rest)
+                            .map_err(|e| e.to_string())
+                            .and_then(|event| server.receive(&name, event));
+                        if let Err(e) = result {
+                            writer.error(&e).await;
+                            break e;
+                        }
+                    }
+                    _ => {
+                        writer.error("Unknown command").await;
+                        break format!("Unknown command {verb}");
+                    }
+                }
+            }
+            Some(event) = events.recv() => {
+                if !writer.event(&event).await {
+                    break String::from("Write error");
+                }
+            }
+            _ = &mut idle => {
+                if awaiting_pong {
+                    writer.error("Ping timeout").await;
+                    break String::from("Ping timeout");
+                }
+                awaiting_pong = true;
+                writer.write(String::from("PING")).await;
+                idle.as_mut().reset(Instant::now() + PING_TIMEOUT);
+            }
+            _ = server.stopping() => {
+                writer.error("Server shutting down").await;
+                break String::from("Server shutting down");
+            }
+        }
+    };
+
+    let (servers, users) = server.split(&name);
+    log(
+        format!(
+            "{name} sunucusuyla bağlantı koptu ({reason}). Ağ bölündü: {servers} sunucu ve {users} kullanıcı ulaşılamaz durumda."
+        ),
+        LogType::WARN,
+    );
+}
@@ -1,20 +1,50 @@
-use clap::Parser;
-use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
-use std::fs;
-use std::net::SocketAddr;
-use tokio::{
-    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
-    net::{tcp::WriteHalf, TcpListener},
-    sync::broadcast,
-};
-mod lib;
+use admission::Refusal;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, ValueEnum};
+use config::{Config, CONFIG_FILE_PATH};
+use flood::{Limits, Rate};
+use history::{History, DEFAULT_REPLAY, HISTORY_FILE_PATH};
+use irc::Front;
+use moderation::{Moderation, BANS_FILE_PATH};
+use oper::{Operators, ENV_FILE_PATH};
+use protocol::{codec, FrameLimits, QuitReason};
+use session::Backend;
+use state::queue::{Overflow, DEFAULT_CAPACITY};
+use state::{resume, Server, Settings, SHUTDOWN_MESSAGE};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::BufReader;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::TlsAcceptor;
+use tokio_util::task::TaskTracker;
+mod account;
+mod admission;
+mod auth;
+mod cluster;
+mod config;
+mod flood;
+mod history;
+mod irc;
+mod link;
+mod moderation;
+mod native;
+mod oper;
+mod session;
+mod state;
+mod tls;
 mod util;
-use lib::hex::to_hex;
-use util::generate_uuid::generate_session_key;
 use util::log::{log, LogType};
 
-const SESSION_FOLDER_PATH: &'static str = "sessions";
+/// How long connections get to flush their queues once a shutdown starts.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+/// Bounds on the pause after a failed accept, e.g. when out of file
+/// descriptors. It doubles with every failure in a row.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(10);
+const MAX_ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+/// How long a refused connection gets to hear why.
+const REFUSAL_TIMEOUT: Duration = Duration::from_secs(2);
+const DEFAULT_PORT: u16 = 33363;
 
 #[derive(Parser)]
 #[command(name = "rustirc")]
@@ -22,276 +52,716 @@ const SESSION_FOLDER_PATH: &'static str = "sessions";
 #[command(version = "1.0")]
 #[command(about = "Rust IRC Sunucusu")]
 struct Args {
+    /// INI file with the same settings as the flags, which take precedence.
+    /// It is read again on SIGHUP.
+    #[arg(short = 'c', long = "config", default_value = CONFIG_FILE_PATH)]
+    config: String,
     #[arg(short = 'H', long = "hostname", default_value = "0.0.0.0")]
     host: String,
-    #[arg(short = 'p', long = "port", default_value = "80")]
+    #[arg(short = 'p', long = "port", default_value_t = DEFAULT_PORT)]
     port: u16,
+    /// Address to listen on, as host:port, in place of -H and -p. Can be
+    /// repeated.
+    #[arg(short = 'b', long = "bind")]
+    bind: Vec<String>,
+    #[arg(short = 'm', long = "mode", value_enum, default_value = "auto")]
+    mode: Mode,
+    /// Name this server goes by on a network of linked servers.
+    #[arg(short = 'n', long = "name", default_value = state::SERVER_NAME)]
+    name: String,
+    /// Text file whose lines are shown to users as they connect.
+    #[arg(long = "motd")]
+    motd: Option<String>,
+    /// Address of a server to link to, dialled over plain TCP, or through
+    /// TLS when prefixed with tls:// for a peer running with --tls. Can be
+    /// repeated.
+    #[arg(long = "link")]
+    links: Vec<String>,
+    /// Secret shared by every linked server. Incoming links are refused
+    /// without one; they are only recognised in auto mode.
+    #[arg(long = "link-secret")]
+    link_secret: Option<String>,
+    #[arg(long = "queue-size", default_value_t = DEFAULT_CAPACITY)]
+    queue_size: usize,
+    #[arg(long = "overflow", value_enum, default_value = "drop-oldest")]
+    overflow: Overflow,
+    /// Requests a connection may send at once before it is throttled.
+    #[arg(long = "flood-burst", default_value_t = flood::DEFAULT_BURST)]
+    flood_burst: f64,
+    /// Requests per second a connection may keep sending.
+    #[arg(long = "flood-rate", default_value_t = flood::DEFAULT_RATE)]
+    flood_rate: f64,
+    /// Like --flood-burst, shared by every connection from one address.
+    #[arg(long = "flood-ip-burst", default_value_t = flood::DEFAULT_IP_BURST)]
+    flood_ip_burst: f64,
+    #[arg(long = "flood-ip-rate", default_value_t = flood::DEFAULT_IP_RATE)]
+    flood_ip_rate: f64,
+    /// Throttled requests in a row after which a connection is dropped.
+    #[arg(long = "flood-strikes", default_value_t = flood::DEFAULT_STRIKES)]
+    flood_strikes: u32,
+    /// Longest line, in bytes, a client may send before it is disconnected.
+    #[arg(long = "max-line-length", default_value_t = codec::MAX_LINE_LENGTH)]
+    max_line_length: usize,
+    /// Largest JSON body, in bytes, a request may carry.
+    #[arg(long = "max-body-length", default_value_t = codec::MAX_BODY_LENGTH)]
+    max_body_length: usize,
+    /// Open connections the server accepts at once, 0 for no limit.
+    #[arg(long = "max-connections", default_value_t = admission::DEFAULT_MAX_CONNECTIONS)]
+    max_connections: usize,
+    /// Open connections a single address may hold, 0 for no limit.
+    #[arg(long = "max-per-ip", default_value_t = admission::DEFAULT_MAX_PER_ADDRESS)]
+    max_per_ip: usize,
+    /// Seconds a connection gets to identify or register before it is dropped.
+    #[arg(long = "handshake-timeout", default_value_t = admission::DEFAULT_HANDSHAKE_TIMEOUT)]
+    handshake_timeout: u64,
+    /// Seconds a dropped connection's user stays online so it can resume.
+    /// 0 turns resuming off.
+    #[arg(long = "resume-grace", default_value_t = resume::DEFAULT_RESUME_GRACE)]
+    resume_grace: u64,
+    /// Seconds a user may hold a registered nickname without logging in
+    /// before being renamed.
+    #[arg(long = "nick-grace", default_value_t = account::DEFAULT_NICK_GRACE)]
+    nick_grace: u64,
+    /// File channel messages are appended to. Only read at startup.
+    #[arg(long = "history-file", default_value = HISTORY_FILE_PATH)]
+    history_file: String,
+    /// Messages per channel kept for replay.
+    #[arg(long = "history-size", default_value_t = DEFAULT_REPLAY)]
+    history_size: usize,
+    #[arg(long = "bans-file", default_value = BANS_FILE_PATH)]
+    bans_file: String,
+    /// File holding the operator credentials, read again on every reload.
+    #[arg(long = "env-file", default_value = ENV_FILE_PATH)]
+    env_file: String,
+    #[arg(long = "session-store", value_enum, default_value = "directory")]
+    session_store: Backend,
+    /// Folder of the directory store, or URL of the Redis store.
+    #[arg(long = "session-location")]
+    session_location: Option<String>,
+    /// Redis URL through which several listeners share their users and
+    /// channels.
+    #[arg(long = "cluster")]
+    cluster: Option<String>,
+    #[arg(long = "tls")]
+    tls: bool,
+    #[arg(long = "tls-cert", default_value = tls::CERT_FILE)]
+    tls_cert: String,
+    #[arg(long = "tls-key", default_value = tls::KEY_FILE)]
+    tls_key: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Identify {
-    username: String,
-    pem: String,
+#[derive(Clone, Copy, ValueEnum)]
+enum Mode {
+    Auto,
+    Native,
+    Irc,
 }
 
-
-
-struct Writer<'a> {
-    addr: &'a SocketAddr,
-    writer: &'a mut WriteHalf<'a>,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct User {
-    username: String,
-    pem: String,
-    uuid: String,
-    addr: String,
-    banned: bool,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct Message {
-    uuid: String,
-    content: String,
-}
-
-impl Message {
-    fn new(uuid: &str, content: &str) -> Self {
-        Self {
-            uuid: uuid.to_string(),
-            content: content.to_string(),
+#[tokio::main]
+async fn main() {
+    clear();
+    let matches = Args::command().get_matches();
+    let (mut args, config) = match settle(&matches) {
+        Ok(settled) => settled,
+        Err(e) => {
+            log(
+                format!("Yapılandırma yüklenirken bir hata oluştu: {e}"),
+                LogType::ERROR,
+            );
+            return;
         }
+    };
+    if config.is_some() {
+        log(
+            format!("Yapılandırma {} dosyasından yüklendi.", args.config),
+            LogType::INFO,
+        );
     }
-}
-
-impl User {
-    fn new(u: (&String, &String, &String), uuid: &String, banned: bool) -> Self {
-        let mut bytes = String::new();
-        let ip = u.2;
-        to_hex(&ip, &mut bytes);
-        Self {
-            username: u.0.to_string(),
-            pem: u.1.to_string(),
-            uuid: uuid.to_string(),
-            banned,
-            addr: ip.to_string(),
+    let settings = match settings(&args, config.as_ref()) {
+        Ok(settings) => settings,
+        Err(e) => {
+            log(
+                format!("Yapılandırma yüklenirken bir hata oluştu: {e}"),
+                LogType::ERROR,
+            );
+            return;
         }
-    }
-}
-
-impl<'a> Writer<'a> {
-    pub fn new(addr: &'a SocketAddr, writer: &'a mut WriteHalf<'a>) -> Self {
-        Self { addr, writer }
-    }
-
-    pub async fn write(&mut self, text: &str) {
-        if let Err(e) = self.writer.write_all(format!("{}", text).as_bytes()).await {
+    };
+    log(
+        format!(
+            "Sunucu {} adresinde başlatılıyor...",
+            binds(&args).join(", ")
+        ),
+        LogType::INFO,
+    );
+    let mut acceptor = match tls_acceptor(&args) {
+        Ok(acceptor) => acceptor,
+        Err(e) => {
             log(
-                format!(
-                    "{} makinesine yanıt gönderilirken bir hata oluştu: {}",
-                    self.addr, e
-                ),
+                format!("TLS yapılandırması yüklenirken bir hata oluştu: {e}"),
                 LogType::ERROR,
             );
+            return;
         }
-        self.writer.flush().await.unwrap();
-    }
-}
-
-#[tokio::main]
-async fn main() {
-    if fs::exists(SESSION_FOLDER_PATH).unwrap() {
-        fs::remove_dir_all(SESSION_FOLDER_PATH).unwrap();
-    }
-    clear();
-    let args = Args::parse();
-    let addr = format!("{}:{}", args.host, args.port);
+    };
+    let history = match History::open(&args.history_file, args.history_size) {
+        Ok(history) => history,
+        Err(e) => {
+            log(
+                format!("Mesaj geçmişi yüklenirken bir hata oluştu: {e}"),
+                LogType::ERROR,
+            );
+            return;
+        }
+    };
+    let moderation = match Moderation::open(&args.bans_file) {
+        Ok(moderation) => moderation,
+        Err(e) => {
+            log(
+                format!("Yasak listesi yüklenirken bir hata oluştu: {e}"),
+                LogType::ERROR,
+            );
+            return;
+        }
+    };
     log(
-        format!("Sunucu {addr} adresinde başlatılıyor..."),
+        format!(
+            "Yasak listesinde {} geçerli yasak bulundu.",
+            moderation.count()
+        ),
         LogType::INFO,
     );
-    let listener = TcpListener::bind(&addr).await;
-    if let Err(e) = listener {
-        log(format!("RustIRC ana makine üzerinde bir sunucu oluşturmaya çalışırken bir hata oluştu: {e}"), LogType::ERROR);
-        return;
-    }
-    let listener = listener.unwrap();
     log(
-        format!("{addr} adresinde bir TCP sunucusu oluşturuldu. Bağlantılar için hazır."),
-        LogType::OK,
+        format!("{} operatör tanımı yüklendi.", settings.operators.count()),
+        LogType::INFO,
     );
-    let (tx, _) = broadcast::channel::<String>(10);
-    loop {
-        let conn = listener.accept().await;
-        if let Err(e) = conn {
+    let location = args
+        .session_location
+        .clone()
+        .unwrap_or_else(|| match args.session_store {
+            Backend::Redis => session::REDIS_URL.to_string(),
+            _ => session::SESSION_FOLDER_PATH.to_string(),
+        });
+    let store = match session::open(args.session_store, &location).await {
+        Ok(store) => store,
+        Err(e) => {
             log(
-                format!(
-                    "Dış istemciden gelen bağlantı isteği kabul edilirken bir hata oluştu: {e}"
-                ),
+                format!("Oturum deposu açılırken bir hata oluştu: {e}"),
                 LogType::ERROR,
             );
             return;
         }
-        let (mut socket, addr) = conn.unwrap();
-        log(format!("{addr} ile ana makine arasında bir bağlantı oluşturuldu. İstemci tarafından başlangıç bayrağı bekleniyor."), LogType::STATUS);
-        let identify_message = Identify {
-            username: String::from("Provide your username"),
-            pem: String::from("Provide the PEM for use in the handshake"),
+    };
+    match store.list().await {
+        Ok(users) => log(
+            format!(
+                "Oturum deposunda {} kayıtlı kullanıcı bulundu.",
+                users.len()
+            ),
+            LogType::INFO,
+        ),
+        Err(e) => log(
+            format!("Oturum deposundaki kullanıcılar okunamadı: {e}"),
+            LogType::WARN,
+        ),
+    }
+    let mut listeners = Vec::new();
+    if let Err(e) = rebind(&mut listeners, &binds(&args), acceptor.is_some()).await {
+        log(format!("RustIRC ana makine üzerinde bir sunucu oluşturmaya çalışırken bir hata oluştu: {e}"), LogType::ERROR);
+        return;
+    }
+    let mut server = Server::new(args.queue_size, args.overflow, history, store, moderation);
+    server.name = args.name.clone();
+    server.reconfigure(settings);
+    let server = Arc::new(server);
+    let cluster = match &args.cluster {
+        Some(url) => match cluster::join(url, Arc::clone(&server)).await {
+            Ok(cluster) => {
+                log(
+                    format!(
+                        "{url} üzerinden kümeye {} kimliğiyle katılındı.",
+                        cluster.node
+                    ),
+                    LogType::OK,
+                );
+                Some(cluster)
+            }
+            Err(e) => {
+                log(
+                    format!("Kümeye katılırken bir hata oluştu: {e}"),
+                    LogType::ERROR,
+                );
+                return;
+            }
+        },
+        None => None,
+    };
+    let tracker = TaskTracker::new();
+    match &args.link_secret {
+        Some(secret) => {
+            for target in &args.links {
+                tracker.spawn(link::connect(
+                    target.clone(),
+                    Arc::clone(&server),
+                    secret.clone(),
+                ));
+            }
+        }
+        None if !args.links.is_empty() => log(
+            String::from(
+                "Sunucu bağlantıları için --link-secret belirtilmedi, bağlantı kurulmayacak.",
+            ),
+            LogType::WARN,
+        ),
+        None => {}
+    }
+    let shutdown = util::signal::shutdown();
+    tokio::pin!(shutdown);
+    let mut hangups = util::signal::hangups();
+    let mut backoff = ACCEPT_BACKOFF;
+    loop {
+        let conn = tokio::select! {
+            conn = accept(&listeners) => conn,
+            _ = hangups.recv() => {
+                reload(&matches, &mut args, &mut listeners, &mut acceptor, &server).await;
+                continue;
+            }
+            _ = &mut shutdown => break,
+            _ = server.stopping() => break,
         };
-        let identify_message = serde_json::to_string(&identify_message).unwrap();
-        let tx = tx.clone();
-        let mut rx = tx.subscribe();
-        tokio::spawn(async move {
-            let (reader, mut writer) = socket.split();
-            let mut reader = BufReader::new(reader);
-            let mut socket_writer = Writer::new(&addr, &mut writer);
-
-            socket_writer
-            .write(&format!(
-                "MSG::Hello, stranger! You have a message from the server you tried to connect to. Please identify yourself and send your message with the protocol start flag 'FN' in order to receive your handshake ID.\r\nExample: FN<>::Identify {}\r\n",
-                identify_message
-            ))
-            .await;
+        let (socket, addr) = match conn {
+            Ok(conn) => {
+                backoff = ACCEPT_BACKOFF;
+                conn
+            }
+            Err(e) => {
+                log(
+                    format!(
+                        "Dış istemciden gelen bağlantı isteği kabul edilirken bir hata oluştu, {} ms sonra yeniden denenecek: {e}",
+                        backoff.as_millis()
+                    ),
+                    LogType::ERROR,
+                );
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_ACCEPT_BACKOFF);
+                continue;
+            }
+        };
+        let permit = match server.admission.admit(addr.ip()) {
+            Ok(permit) => permit,
+            Err(refusal) => {
+                log(
+                    format!(
+                        "{addr} bağlantısı reddedildi ({} açık bağlantı): {refusal}",
+                        server.admission.open()
+                    ),
+                    LogType::WARN,
+                );
+                let acceptor = acceptor.clone();
+                let mode = args.mode;
+                tracker.spawn(async move {
+                    let refusing = refuse(socket, addr, acceptor, mode, refusal);
+                    let _ = tokio::time::timeout(REFUSAL_TIMEOUT, refusing).await;
+                });
+                continue;
+            }
+        };
+        log(format!("{addr} ile ana makine arasında bir bağlantı oluşturuldu. İstemci tarafından başlangıç bayrağı bekleniyor."), LogType::STATUS);
+        let server = Arc::clone(&server);
+        let acceptor = acceptor.clone();
+        let secret = args.link_secret.clone();
+        let mode = args.mode;
+        tracker.spawn(async move {
+            let _permit = permit;
+            let handshake = server.admission.limits().handshake;
+            let Some((mut socket, binding)) = secure(socket, addr, acceptor, handshake).await
+            else {
+                return;
+            };
+            match front(&mut socket, mode).await {
+                Front::Native => native::handle(socket, addr, server).await,
+                Front::Irc => irc::handle(socket, addr, server).await,
+                Front::Link => {
+                    link::handle(socket, addr.to_string(), server, secret, binding).await
+                }
+            }
+        });
+    }
 
-            let mut lines = String::new();
-            loop {
-                lines.clear();
-                tokio::select! {
-                    result = reader.read_line(&mut lines) => {
-                        match result {
-                            Ok(0) => {
-                                break;
-                            },
-                            Ok(_) => {
-                                let identifiers: HashSet<&str> =
-                                ["FN", "FN_RESET", "FN_FIN"].iter().cloned().collect();
-                            if let Some((mut header, body)) = lines.split_once("::") {
-                                if let Some(header_) = header.split("<>").nth(0) {
-                                    header = header_;
-                                } else {
-                                    log(
-                                        format!(
-                                            "{addr} tarafından gönderilen mesaj bir tanımlayıcı içermiyor."
-                                        ),
-                                        LogType::STATUS,
-                                    );
-                                    socket_writer.write("ERR::The message doesn't contain an identifier. Your connection will be lost.").await;
+    drop(listeners);
+    log(
+        format!(
+            "Kapatma sinyali alındı. Yeni bağlantılar reddediliyor, {} bağlantıya kapanış bildirimi gönderiliyor..",
+            tracker.len()
+        ),
+        LogType::WARN,
+    );
+    let sessions = server.connected();
+    server.shutdown();
+    tracker.close();
+    if tokio::time::timeout(DRAIN_TIMEOUT, tracker.wait())
+        .await
+        .is_err()
+    {
+        log(
+            format!(
+                "{} bağlantı {} saniye içinde kapanmadı, bekleyen mesajları bırakılıyor.",
+                tracker.len(),
+                DRAIN_TIMEOUT.as_secs()
+            ),
+            LogType::WARN,
+        );
+    }
+    for (uuid, username) in &sessions {
+        if let Err(e) = session::flush_session(server.store(), uuid, username).await {
+            log(
+                format!("{username} için oturum dosyası kaydedilirken bir hata oluştu: {e}"),
+                LogType::ERROR,
+            );
+        }
+    }
+    if let Some(cluster) = &cluster {
+        for (uuid, _) in server.connected() {
+            server.unregister(&uuid, QuitReason::Closed, SHUTDOWN_MESSAGE);
+        }
+        if tokio::time::timeout(DRAIN_TIMEOUT, cluster.flush())
+            .await
+            .is_err()
+        {
+            log(
+                String::from("Kümeye gönderilecek olaylar zamanında iletilemedi."),
+                LogType::WARN,
+            );
+        }
+    }
+    log(
+        format!("Sunucu kapatıldı. {} oturum kaydedildi.", sessions.len()),
+        LogType::OK,
+    );
+}
 
-                                }
-                                if identifiers.contains(header) {
-                                    let identifier = body.split_whitespace().next().unwrap_or_default();
-                                    let data = &body[identifier.len()..].trim();
+/// Completes the TLS handshake when the listener has one, along with the
+/// channel binding of the session.
+async fn secure(
+    socket: TcpStream,
+    addr: SocketAddr,
+    acceptor: Option<TlsAcceptor>,
+    within: Duration,
+) -> Option<(tls::Stream, Option<String>)> {
+    let Some(acceptor) = acceptor else {
+        return Some((BufReader::new(Box::new(socket)), None));
+    };
+    match tokio::time::timeout(within, acceptor.accept(socket)).await {
+        Ok(Ok(stream)) => {
+            let binding = tls::binding(stream.get_ref().1);
+            Some((BufReader::new(Box::new(stream)), binding))
+        }
+        Ok(Err(e)) => {
+            log(
+                format!("{addr} ile TLS el sıkışması başarısız oldu: {e}"),
+                LogType::WARN,
+            );
+            None
+        }
+        Err(_) => {
+            log(
+                format!("{addr} ile TLS el sıkışması zaman aşımına uğradı."),
+                LogType::WARN,
+            );
+            None
+        }
+    }
+}
 
-                                    match identifier {
-                                        "Identify" => match serde_json::from_str::<Identify>(data.trim()) {
-                                            Ok(identify) => {
-                                                let Identify { username, pem } = &identify;
-                                                log(
-                                                    format!(
-                                                        r#"{addr} tarafından gönderilen tanımlayıcı çözüldü: "{username}" isimli oturum dosyası oluşturuluyor.."#
-                                                    ),
-                                                    LogType::STATUS,
-                                                );
-                                                socket_writer.write("\r\nOK::Connection verified. Your session is being prepared. Please wait for an ACK response before sending any messages.").await;
-                                                match create_session((&username, &pem, &addr.to_string())).await
-                                                {
-                                                    Ok(user) => {
-                                                        let f = format!("OK::Connection Established. Your user profile has been created and you are now ready for chat! Use your user id to send a message.\r\n");
+async fn front(socket: &mut tls::Stream, mode: Mode) -> Front {
+    match mode {
+        Mode::Auto => irc::detect(socket).await,
+        Mode::Native => Front::Native,
+        Mode::Irc => Front::Irc,
+    }
+}
 
-                                                        socket_writer.write(f.as_str()).await;
-                                                        socket_writer.write(format!("UUID::{}\r\n", user.uuid).as_str()).await;
-                                                        tx.send(format!("JOIN::{username} joined just now.")).unwrap();
-                                                    }
-                                                    Err(e) => {
-                                                        log(format!("{username} için oturum dosyası oluşturulurken bir hata oluştu: {e}"), LogType::ERROR);
-                                                        socket_writer.write("ERR::The session creation process fails. The link will be terminated.").await;
-                                                        break;
-                                                    }
-                                                }
-                                            }
-                                            Err(e) => {
-                                                log(
-                                                    format!("{addr} tarafından gönderilen tanımlayıcı çözülemedi: {}. Bağlantı sonlandırılıyor..", e),
-                                                    LogType::STATUS,
-                                                );
-                                                socket_writer.write("ERR::The data is broken or unsupported.").await;
+/// Tells a socket the admission limits turned away why, in whatever it
+/// speaks, before closing it.
+async fn refuse(
+    socket: TcpStream,
+    addr: SocketAddr,
+    acceptor: Option<TlsAcceptor>,
+    mode: Mode,
+    refusal: Refusal,
+) {
+    let Some((mut socket, _)) = secure(socket, addr, acceptor, REFUSAL_TIMEOUT).await else {
+        return;
+    };
+    let reason = refusal.to_string();
+    match front(&mut socket, mode).await {
+        Front::Native => native::refuse(socket, &reason).await,
+        Front::Irc | Front::Link => irc::refuse(socket, addr, &reason).await,
+    }
+}
 
-                                            }
-                                        },
-                                        "Message" =>  match serde_json::from_str::<Message>(data.trim()){
-                                           Ok(message) => {
-                                            let u = message.uuid;
-                                            let m = message.content;
-                                            tx.send(format!("{u}: {m}")).unwrap();
-                                           }
-                                           Err(e) => {
-                                            log(format!("{addr} tarafından gönderilen mesaj çözülemedi: {}. Bağlantı sonlandırılıyor..", e),
-                                            LogType::STATUS);
-                                            socket_writer.write("ERR::The data is broken or unsupported.").await;
-                                           }
-                                        },
-                                        _ => {
-                                            log(
-                                            format!("{addr} tarafından gönderilen tanımlayıcı {header} desteklenmiyor. Bağlantı sonlandırıldı.", header = header),
-                                            LogType::STATUS,
-                                        );
-                                            socket_writer
-                                            .write("ERR::Your identifier is not supported.")
-                                            .await;
-                                        }
-                                    }
-                                } else {
-                                    log(format!("{addr} tarafından gönderilen tanımlayıcı bozuk veya desteklenmiyor. Bağlantı sonlandırıldı."), LogType::STATUS);
-                                    socket_writer
-                                    .write("ERR::Your ACK is not supported or broken.")
-                                    .await;
-                                }
-                            } else {
-                                log(format!("{addr} tarafından gönderilen tanımlayıcı bozuk veya desteklenmiyor. Bağlantı sonlandırıldı."), LogType::STATUS);
-                                socket_writer
-                                .write(
-                                    "ERR::Your ACK is not supported or broken.",
-                                )
-                                .await;
-                            }
-                            },
-                            Err(e) => {
-                              log(format!("Bir hata oluştu: {e}"), LogType::ERROR);
-                              break;
-                            }
-                        }
+/// Works out the arguments in effect: the command line first, then the
+/// config file, then the defaults.
+fn settle(matches: &ArgMatches) -> Result<(Args, Option<Config>), String> {
+    let mut args = Args::from_arg_matches(matches).map_err(|e| e.to_string())?;
+    let config = Config::load(&args.config)?;
+    match &config {
+        Some(config) => configure(&mut args, matches, config)?,
+        None if given(matches, "config") => {
+            return Err(format!("{}: file not found", args.config));
+        }
+        None => {}
+    }
+    validate(&args)?;
+    Ok((args, config))
+}
 
+fn given(matches: &ArgMatches, id: &str) -> bool {
+    matches.value_source(id) == Some(ValueSource::CommandLine)
+}
 
-                    }
-                    result = rx.recv() => {
-                    let msg = result.unwrap();
-                    println!("{msg}");
-                    socket_writer.write(format!("UMSG::{msg}\r\n").as_str()).await;
-                    }
+/// Takes from the config file whatever the command line left out.
+fn configure(args: &mut Args, matches: &ArgMatches, config: &Config) -> Result<(), String> {
+    macro_rules! set {
+        ($field:ident, $value:expr) => {
+            if !given(matches, stringify!($field)) {
+                if let Some(value) = $value {
+                    args.$field = value.into();
                 }
             }
-        });
+        };
+    }
+    if !["bind", "host", "port"].iter().any(|id| given(matches, id)) {
+        let bind = config.list("server", "bind");
+        if !bind.is_empty() {
+            args.bind = bind;
+        }
     }
+    set!(name, config.get::<String>("server", "name")?);
+    set!(mode, config.choice::<Mode>("server", "mode")?);
+    set!(motd, config.get::<String>("server", "motd")?);
+    set!(
+        max_line_length,
+        config.get::<usize>("limits", "max-line-length")?
+    );
+    set!(
+        max_body_length,
+        config.get::<usize>("limits", "max-body-length")?
+    );
+    set!(
+        max_connections,
+        config.get::<usize>("limits", "max-connections")?
+    );
+    set!(max_per_ip, config.get::<usize>("limits", "max-per-ip")?);
+    set!(
+        handshake_timeout,
+        config.get::<u64>("limits", "handshake-timeout")?
+    );
+    set!(queue_size, config.get::<usize>("limits", "queue-size")?);
+    set!(overflow, config.choice::<Overflow>("limits", "overflow")?);
+    set!(flood_burst, config.get::<f64>("limits", "flood-burst")?);
+    set!(flood_rate, config.get::<f64>("limits", "flood-rate")?);
+    set!(
+        flood_ip_burst,
+        config.get::<f64>("limits", "flood-ip-burst")?
+    );
+    set!(flood_ip_rate, config.get::<f64>("limits", "flood-ip-rate")?);
+    set!(flood_strikes, config.get::<u32>("limits", "flood-strikes")?);
+    set!(resume_grace, config.get::<u64>("limits", "resume-grace")?);
+    set!(nick_grace, config.get::<u64>("limits", "nick-grace")?);
+    set!(history_file, config.get::<String>("history", "file")?);
+    set!(history_size, config.get::<usize>("history", "size")?);
+    set!(tls, config.get::<bool>("tls", "enabled")?);
+    set!(tls_cert, config.get::<String>("tls", "cert")?);
+    set!(tls_key, config.get::<String>("tls", "key")?);
+    Ok(())
 }
 
-fn clear() {
-    print!("\x1B[2J\x1B[1;1H");
+/// Rejects settings the server could not run with.
+fn validate(args: &Args) -> Result<(), String> {
+    for addr in binds(args) {
+        addr.to_socket_addrs()
+            .map_err(|e| format!("bind address {addr}: {e}"))?;
+    }
+    if args.max_line_length == 0 || args.max_body_length == 0 {
+        return Err(String::from(
+            "max-line-length and max-body-length must be above 0",
+        ));
+    }
+    if args.max_body_length > args.max_line_length {
+        return Err(String::from(
+            "max-body-length cannot be larger than max-line-length",
+        ));
+    }
+    let rates = [
+        args.flood_burst,
+        args.flood_rate,
+        args.flood_ip_burst,
+        args.flood_ip_rate,
+    ];
+    if rates.iter().any(|rate| !rate.is_finite() || *rate <= 0.0) {
+        return Err(String::from("flood bursts and rates must be above 0"));
+    }
+    if args.queue_size == 0 || args.handshake_timeout == 0 {
+        return Err(String::from(
+            "queue-size and handshake-timeout must be above 0",
+        ));
+    }
+    Ok(())
+}
+
+fn binds(args: &Args) -> Vec<String> {
+    if args.bind.is_empty() {
+        vec![format!("{}:{}", args.host, args.port)]
+    } else {
+        args.bind.clone()
+    }
+}
+
+/// Collects what the server can pick up again while running.
+fn settings(args: &Args, config: Option<&Config>) -> Result<Settings, String> {
+    let operators = Operators::load(
+        &args.env_file,
+        config.map(Config::operators).unwrap_or_default(),
+    )
+    .map_err(|e| format!("operators: {e}"))?;
+    let motd = match &args.motd {
+        Some(path) => std::fs::read_to_string(path)
+            .map_err(|e| format!("{path}: {e}"))?
+            .lines()
+            .map(|line| line.trim_end().to_string())
+            .collect(),
+        None => Vec::new(),
+    };
+    Ok(Settings {
+        operators,
+        limits: FrameLimits {
+            line: args.max_line_length,
+            body: args.max_body_length,
+        },
+        flood: Limits {
+            connection: Rate {
+                burst: args.flood_burst,
+                refill: args.flood_rate,
+            },
+            address: Rate {
+                burst: args.flood_ip_burst,
+                refill: args.flood_ip_rate,
+            },
+            strikes: args.flood_strikes,
+        },
+        admission: admission::Limits {
+            connections: args.max_connections,
+            per_address: args.max_per_ip,
+            handshake: Duration::from_secs(args.handshake_timeout),
+        },
+        replay: args.history_size,
+        resume_grace: Duration::from_secs(args.resume_grace),
+        nick_grace: Duration::from_secs(args.nick_grace),
+        motd,
+    })
+}
+
+fn tls_acceptor(args: &Args) -> Result<Option<TlsAcceptor>, String> {
+    match args.tls {
+        true => tls::acceptor(&args.tls_cert, &args.tls_key, &args.host).map(Some),
+        false => Ok(None),
+    }
 }
 
-async fn create_session(u: (&String, &String, &String)) -> Result<User, String> {
-    if !fs::exists(SESSION_FOLDER_PATH).unwrap() {
-        fs::create_dir(SESSION_FOLDER_PATH).unwrap();
+/// Opens the addresses not listened on yet and closes the ones no longer
+/// wanted. Nothing changes unless every new address could be opened.
+async fn rebind(
+    listeners: &mut Vec<(String, TcpListener)>,
+    addrs: &[String],
+    tls: bool,
+) -> Result<(), String> {
+    let mut opened = Vec::new();
+    for addr in addrs {
+        if listeners.iter().any(|(bound, _)| bound == addr) {
+            continue;
+        }
+        let listener = TcpListener::bind(addr)
+            .await
+            .map_err(|e| format!("{addr}: {e}"))?;
+        opened.push((addr.clone(), listener));
     }
-    let uuid = generate_session_key();
-    let user = User::new(u, &uuid, false);
-    let user_json = serde_json::to_string(&user).unwrap();
-    let mut user_bytes = String::new();
-    to_hex(&user_json, &mut user_bytes);
-    let create_user_dat = fs::write(format!("{SESSION_FOLDER_PATH}/{uuid}.dat"), user_bytes);
-    if let Err(e) = create_user_dat {
-        return Err(e.to_string());
+    listeners.retain(|(bound, _)| {
+        let keep = addrs.contains(bound);
+        if !keep {
+            log(
+                format!("{bound} adresindeki sunucu kapatıldı."),
+                LogType::WARN,
+            );
+        }
+        keep
+    });
+    for (addr, _) in &opened {
+        log(
+            format!(
+                "{addr} adresinde bir {} sunucusu oluşturuldu. Bağlantılar için hazır.",
+                if tls { "TLS" } else { "TCP" }
+            ),
+            LogType::OK,
+        );
     }
+    listeners.extend(opened);
+    Ok(())
+}
 
-    Ok(user)
+/// Waits for a connection on any of the listeners.
+async fn accept(listeners: &[(String, TcpListener)]) -> std::io::Result<(TcpStream, SocketAddr)> {
+    let accepts = listeners
+        .iter()
+        .map(|(_, listener)| Box::pin(listener.accept()));
+    futures::future::select_all(accepts).await.0
+}
+
+/// Reads the config file again and applies it without touching open
+/// connections. The name, the history file, the stores, the cluster and
+/// server links only change on a restart; the history size is applied to the
+/// messages already kept. If anything is wrong, the old settings stay.
+async fn reload(
+    matches: &ArgMatches,
+    args: &mut Args,
+    listeners: &mut Vec<(String, TcpListener)>,
+    acceptor: &mut Option<TlsAcceptor>,
+    server: &Server,
+) {
+    log(
+        String::from("SIGHUP alındı, yapılandırma yeniden yükleniyor.."),
+        LogType::INFO,
+    );
+    let reloaded = async {
+        let (fresh, config) = settle(matches)?;
+        let settings = settings(&fresh, config.as_ref())?;
+        let tls = tls_acceptor(&fresh)?;
+        rebind(listeners, &binds(&fresh), tls.is_some()).await?;
+        Ok::<_, String>((fresh, settings, tls))
+    };
+    match reloaded.await {
+        Ok((fresh, settings, tls)) => {
+            if fresh.history_file != args.history_file {
+                log(
+                    format!(
+                        "Geçmiş dosyası ({}) ancak sunucu yeniden başlatıldığında değişir.",
+                        fresh.history_file
+                    ),
+                    LogType::WARN,
+                );
+            }
+            server.reconfigure(settings);
+            *acceptor = tls;
+            *args = fresh;
+            log(String::from("Yapılandırma yeniden yüklendi."), LogType::OK);
+        }
+        Err(e) => log(
+            format!("Yapılandırma yeniden yüklenemedi, önceki ayarlar geçerli: {e}"),
+            LogType::ERROR,
+        ),
+    }
+}
+
+fn clear() {
+    print!("\x1B[2J\x1B[1;1H");
 }
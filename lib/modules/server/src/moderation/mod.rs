@@ -0,0 +1,301 @@
+use chrono::{DateTime, Local, SecondsFormat, TimeDelta};
+use protocol::{BanKind, Sanction};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+
+pub const BANS_FILE_PATH: &str = "moderation/bans.json";
+
+/// A ban as it is stored and shared between nodes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Ban {
+    pub kind: BanKind,
+    pub mask: String,
+    pub by: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub reason: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until: Option<String>,
+}
+
+/// Whatever a connection can be banned by. Remote users only carry their
+/// UUID and nickname; their own node knows the rest.
+pub struct Subject<'a> {
+    pub uuid: &'a str,
+    pub nick: &'a str,
+    pub ip: &'a str,
+    pub key: &'a str,
+}
+
+impl Ban {
+    pub fn new(kind: BanKind, mask: &str, by: &str, reason: &str, until: Option<String>) -> Self {
+        Self {
+            kind,
+            mask: match kind {
+                BanKind::Uuid => mask.to_string(),
+                _ => mask.to_lowercase(),
+            },
+            by: by.to_string(),
+            reason: reason.to_string(),
+            until,
+        }
+    }
+
+    fn expired(&self) -> bool {
+        expired(self.until.as_deref())
+    }
+
+    pub fn matches(&self, subject: &Subject) -> bool {
+        match self.kind {
+            BanKind::Uuid => !subject.uuid.is_empty() && self.mask == subject.uuid,
+            BanKind::Nick => glob(&self.mask, &subject.nick.to_lowercase()),
+            BanKind::Ip => !subject.ip.is_empty() && glob(&self.mask, subject.ip),
+            BanKind::Key => !subject.key.is_empty() && self.mask == subject.key,
+        }
+    }
+
+    pub fn sanction(&self) -> Sanction {
+        Sanction {
+            by: self.by.clone(),
+            reason: self.reason.clone(),
+            ban: true,
+            until: self.until.clone(),
+        }
+    }
+}
+
+/// The RFC 3339 time `duration` seconds from now, `None` for no limit. Fails
+/// when that lies beyond what a date can hold.
+pub fn expiry(duration: Option<u64>) -> Result<Option<String>, String> {
+    let Some(seconds) = duration else {
+        return Ok(None);
+    };
+    i64::try_from(seconds)
+        .ok()
+        .and_then(TimeDelta::try_seconds)
+        .and_then(|delta| Local::now().checked_add_signed(delta))
+        .map(|until| Some(until.to_rfc3339_opts(SecondsFormat::Secs, true)))
+        .ok_or_else(|| String::from("The duration is too long."))
+}
+
+fn expired(until: Option<&str>) -> bool {
+    until
+        .and_then(|until| DateTime::parse_from_rfc3339(until).ok())
+        .is_some_and(|until| until < Local::now())
+}
+
+/// Matches `*` against any run of characters and `?` against exactly one.
+fn glob(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Bans, kept in a JSON file so they survive restarts, and mutes, which only
+/// last as long as the server runs.
+pub struct Moderation {
+    path: String,
+    bans: Mutex<Vec<Ban>>,
+    mutes: Mutex<HashMap<String, Option<String>>>,
+}
+
+impl Moderation {
+    /// Loads the bans earlier runs left behind, dropping the expired ones.
+    pub fn open(path: &str) -> Result<Self, String> {
+        if let Some(parent) = Path::new(path).parent() {
+            fs::create_dir_all(parent).map_err(|e| format!("{path}: {e}"))?;
+        }
+        let mut bans: Vec<Ban> = match fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content).map_err(|e| format!("{path}: {e}"))?,
+            Err(_) => Vec::new(),
+        };
+        bans.retain(|ban| !ban.expired());
+        Ok(Self {
+            path: path.to_string(),
+            bans: Mutex::new(bans),
+            mutes: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// How many bans are in force.
+    pub fn count(&self) -> usize {
+        let bans = self.bans.lock().unwrap();
+        bans.iter().filter(|ban| !ban.expired()).count()
+    }
+
+    /// Adds the ban, replacing any earlier one on the same mask.
+    pub fn ban(&self, ban: Ban) -> Result<(), String> {
+        let mut bans = self.bans.lock().unwrap();
+        bans.retain(|old| !old.expired() && (old.kind != ban.kind || old.mask != ban.mask));
+        bans.push(ban);
+        self.save(&bans)
+    }
+
+    /// Lifts the ban on the mask. Returns `false` if there was none.
+    pub fn unban(&self, kind: BanKind, mask: &str) -> Result<bool, String> {
+        let mut bans = self.bans.lock().unwrap();
+        let count = bans.len();
+        bans.retain(|ban| ban.kind != kind || !ban.mask.eq_ignore_ascii_case(mask));
+        if bans.len() == count {
+            return Ok(false);
+        }
+        self.save(&bans)?;
+        Ok(true)
+    }
+
+    /// The first ban still in force that matches the subject.
+    pub fn find(&self, subject: &Subject) -> Option<Ban> {
+        let bans = self.bans.lock().unwrap();
+        bans.iter()
+            .find(|ban| !ban.expired() && ban.matches(subject))
+            .cloned()
+    }
+
+    fn save(&self, bans: &[Ban]) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(bans).map_err(|e| e.to_string())?;
+        fs::write(&self.path, json).map_err(|e| format!("{}: {e}", self.path))
+    }
+
+    pub fn mute(&self, uuid: &str, until: Option<String>) {
+        self.mutes.lock().unwrap().insert(uuid.to_string(), until);
+    }
+
+    pub fn unmute(&self, uuid: &str) -> bool {
+        self.mutes.lock().unwrap().remove(uuid).is_some()
+    }
+
+    pub fn muted(&self, uuid: &str) -> bool {
+        let mut mutes = self.mutes.lock().unwrap();
+        match mutes.get(uuid) {
+            Some(until) if expired(until.as_deref()) => {
+                mutes.remove(uuid);
+                false
+            }
+            Some(_) => true,
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_matches_stars_and_question_marks() {
+        assert!(glob("alice", "alice"));
+        assert!(!glob("alice", "alicex"));
+        assert!(!glob("alice", "alic"));
+        assert!(glob("*", ""));
+        assert!(glob("*", "anything"));
+        assert!(glob("al*", "al"));
+        assert!(glob("al*", "alice"));
+        assert!(glob("*ce", "alice"));
+        assert!(glob("a*c*e", "abcde"));
+        assert!(!glob("a*c*e", "abcdef"));
+        assert!(glob("a?c", "abc"));
+        assert!(!glob("a?c", "ac"));
+        assert!(glob("*a*a*", "banana"));
+        assert!(glob("10.0.*.?", "10.0.12.7"));
+        assert!(!glob("10.0.*.?", "10.0.12.70"));
+        assert!(glob("doğ?n", "doğan"));
+    }
+
+    #[test]
+    fn bans_match_by_their_kind() {
+        let subject = Subject {
+            uuid: "u-1",
+            nick: "Alice",
+            ip: "10.0.0.1",
+            key: "fingerprint",
+        };
+        let ban = |kind, mask| Ban::new(kind, mask, "oper", "", None);
+        assert!(ban(BanKind::Nick, "ALI*").matches(&subject));
+        assert!(!ban(BanKind::Nick, "bob").matches(&subject));
+        assert!(ban(BanKind::Ip, "10.0.0.*").matches(&subject));
+        assert!(ban(BanKind::Uuid, "u-1").matches(&subject));
+        assert!(!ban(BanKind::Uuid, "U-1").matches(&subject));
+        assert!(ban(BanKind::Key, "fingerprint").matches(&subject));
+        let remote = Subject {
+            ip: "",
+            key: "",
+            ..subject
+        };
+        assert!(!ban(BanKind::Ip, "*").matches(&remote));
+        assert!(!ban(BanKind::Key, "").matches(&remote));
+    }
+
+    #[test]
+    fn expired_bans_are_not_found() {
+        let folder = std::env::temp_dir().join(format!("rustirc-{}", uuid::Uuid::new_v4()));
+        let path = folder.join("bans.json").to_string_lossy().to_string();
+        let moderation = Moderation::open(&path).unwrap();
+        let past = Some(String::from("2000-01-01T00:00:00Z"));
+        moderation
+            .ban(Ban::new(BanKind::Nick, "old", "oper", "", past))
+            .unwrap();
+        moderation
+            .ban(Ban::new(
+                BanKind::Nick,
+                "new",
+                "oper",
+                "",
+                expiry(Some(60)).unwrap(),
+            ))
+            .unwrap();
+        let subject = |nick| Subject {
+            uuid: "",
+            nick,
+            ip: "",
+            key: "",
+        };
+        assert!(moderation.find(&subject("old")).is_none());
+        assert!(moderation.find(&subject("new")).is_some());
+        assert_eq!(moderation.count(), 1);
+        assert_eq!(Moderation::open(&path).unwrap().count(), 1);
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn expiry_without_duration_is_permanent() {
+        assert_eq!(expiry(None), Ok(None));
+    }
+
+    #[test]
+    fn expiry_lies_ahead() {
+        let until = expiry(Some(60)).unwrap().unwrap();
+        assert!(!expired(Some(&until)));
+        assert!(DateTime::parse_from_rfc3339(&until).unwrap() > Local::now());
+    }
+
+    #[test]
+    fn expiry_rejects_durations_out_of_range() {
+        assert!(expiry(Some(u64::MAX)).is_err());
+        assert!(expiry(Some(i64::MAX as u64)).is_err());
+        assert!(expiry(Some(400_000 * 365 * 24 * 60 * 60)).is_err());
+    }
+}
@@ -0,0 +1,1509 @@
+use crate::account::{Account, GUEST_RETRY, MIN_PASSWORD_LENGTH};
+use crate::auth;
+use crate::auth::Challenge;
+use crate::flood::{Limiter, Verdict};
+use crate::moderation::{self, Subject};
+use crate::oper::Role;
+use crate::session::{create_session, find_session, mark_banned, renew_session, User};
+use crate::state::queue::{Delivery, Outbox, Packet};
+use crate::state::resume::Reclaim;
+use crate::state::{valid_channel, valid_nick, Server, SERVER_VERSION, SHUTDOWN_MESSAGE};
+use crate::tls::Stream;
+use crate::util::log::{log, LogType};
+use futures::{SinkExt, StreamExt};
+use protocol::{
+    Ban, BanKind, Frame, FrameCodec, Header, Hello, Identify, Kick, Login, Membership, Message,
+    Motd, Mute, Oper, PrivateMessage, PrivateRequest, ProtocolError, Quit, QuitReason, Register,
+    Sanction, Unban, Unmute, Verify, DEFAULT_CHANNEL,
+};
+use std::collections::HashSet;
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncWriteExt, ReadHalf, WriteHalf};
+use tokio::time::Instant;
+use tokio_util::codec::{FramedRead, FramedWrite};
+
+/// Features every connection gets, announced in the hello. `resume` is
+/// added when resuming is turned on.
+const CAPABILITIES: &[&str] = &["verify", "history", "away", "accounts", "moderation"];
+const INVALID_NICK: &str = "The nickname is not valid. It cannot be empty, longer than 32 characters, start with # or : or contain spaces, control characters or any of , ! @ * ?";
+
+struct Writer<'a> {
+    addr: &'a SocketAddr,
+    writer: FramedWrite<WriteHalf<Stream>, FrameCodec>,
+}
+
+impl<'a> Writer<'a> {
+    pub fn new(addr: &'a SocketAddr, writer: WriteHalf<Stream>) -> Self {
+        Self {
+            addr,
+            writer: FramedWrite::new(writer, FrameCodec::new()),
+        }
+    }
+
+    pub async fn write(&mut self, frame: Frame) {
+        if let Err(e) = self.writer.send(frame).await {
+            log(
+                format!(
+                    "{} makinesine yanıt gönderilirken bir hata oluştu: {}",
+                    self.addr, e
+                ),
+                LogType::ERROR,
+            );
+        }
+    }
+
+    pub async fn write_line(&mut self, line: Arc<str>) {
+        if let Err(e) = self.writer.send(line).await {
+            log(
+                format!(
+                    "{} makinesine yanıt gönderilirken bir hata oluştu: {}",
+                    self.addr, e
+                ),
+                LogType::ERROR,
+            );
+        }
+    }
+
+    /// Flushes whatever is still buffered and shuts the write half down.
+    pub async fn close(&mut self) {
+        let _ = SinkExt::<Frame>::close(&mut self.writer).await;
+    }
+}
+
+/// How a resume token played out.
+enum Restore {
+    /// The connection was taken care of: resumed, or closed for a ban.
+    Done,
+    /// The user was offline; register it again under its old profile.
+    Profile(User, String),
+    /// Carry on with a brand new session.
+    Fresh,
+}
+
+/// Where a native connection is in its lifecycle.
+///
+/// ```text
+/// AwaitingIdentify --Identify--> Verifying --Verify--> Established
+///        ^                           |                      |
+///        +--------FN_RESET-----------+----------------------+
+///
+/// any state --FN_FIN / Quit / fatal error--> Closing
+/// ```
+enum State {
+    AwaitingIdentify,
+    Verifying(Identify, Challenge),
+    Established { uuid: String, username: String },
+    Closing,
+}
+
+struct Connection {
+    addr: SocketAddr,
+    server: Arc<Server>,
+    state: State,
+    channels: HashSet<String>,
+    outbox: Outbox,
+    limiter: Limiter,
+    departure: (QuitReason, String),
+    /// When the connection is renamed unless it logs into the account on
+    /// its nickname.
+    nick_deadline: Option<Instant>,
+}
+
+impl Connection {
+    fn new(addr: SocketAddr, server: Arc<Server>) -> Self {
+        let outbox = server.outbox();
+        let limiter = Limiter::new(addr.ip(), &server.flood);
+        Self {
+            addr,
+            server,
+            state: State::AwaitingIdentify,
+            channels: HashSet::new(),
+            outbox,
+            limiter,
+            departure: (QuitReason::Closed, String::new()),
+            nick_deadline: None,
+        }
+    }
+
+    fn is_closing(&self) -> bool {
+        matches!(self.state, State::Closing)
+    }
+
+    fn is_established(&self) -> bool {
+        matches!(self.state, State::Established { .. })
+    }
+
+    fn session(&self) -> Option<(&str, &str)> {
+        match &self.state {
+            State::Established { uuid, username } => Some((uuid, username)),
+            _ => None,
+        }
+    }
+
+    /// Sends a final frame, leaves the session behind and moves to `Closing`;
+    /// the handler drops the link once the current iteration is over.
+    async fn close(&mut self, writer: &mut Writer<'_>, frame: Frame) {
+        writer.write(frame).await;
+        let (reason, message) = self.departure.clone();
+        self.reset(reason, &message);
+        self.state = State::Closing;
+    }
+
+    /// Drops the established session, if any, so the connection can identify
+    /// again from scratch.
+    fn reset(&mut self, reason: QuitReason, message: &str) {
+        if let Some((uuid, _)) = self.session() {
+            if self.server.attached(uuid, &self.outbox) {
+                self.server.unregister(uuid, reason, message);
+            }
+        }
+        self.channels.clear();
+        self.nick_deadline = None;
+        self.state = State::AwaitingIdentify;
+    }
+
+    /// Holds the connection to its flood limits before a request is
+    /// handled. Returns how long the request has to wait, or `None` once the
+    /// connection has been dropped for flooding.
+    async fn pace(&mut self, writer: &mut Writer<'_>) -> Option<Duration> {
+        let addr = self.addr;
+        match self.limiter.check(&self.server.flood) {
+            Verdict::Allow => Some(Duration::ZERO),
+            Verdict::Throttle { wait, warn } => {
+                if warn {
+                    log(
+                        format!("{addr} mesaj sınırını aştı, istekleri yavaşlatılıyor."),
+                        LogType::WARN,
+                    );
+                    writer
+                        .write(Frame::Err(String::from(
+                            "You are sending messages too fast. Slow down.",
+                        )))
+                        .await;
+                }
+                Some(wait)
+            }
+            Verdict::Disconnect => {
+                log(
+                    format!(
+                        "{addr} mesaj sınırını sürekli aştığı için bağlantısı sonlandırılıyor."
+                    ),
+                    LogType::WARN,
+                );
+                self.departure = (QuitReason::Closed, String::from("Excess flood"));
+                self.close(
+                    writer,
+                    Frame::Err(String::from(
+                        "You kept sending messages too fast. The link will be terminated.",
+                    )),
+                )
+                .await;
+                None
+            }
+        }
+    }
+
+    async fn on_line(&mut self, line: Result<Frame, ProtocolError>, writer: &mut Writer<'_>) {
+        match line {
+            Ok(frame) => self.on_frame(frame, writer).await,
+            Err(e) => self.on_error(e, writer).await,
+        }
+    }
+
+    async fn on_frame(&mut self, frame: Frame, writer: &mut Writer<'_>) {
+        let addr = self.addr;
+        let header = frame.header();
+        let quit = matches!(frame, Frame::Quit(..));
+        if header == Some(Header::FnReset) {
+            log(
+                format!("{addr} bağlantıyı sıfırladı, yeniden kimlik doğrulaması bekleniyor.."),
+                LogType::STATUS,
+            );
+            self.reset(QuitReason::Quit, "Re-identifying");
+        }
+
+        match frame {
+            Frame::Identify(_, identify) => self.on_identify(identify, writer).await,
+            Frame::Verify(_, verify) => self.on_verify(verify, writer).await,
+            Frame::Message(_, message) => self.on_message(message, writer).await,
+            Frame::JoinChannel(_, request) => self.on_join(&request.channel, writer).await,
+            Frame::PartChannel(_, request) => self.on_part(&request.channel, writer).await,
+            Frame::Private(_, request) => self.on_private(request, writer).await,
+            Frame::Names(_, request) => self.on_names(&request.channel, writer).await,
+            Frame::Away(_, away) => self.on_away(&away.message, writer).await,
+            Frame::Kick(_, kick) => self.on_kick(kick, writer).await,
+            Frame::Ban(_, ban) => self.on_ban(ban, writer).await,
+            Frame::Unban(_, unban) => self.on_unban(unban, writer).await,
+            Frame::Mute(_, mute) => self.on_mute(mute, writer).await,
+            Frame::Unmute(_, unmute) => self.on_unmute(unmute, writer).await,
+            Frame::Oper(_, oper) => self.on_oper(oper, writer).await,
+            Frame::Register(_, register) => self.on_register(register, writer).await,
+            Frame::Login(_, login) => self.on_login(login, writer).await,
+            Frame::Die(_, quit) => self.on_die(quit, writer).await,
+            Frame::Quit(_, quit) => self.departure = (QuitReason::Quit, quit.reason),
+            _ => {
+                log(
+                    format!("{addr} tarafından gönderilen çerçeve istemciler için tanımlı değil."),
+                    LogType::WARN,
+                );
+                writer
+                    .write(Frame::Err(String::from(
+                        "Your identifier is not supported.",
+                    )))
+                    .await;
+            }
+        }
+
+        if (quit || header == Some(Header::FnFin)) && !self.is_closing() {
+            log(format!("{addr} bağlantıyı sonlandırdı."), LogType::STATUS);
+            self.close(writer, Frame::Ok(String::from("Goodbye.")))
+                .await;
+        }
+    }
+
+    async fn on_error(&mut self, e: ProtocolError, writer: &mut Writer<'_>) {
+        let addr = self.addr;
+        if e.is_fatal() {
+            log(
+                format!("{addr} tarafından gönderilen mesaj çözülemedi, bağlantı sonlandırılıyor: {e:?}"),
+                LogType::WARN,
+            );
+            self.departure = (QuitReason::Closed, e.to_string());
+            self.close(
+                writer,
+                Frame::Err(format!("{e} The link will be terminated.")),
+            )
+            .await;
+        } else {
+            log(
+                format!("{addr} tarafından gönderilen mesaj çözülemedi: {e:?}"),
+                LogType::STATUS,
+            );
+            writer.write(Frame::Err(e.to_string())).await;
+        }
+    }
+
+    async fn on_identify(&mut self, identify: Identify, writer: &mut Writer<'_>) {
+        let addr = self.addr;
+        if !matches!(self.state, State::AwaitingIdentify) {
+            writer
+                .write(Frame::Err(String::from(
+                    "You have already identified. Send FN_RESET<>::Identify to start over.",
+                )))
+                .await;
+            return;
+        }
+        let username = &identify.username;
+        if !valid_nick(username) {
+            writer.write(Frame::Err(String::from(INVALID_NICK))).await;
+            return;
+        }
+        match Challenge::new(&identify.pem) {
+            Ok(challenge) => {
+                let subject = Subject {
+                    uuid: "",
+                    nick: username,
+                    ip: &addr.ip().to_string(),
+                    key: &challenge.fingerprint(),
+                };
+                if let Some(ban) = self.server.banned(&subject) {
+                    log(
+                        format!(
+                            r#"{addr} "{username}" olarak bağlanmaya çalıştı ancak {} yasağı bulunuyor. Bağlantı sonlandırılıyor.."#,
+                            ban.kind
+                        ),
+                        LogType::WARN,
+                    );
+                    self.close(writer, Frame::Kicked(ban.sanction())).await;
+                    return;
+                }
+                if identify.resume.is_empty() && self.server.taken(username, "") {
+                    writer
+                        .write(Frame::Err(format!(
+                            "The nickname {username} is already in use."
+                        )))
+                        .await;
+                    return;
+                }
+                log(
+                    format!(
+                        r#"{addr} tarafından gönderilen tanımlayıcı çözüldü: "{username}" için anahtar doğrulaması bekleniyor.."#
+                    ),
+                    LogType::STATUS,
+                );
+                writer.write(Frame::Challenge(challenge.nonce())).await;
+                self.state = State::Verifying(identify, challenge);
+            }
+            Err(e) => {
+                log(
+                    format!("{addr} tarafından gönderilen açık anahtar çözülemedi: {e}"),
+                    LogType::STATUS,
+                );
+                writer
+                    .write(Frame::Err(String::from(
+                        "The public key is broken or unsupported.",
+                    )))
+                    .await;
+            }
+        }
+    }
+
+    async fn on_verify(&mut self, verify: Verify, writer: &mut Writer<'_>) {
+        let addr = self.addr;
+        let (identify, challenge) =
+            match std::mem::replace(&mut self.state, State::AwaitingIdentify) {
+                State::Verifying(identify, challenge) => (identify, challenge),
+                state => {
+                    self.state = state;
+                    writer
+                        .write(Frame::Err(String::from(
+                            "There is no pending challenge. Identify yourself first.",
+                        )))
+                        .await;
+                    return;
+                }
+            };
+        let Identify {
+            username,
+            pem,
+            resume,
+            password,
+        } = &identify;
+        let key = challenge.fingerprint();
+        if !challenge.verify(&verify.signature) {
+            log(
+                format!(
+                    r#"{addr} tarafından "{username}" için gönderilen imza doğrulanamadı. Bağlantı sonlandırılıyor.."#
+                ),
+                LogType::WARN,
+            );
+            self.close(
+                writer,
+                Frame::Err(String::from(
+                    "The signature could not be verified. The link will be terminated.",
+                )),
+            )
+            .await;
+            return;
+        }
+        log(
+            format!(
+                r#"{addr} anahtar sahipliğini kanıtladı ({key}): "{username}" isimli oturum dosyası oluşturuluyor.."#
+            ),
+            LogType::STATUS,
+        );
+        writer.write(Frame::Ok(String::from("Connection verified. Your session is being prepared. Please wait for an ACK response before sending any messages."))).await;
+        let restored = match resume.is_empty() {
+            true => Restore::Fresh,
+            false => self.restore(resume, &key, writer).await,
+        };
+        let fresh = matches!(restored, Restore::Fresh);
+        let (created, greeting) = match restored {
+            Restore::Done => {
+                if self.is_established() {
+                    self.guard(&key, password, writer).await;
+                }
+                return;
+            }
+            Restore::Profile(user, token) => (Ok((user, token)), "Connection Established. Your user profile has been restored and you are now ready for chat! Use your user id to send a message."),
+            Restore::Fresh => (
+                create_session(self.server.store(), username, pem, &addr.to_string()).await,
+                "Connection Established. Your user profile has been created and you are now ready for chat! Use your user id to send a message.",
+            ),
+        };
+        match created {
+            Ok((user, token)) => {
+                let username = &user.username;
+                if !self.server.register(
+                    &user.uuid,
+                    username,
+                    &addr.ip().to_string(),
+                    &key,
+                    Arc::clone(&self.outbox),
+                ) {
+                    log(
+                        format!(
+                            r#"{addr} "{username}" takma adını kullanamadı, bu ad zaten kullanımda."#
+                        ),
+                        LogType::WARN,
+                    );
+                    if fresh {
+                        let _ = self.server.store().delete(&user.uuid).await;
+                    }
+                    writer
+                        .write(Frame::Err(format!(
+                            "The nickname {username} is already in use."
+                        )))
+                        .await;
+                    return;
+                }
+                writer.write(Frame::Ok(String::from(greeting))).await;
+                writer.write(Frame::Uuid(user.uuid.clone())).await;
+                writer.write(Frame::Resume(token)).await;
+                let motd = self.server.settings().motd.clone();
+                if !motd.is_empty() {
+                    writer.write(Frame::Motd(Motd { lines: motd })).await;
+                }
+                let operator = self
+                    .server
+                    .settings()
+                    .operators
+                    .by_key(&key)
+                    .map(|(name, role)| (name.to_string(), role));
+                if let Some((name, role)) = operator {
+                    log(
+                        format!("{addr} ({username}) anahtarıyla {name} operatörü olarak tanındı: {role}"),
+                        LogType::STATUS,
+                    );
+                    self.server.oper(&user.uuid, role);
+                    writer
+                        .write(Frame::Ok(format!("You are now a server {role}.")))
+                        .await;
+                }
+                if self.server.join(DEFAULT_CHANNEL, &user.uuid, username) {
+                    self.channels.insert(DEFAULT_CHANNEL.to_string());
+                    self.replay(DEFAULT_CHANNEL, writer).await;
+                }
+                self.state = State::Established {
+                    uuid: user.uuid.clone(),
+                    username: username.to_string(),
+                };
+                self.guard(&key, password, writer).await;
+            }
+            Err(e) => {
+                log(
+                    format!("{username} için oturum dosyası oluşturulurken bir hata oluştu: {e}"),
+                    LogType::ERROR,
+                );
+                self.close(
+                    writer,
+                    Frame::Err(String::from(
+                        "The session creation process fails. The link will be terminated.",
+                    )),
+                )
+                .await;
+            }
+        }
+    }
+
+    /// Takes back the session a resume token was issued for. The key just
+    /// proven must be the one the session was created with.
+    async fn restore(&mut self, token: &str, key: &str, writer: &mut Writer<'_>) -> Restore {
+        let addr = self.addr;
+        let ip = addr.ip().to_string();
+        let store = self.server.store();
+        let user = match find_session(store, token).await {
+            Ok(Some(user)) if auth::fingerprint(&user.pem).as_deref() == Some(key) => user,
+            Ok(_) => {
+                log(
+                    format!(
+                        "{addr} geçersiz bir oturum anahtarı sundu, yeni bir oturum oluşturulacak."
+                    ),
+                    LogType::STATUS,
+                );
+                writer
+                    .write(Frame::Err(String::from(
+                        "The resume token is not valid for this key. A new session will be created.",
+                    )))
+                    .await;
+                return Restore::Fresh;
+            }
+            Err(e) => {
+                log(
+                    format!("{addr} için oturum dosyası okunurken bir hata oluştu: {e}"),
+                    LogType::ERROR,
+                );
+                return Restore::Fresh;
+            }
+        };
+        let subject = Subject {
+            uuid: &user.uuid,
+            nick: &user.username,
+            ip: &ip,
+            key,
+        };
+        if let Some(ban) = self.server.banned(&subject) {
+            log(
+                format!(
+                    r#"{addr} "{}" oturumunu devralmaya çalıştı ancak {} yasağı bulunuyor. Bağlantı sonlandırılıyor.."#,
+                    user.username, ban.kind
+                ),
+                LogType::WARN,
+            );
+            self.close(writer, Frame::Kicked(ban.sanction())).await;
+            return Restore::Done;
+        }
+        if user.banned {
+            log(
+                format!(
+                    r#"{addr} yasaklı "{}" oturumunu devralmaya çalıştı. Bağlantı sonlandırılıyor.."#,
+                    user.username
+                ),
+                LogType::WARN,
+            );
+            self.close(
+                writer,
+                Frame::Err(String::from(
+                    "This session is banned. The link will be terminated.",
+                )),
+            )
+            .await;
+            return Restore::Done;
+        }
+        let mut user = user;
+        match self.server.reclaim(&user.uuid, &self.outbox, &ip, key) {
+            Reclaim::Elsewhere => {
+                writer
+                    .write(Frame::Err(String::from(
+                        "The session is active on another server. A new session will be created.",
+                    )))
+                    .await;
+                Restore::Fresh
+            }
+            Reclaim::Offline => match renew_session(store, &mut user, &addr.to_string()).await {
+                Ok(token) => {
+                    log(
+                        format!(r#"{addr} "{}" oturumunu geri yükledi."#, user.username),
+                        LogType::STATUS,
+                    );
+                    Restore::Profile(user, token)
+                }
+                Err(e) => {
+                    log(
+                        format!("{} için oturum dosyası güncellenemedi: {e}", user.username),
+                        LogType::ERROR,
+                    );
+                    Restore::Fresh
+                }
+            },
+            Reclaim::Resumed { username, channels } => {
+                log(
+                    format!(r#"{addr} "{username}" oturumunu devraldı."#),
+                    LogType::OK,
+                );
+                let token = match renew_session(store, &mut user, &addr.to_string()).await {
+                    Ok(token) => token,
+                    Err(e) => {
+                        log(
+                            format!("{username} için oturum dosyası güncellenemedi: {e}"),
+                            LogType::WARN,
+                        );
+                        String::new()
+                    }
+                };
+                writer
+                    .write(Frame::Ok(String::from(
+                        "Session resumed. Anything sent to you while you were away follows.",
+                    )))
+                    .await;
+                writer.write(Frame::Uuid(user.uuid.clone())).await;
+                if !token.is_empty() {
+                    writer.write(Frame::Resume(token)).await;
+                }
+                self.channels = channels.into_iter().collect();
+                self.state = State::Established {
+                    uuid: user.uuid,
+                    username,
+                };
+                Restore::Done
+            }
+        }
+    }
+
+    async fn on_message(&mut self, message: Message, writer: &mut Writer<'_>) {
+        let addr = self.addr;
+        let Some((uuid, username)) = self.session() else {
+            log(
+                format!("{addr} kimliğini doğrulamadan mesaj göndermeye çalıştı."),
+                LogType::WARN,
+            );
+            writer
+                .write(Frame::Err(String::from(
+                    "Identify yourself before sending messages.",
+                )))
+                .await;
+            return;
+        };
+        if !message.uuid.is_empty() && message.uuid != uuid {
+            log(
+                format!(
+                    "{addr} ({username}) başka bir kullanıcı kimliğiyle mesaj göndermeye çalıştı: {}",
+                    message.uuid
+                ),
+                LogType::WARN,
+            );
+            writer
+                .write(Frame::Err(String::from(
+                    "The user id doesn't belong to this connection.",
+                )))
+                .await;
+            return;
+        }
+        if self.server.muted(uuid) {
+            writer
+                .write(Frame::Err(String::from("You are muted.")))
+                .await;
+            return;
+        }
+        let channel = message.channel.to_lowercase();
+        if self.channels.contains(&channel) {
+            self.server.publish(message.attributed(uuid, username));
+        } else {
+            writer
+                .write(Frame::Err(String::from("You are not on that channel.")))
+                .await;
+        }
+    }
+
+    async fn on_join(&mut self, channel: &str, writer: &mut Writer<'_>) {
+        let addr = self.addr;
+        let channel = channel.to_lowercase();
+        let Some((uuid, username)) = self.session() else {
+            writer
+                .write(Frame::Err(String::from(
+                    "Identify yourself before joining a channel.",
+                )))
+                .await;
+            return;
+        };
+        if !valid_channel(&channel) {
+            writer
+                .write(Frame::Err(String::from("The channel name is not valid.")))
+                .await;
+            return;
+        }
+        if self.server.join(&channel, uuid, username) {
+            log(
+                format!("{addr} ({username}) {channel} kanalına katıldı."),
+                LogType::STATUS,
+            );
+            self.replay(&channel, writer).await;
+            self.channels.insert(channel);
+        }
+    }
+
+    /// Catches a connection up on what was said in a channel before it came.
+    async fn replay(&self, channel: &str, writer: &mut Writer<'_>) {
+        for message in self.server.history(channel) {
+            writer.write(Frame::History(message)).await;
+        }
+    }
+
+    async fn on_part(&mut self, channel: &str, writer: &mut Writer<'_>) {
+        let addr = self.addr;
+        let channel = channel.to_lowercase();
+        let parted = match self.session() {
+            Some((uuid, username)) if self.channels.contains(&channel) => {
+                self.server.part(&channel, uuid, "");
+                log(
+                    format!("{addr} ({username}) {channel} kanalından ayrıldı."),
+                    LogType::STATUS,
+                );
+                Some(Frame::Part(Membership::new(&channel, username)))
+            }
+            _ => None,
+        };
+        match parted {
+            Some(frame) => {
+                self.channels.remove(&channel);
+                writer.write(frame).await;
+            }
+            None => {
+                writer
+                    .write(Frame::Err(String::from("You are not on that channel.")))
+                    .await;
+            }
+        }
+    }
+
+    async fn on_names(&mut self, channel: &str, writer: &mut Writer<'_>) {
+        if self.session().is_none() {
+            writer
+                .write(Frame::Err(String::from(
+                    "Identify yourself before listing users.",
+                )))
+                .await;
+            return;
+        }
+        if !channel.is_empty() && !self.server.exists(channel) {
+            writer
+                .write(Frame::Err(String::from("There is no such channel.")))
+                .await;
+            return;
+        }
+        writer
+            .write(Frame::Roster(self.server.roster(channel)))
+            .await;
+    }
+
+    async fn on_away(&mut self, message: &str, writer: &mut Writer<'_>) {
+        let Some((uuid, _)) = self.session() else {
+            writer
+                .write(Frame::Err(String::from(
+                    "Identify yourself before changing your status.",
+                )))
+                .await;
+            return;
+        };
+        self.server.set_away(uuid, message.trim());
+        writer
+            .write(Frame::Ok(String::from(if message.trim().is_empty() {
+                "You are no longer marked as being away."
+            } else {
+                "You have been marked as being away."
+            })))
+            .await;
+    }
+
+    async fn on_private(&mut self, request: PrivateRequest, writer: &mut Writer<'_>) {
+        let Some((uuid, username)) = self.session() else {
+            writer
+                .write(Frame::Err(String::from(
+                    "Identify yourself before sending a private message.",
+                )))
+                .await;
+            return;
+        };
+        if self.server.muted(uuid) {
+            writer
+                .write(Frame::Err(String::from("You are muted.")))
+                .await;
+            return;
+        }
+        let Some((target, to)) = self.server.lookup(&request.target) else {
+            writer
+                .write(Frame::Err(String::from(
+                    "The user you are trying to reach is not online.",
+                )))
+                .await;
+            return;
+        };
+        let message = PrivateMessage {
+            from: username.to_string(),
+            uuid: uuid.to_string(),
+            to,
+            content: request.content,
+        };
+        if self.server.deliver(&target, Frame::Pmsg(message.clone())) {
+            if target != message.uuid {
+                writer.write(Frame::Pmsg(message)).await;
+            }
+        } else {
+            writer
+                .write(Frame::Err(String::from(
+                    "The user you are trying to reach is not online.",
+                )))
+                .await;
+        }
+    }
+
+    /// The nickname and role of a connection holding at least `needed`, or
+    /// `None` after telling it that it does not.
+    async fn authorize(&self, needed: Role, writer: &mut Writer<'_>) -> Option<(String, Role)> {
+        let Some((uuid, username)) = self.session() else {
+            writer
+                .write(Frame::Err(String::from(
+                    "Identify yourself before moderating.",
+                )))
+                .await;
+            return None;
+        };
+        match self.server.role(uuid) {
+            Some(role) if role >= needed => Some((username.to_string(), role)),
+            _ => {
+                log(
+                    format!(
+                        "{} ({username}) yetkisi olmadan {needed} komutu göndermeye çalıştı.",
+                        self.addr
+                    ),
+                    LogType::WARN,
+                );
+                writer
+                    .write(Frame::Err(format!(
+                        "You need to be a server {needed} to do that."
+                    )))
+                    .await;
+                None
+            }
+        }
+    }
+
+    /// Moderators can only act on users below their own role.
+    async fn outranks(&self, role: Role, target: &str, writer: &mut Writer<'_>) -> bool {
+        if self
+            .server
+            .role(target)
+            .is_some_and(|theirs| theirs >= role)
+        {
+            writer
+                .write(Frame::Err(String::from(
+                    "You cannot moderate a user with an equal or higher role.",
+                )))
+                .await;
+            return false;
+        }
+        true
+    }
+
+    async fn on_kick(&mut self, kick: Kick, writer: &mut Writer<'_>) {
+        let Some((by, role)) = self.authorize(Role::Moderator, writer).await else {
+            return;
+        };
+        let Some((uuid, username)) = self.server.lookup(&kick.target) else {
+            writer
+                .write(Frame::Err(String::from(
+                    "The user you are trying to reach is not online.",
+                )))
+                .await;
+            return;
+        };
+        if !self.outranks(role, &uuid, writer).await {
+            return;
+        }
+        log(
+            format!(
+                "{by}, {username} kullanıcısını sunucudan attı: {}",
+                kick.reason
+            ),
+            LogType::WARN,
+        );
+        self.server.kick(
+            &uuid,
+            Sanction {
+                by,
+                reason: kick.reason,
+                ban: false,
+                until: None,
+            },
+        );
+        writer
+            .write(Frame::Ok(format!("{username} has been kicked.")))
+            .await;
+    }
+
+    async fn on_ban(&mut self, ban: Ban, writer: &mut Writer<'_>) {
+        let Some((by, role)) = self.authorize(Role::Operator, writer).await else {
+            return;
+        };
+        let target = match ban.kind {
+            BanKind::Uuid => self
+                .server
+                .lookup(&ban.mask)
+                .map(|(uuid, _)| (uuid, String::new(), String::new())),
+            BanKind::Nick => None,
+            BanKind::Ip | BanKind::Key => self.server.origin(&ban.mask),
+        };
+        let mask = match (ban.kind, target) {
+            (BanKind::Uuid, Some((uuid, _, _))) => uuid,
+            (BanKind::Ip, Some((_, ip, _))) => ip,
+            (BanKind::Key, Some((_, _, key))) if !key.is_empty() => key,
+            (BanKind::Key, Some(_)) => {
+                writer
+                    .write(Frame::Err(String::from(
+                        "That user did not identify with a key.",
+                    )))
+                    .await;
+                return;
+            }
+            _ => ban.mask.trim().to_string(),
+        };
+        if mask.is_empty() || mask.chars().all(|c| c == '*' || c == '?') {
+            writer
+                .write(Frame::Err(String::from("The ban mask is too broad.")))
+                .await;
+            return;
+        }
+        let until = match moderation::expiry(ban.duration) {
+            Ok(until) => until,
+            Err(e) => {
+                writer.write(Frame::Err(e)).await;
+                return;
+            }
+        };
+        let entry = moderation::Ban::new(ban.kind, &mask, &by, &ban.reason, until.clone());
+        if self.server.shields(&entry, role) {
+            writer
+                .write(Frame::Err(String::from(
+                    "The ban would catch a user with an equal or higher role.",
+                )))
+                .await;
+            return;
+        }
+        match self.server.ban(entry) {
+            Ok(dropped) => {
+                log(
+                    format!(
+                        "{by}, {} {mask} için {} yasak koydu: {}",
+                        ban.kind,
+                        until
+                            .as_deref()
+                            .map_or(String::from("kalıcı"), |until| format!(
+                                "{until} tarihine kadar"
+                            )),
+                        ban.reason
+                    ),
+                    LogType::WARN,
+                );
+                if ban.kind == BanKind::Uuid && until.is_none() {
+                    if let Err(e) = mark_banned(self.server.store(), &mask, true).await {
+                        log(
+                            format!("{mask} için oturum dosyası güncellenemedi: {e}"),
+                            LogType::WARN,
+                        );
+                    }
+                }
+                let term = until.map_or(String::from("permanently"), |until| {
+                    format!("until {until}")
+                });
+                writer
+                    .write(Frame::Ok(format!(
+                        "Banned {} {mask} {term}. {dropped} connection(s) dropped.",
+                        ban.kind
+                    )))
+                    .await;
+            }
+            Err(e) => {
+                log(
+                    format!("Yasak kaydedilirken bir hata oluştu: {e}"),
+                    LogType::ERROR,
+                );
+                writer
+                    .write(Frame::Err(String::from("The ban could not be saved.")))
+                    .await;
+            }
+        }
+    }
+
+    async fn on_unban(&mut self, unban: Unban, writer: &mut Writer<'_>) {
+        let Some((by, _)) = self.authorize(Role::Operator, writer).await else {
+            return;
+        };
+        let mask = unban.mask.trim();
+        match self.server.unban(unban.kind, mask) {
+            Ok(true) => {
+                log(
+                    format!("{by}, {} {mask} yasağını kaldırdı.", unban.kind),
+                    LogType::STATUS,
+                );
+                if unban.kind == BanKind::Uuid {
+                    let _ = mark_banned(self.server.store(), mask, false).await;
+                }
+                writer
+                    .write(Frame::Ok(format!(
+                        "Lifted the ban on {} {mask}.",
+                        unban.kind
+                    )))
+                    .await;
+            }
+            Ok(false) => {
+                writer
+                    .write(Frame::Err(String::from("There is no such ban.")))
+                    .await;
+            }
+            Err(e) => {
+                log(
+                    format!("Yasak kaldırılırken bir hata oluştu: {e}"),
+                    LogType::ERROR,
+                );
+                writer
+                    .write(Frame::Err(String::from("The ban could not be lifted.")))
+                    .await;
+            }
+        }
+    }
+
+    async fn on_mute(&mut self, mute: Mute, writer: &mut Writer<'_>) {
+        let Some((by, role)) = self.authorize(Role::Moderator, writer).await else {
+            return;
+        };
+        let Some((uuid, username)) = self.server.lookup(&mute.target) else {
+            writer
+                .write(Frame::Err(String::from(
+                    "The user you are trying to reach is not online.",
+                )))
+                .await;
+            return;
+        };
+        if !self.outranks(role, &uuid, writer).await {
+            return;
+        }
+        let until = match moderation::expiry(mute.duration) {
+            Ok(until) => until,
+            Err(e) => {
+                writer.write(Frame::Err(e)).await;
+                return;
+            }
+        };
+        log(
+            format!("{by}, {username} kullanıcısını susturdu: {}", mute.reason),
+            LogType::STATUS,
+        );
+        self.server.mute(
+            &uuid,
+            Sanction {
+                by,
+                reason: mute.reason,
+                ban: false,
+                until,
+            },
+        );
+        writer
+            .write(Frame::Ok(format!("{username} has been muted.")))
+            .await;
+    }
+
+    async fn on_unmute(&mut self, unmute: Unmute, writer: &mut Writer<'_>) {
+        if self.authorize(Role::Moderator, writer).await.is_none() {
+            return;
+        }
+        let Some((uuid, username)) = self.server.lookup(&unmute.target) else {
+            writer
+                .write(Frame::Err(String::from(
+                    "The user you are trying to reach is not online.",
+                )))
+                .await;
+            return;
+        };
+        let reply = if self.server.unmute(&uuid) {
+            Frame::Ok(format!("{username} can speak again."))
+        } else {
+            Frame::Err(format!("{username} is not muted."))
+        };
+        writer.write(reply).await;
+    }
+
+    /// Logs the connection into the account on its nickname if it proved
+    /// the account's key or knows the password. Otherwise it has
+    /// `nick_grace` to log in before it is renamed.
+    async fn guard(&mut self, key: &str, password: &str, writer: &mut Writer<'_>) {
+        let addr = self.addr;
+        let Some((uuid, username)) = self.session() else {
+            return;
+        };
+        let (uuid, username) = (uuid.to_string(), username.to_string());
+        if self
+            .server
+            .account(&uuid)
+            .is_some_and(|account| account.eq_ignore_ascii_case(&username))
+        {
+            return;
+        }
+        let account = match self.server.store().load_account(&username).await {
+            Ok(Some(account)) => account,
+            Ok(None) => return,
+            Err(e) => {
+                log(
+                    format!("{username} hesabı okunurken bir hata oluştu: {e}"),
+                    LogType::ERROR,
+                );
+                return;
+            }
+        };
+        if account.owns_key(key) || account.check_password(password).await {
+            log(
+                format!("{addr} ({username}) hesabına giriş yaptı."),
+                LogType::STATUS,
+            );
+            self.server.login(&uuid, &account.name);
+            writer
+                .write(Frame::Ok(format!("You are now logged in as {username}.")))
+                .await;
+            return;
+        }
+        let grace = self.server.settings().nick_grace;
+        self.nick_deadline = Some(Instant::now() + grace);
+        writer
+            .write(Frame::Err(format!(
+                "The nickname {username} is registered. Log in with FN<>::Login within {} seconds or you will be renamed.",
+                grace.as_secs()
+            )))
+            .await;
+    }
+
+    /// Moves the connection off a registered nickname it did not log into.
+    async fn enforce(&mut self, writer: &mut Writer<'_>) {
+        let addr = self.addr;
+        self.nick_deadline = None;
+        let Some((uuid, username)) = self.session() else {
+            return;
+        };
+        let (uuid, username) = (uuid.to_string(), username.to_string());
+        let Some(guest) = self.server.rename_guest(&uuid) else {
+            log(
+                format!("{addr} ({username}) için boş bir misafir takma adı bulunamadı, yeniden denenecek."),
+                LogType::WARN,
+            );
+            self.nick_deadline = Some(Instant::now() + GUEST_RETRY);
+            return;
+        };
+        log(
+            format!("{addr} ({username}) kayıtlı takma ada giriş yapmadı, {guest} olarak yeniden adlandırıldı."),
+            LogType::WARN,
+        );
+        writer
+            .write(Frame::Err(format!(
+                "You did not log in to {username} in time. You are now known as {guest}."
+            )))
+            .await;
+        self.state = State::Established {
+            uuid,
+            username: guest,
+        };
+    }
+
+    async fn on_register(&mut self, register: Register, writer: &mut Writer<'_>) {
+        let addr = self.addr;
+        let Some((uuid, username)) = self.session() else {
+            writer
+                .write(Frame::Err(String::from(
+                    "Identify yourself before registering a nickname.",
+                )))
+                .await;
+            return;
+        };
+        let (uuid, username) = (uuid.to_string(), username.to_string());
+        if !register.password.is_empty() && register.password.chars().count() < MIN_PASSWORD_LENGTH
+        {
+            writer
+                .write(Frame::Err(format!(
+                    "Passwords must be at least {MIN_PASSWORD_LENGTH} characters long."
+                )))
+                .await;
+            return;
+        }
+        let store = self.server.store();
+        match store.load_account(&username).await {
+            Ok(None) => {}
+            Ok(Some(_)) => {
+                writer
+                    .write(Frame::Err(format!(
+                        "The nickname {username} is already registered."
+                    )))
+                    .await;
+                return;
+            }
+            Err(e) => {
+                log(
+                    format!("{username} hesabı okunurken bir hata oluştu: {e}"),
+                    LogType::ERROR,
+                );
+                writer
+                    .write(Frame::Err(String::from(
+                        "The account could not be created.",
+                    )))
+                    .await;
+                return;
+            }
+        }
+        let key = self
+            .server
+            .origin(&uuid)
+            .map(|(_, _, key)| key)
+            .unwrap_or_default();
+        let created = match Account::new(&username, &register.password, &key, &uuid).await {
+            Ok(account) => store.create_account(&account).await.map(|_| account),
+            Err(e) => Err(e),
+        };
+        match created {
+            Ok(account) => {
+                log(
+                    format!("{addr} ({username}) takma adını kaydettirdi."),
+                    LogType::OK,
+                );
+                self.server.login(&uuid, &account.name);
+                self.nick_deadline = None;
+                writer
+                    .write(Frame::Ok(format!(
+                        "The nickname {username} is now registered to you."
+                    )))
+                    .await;
+            }
+            Err(e) => {
+                log(
+                    format!("{username} hesabı oluşturulurken bir hata oluştu: {e}"),
+                    LogType::ERROR,
+                );
+                writer
+                    .write(Frame::Err(String::from(
+                        "The account could not be created.",
+                    )))
+                    .await;
+            }
+        }
+    }
+
+    async fn on_login(&mut self, login: Login, writer: &mut Writer<'_>) {
+        let addr = self.addr;
+        let Some((uuid, username)) = self.session() else {
+            writer
+                .write(Frame::Err(String::from(
+                    "Identify yourself before logging in.",
+                )))
+                .await;
+            return;
+        };
+        let (uuid, username) = (uuid.to_string(), username.to_string());
+        let name = match login.name.trim() {
+            "" => username.clone(),
+            name => name.to_string(),
+        };
+        if !valid_nick(&name) {
+            writer.write(Frame::Err(String::from(INVALID_NICK))).await;
+            return;
+        }
+        let account = match self.server.store().load_account(&name).await {
+            Ok(Some(account)) => account,
+            Ok(None) => {
+                writer
+                    .write(Frame::Err(format!(
+                        "The nickname {name} is not registered."
+                    )))
+                    .await;
+                return;
+            }
+            Err(e) => {
+                log(
+                    format!("{name} hesabı okunurken bir hata oluştu: {e}"),
+                    LogType::ERROR,
+                );
+                writer
+                    .write(Frame::Err(String::from("The account could not be read.")))
+                    .await;
+                return;
+            }
+        };
+        let key = self
+            .server
+            .origin(&uuid)
+            .map(|(_, _, key)| key)
+            .unwrap_or_default();
+        if !account.owns_key(&key) && !account.check_password(&login.password).await {
+            log(
+                format!("{addr} ({username}) {name} hesabına giriş yapamadı."),
+                LogType::WARN,
+            );
+            writer
+                .write(Frame::Err(String::from("The password is incorrect.")))
+                .await;
+            return;
+        }
+        if !name.eq_ignore_ascii_case(&username) {
+            if !self.server.rename(&uuid, &name) {
+                writer
+                    .write(Frame::Err(format!(
+                        "{name} is in use right now. Log in again once it is free."
+                    )))
+                    .await;
+                return;
+            }
+            self.state = State::Established {
+                uuid: uuid.clone(),
+                username: name.clone(),
+            };
+        }
+        log(
+            format!("{addr} ({username}) {name} hesabına giriş yaptı."),
+            LogType::STATUS,
+        );
+        self.server.login(&uuid, &account.name);
+        self.nick_deadline = None;
+        writer
+            .write(Frame::Ok(format!("You are now logged in as {name}.")))
+            .await;
+    }
+
+    async fn on_oper(&mut self, oper: Oper, writer: &mut Writer<'_>) {
+        let addr = self.addr;
+        let Some((uuid, username)) = self.session() else {
+            writer
+                .write(Frame::Err(String::from(
+                    "Identify yourself before becoming an operator.",
+                )))
+                .await;
+            return;
+        };
+        let role = self
+            .server
+            .settings()
+            .operators
+            .authenticate(&oper.name, &oper.password);
+        match role {
+            Some(role) => {
+                log(
+                    format!(
+                        "{addr} ({username}) {} operatörü olarak giriş yaptı: {role}",
+                        oper.name
+                    ),
+                    LogType::STATUS,
+                );
+                self.server.oper(uuid, role);
+                writer
+                    .write(Frame::Ok(format!("You are now a server {role}.")))
+                    .await;
+            }
+            None => {
+                log(
+                    format!(
+                        "{addr} ({username}) {} operatörü olarak giriş yapamadı.",
+                        oper.name
+                    ),
+                    LogType::WARN,
+                );
+                writer
+                    .write(Frame::Err(String::from(
+                        "The operator name or password is incorrect.",
+                    )))
+                    .await;
+            }
+        }
+    }
+
+    async fn on_die(&mut self, quit: Quit, writer: &mut Writer<'_>) {
+        let Some((by, _)) = self.authorize(Role::Owner, writer).await else {
+            return;
+        };
+        log(
+            format!("{by} sunucuyu kapatıyor: {}", quit.reason),
+            LogType::WARN,
+        );
+        self.server.shutdown();
+    }
+}
+
+fn hello(server: &Server) -> Hello {
+    let settings = server.settings();
+    let mut capabilities: Vec<String> = CAPABILITIES.iter().map(|c| c.to_string()).collect();
+    if !settings.resume_grace.is_zero() {
+        capabilities.push(String::from("resume"));
+    }
+    Hello {
+        name: server.name.clone(),
+        version: SERVER_VERSION.to_string(),
+        capabilities,
+        max_line_length: settings.limits.line,
+        max_body_length: settings.limits.body,
+    }
+}
+
+/// Tells a native client it was turned away before closing the connection.
+pub async fn refuse(socket: Stream, reason: &str) {
+    let mut writer = FramedWrite::new(socket, FrameCodec::new());
+    let _ = writer.send(Frame::Err(reason.to_string())).await;
+    let _ = writer.get_mut().shutdown().await;
+}
+
+pub async fn handle(socket: Stream, addr: SocketAddr, server: Arc<Server>) {
+    let mut connection = Connection::new(addr, server);
+    let outbox = Arc::clone(&connection.outbox);
+    let (reader, writer) = tokio::io::split(socket);
+    let mut reader: FramedRead<ReadHalf<Stream>, FrameCodec> = FramedRead::new(
+        reader,
+        FrameCodec::with_limits(connection.server.settings().limits),
+    );
+    let mut socket_writer = Writer::new(&addr, writer);
+
+    socket_writer
+        .write(Frame::Hello(hello(&connection.server)))
+        .await;
+
+    let server = Arc::clone(&connection.server);
+    let mut stopping = false;
+    let mut dropped = false;
+    let handshake = tokio::time::sleep(server.admission.limits().handshake);
+    tokio::pin!(handshake);
+    let mut handshaking = true;
+    // A throttled request waits here for the gate to open. Only reading is
+    // held up; replies and deliveries keep flowing meanwhile.
+    let mut held = None;
+    let gate = tokio::time::sleep(Duration::ZERO);
+    tokio::pin!(gate);
+    while !connection.is_closing() {
+        // FN_RESET drops the session, so the new identification gets a
+        // fresh deadline of its own.
+        let identifying = !connection.is_established();
+        if identifying && !handshaking {
+            handshake
+                .as_mut()
+                .reset(Instant::now() + server.admission.limits().handshake);
+        }
+        handshaking = identifying;
+        tokio::select! {
+            _ = tokio::time::sleep_until(connection.nick_deadline.unwrap_or_else(Instant::now)), if connection.nick_deadline.is_some() => {
+                connection.enforce(&mut socket_writer).await;
+            }
+            _ = &mut handshake, if handshaking => {
+                log(format!("{addr} zamanında kimliğini doğrulamadı, bağlantı sonlandırılıyor."), LogType::WARN);
+                connection.departure = (QuitReason::Timeout, String::new());
+                connection.close(&mut socket_writer, Frame::Err(String::from("You did not identify in time. The link will be terminated."))).await;
+            }
+            _ = server.stopping(), if !stopping => {
+                stopping = true;
+                connection.departure = (QuitReason::Closed, String::from(SHUTDOWN_MESSAGE));
+                outbox.push(Packet::new(Frame::Shutdown(String::from(
+                    "The server is shutting down. The link will be terminated.",
+                ))));
+                outbox.close();
+            }
+            _ = &mut gate, if held.is_some() => {
+                if let Some(line) = held.take() {
+                    connection.on_line(line, &mut socket_writer).await;
+                }
+            }
+            result = reader.next(), if held.is_none() => {
+                match result {
+                    None => {
+                        dropped = true;
+                        break;
+                    },
+                    Some(Ok(line)) => match connection.pace(&mut socket_writer).await {
+                        Some(wait) if wait.is_zero() => connection.on_line(line, &mut socket_writer).await,
+                        Some(wait) => {
+                            gate.as_mut().reset(Instant::now() + wait);
+                            held = Some(line);
+                        }
+                        None => {}
+                    },
+                    Some(Err(e)) => {
+                      log(format!("Bir hata oluştu: {e}"), LogType::ERROR);
+                      if matches!(&e, ProtocolError::Io(io) if io.kind() == ErrorKind::TimedOut) {
+                          connection.departure = (QuitReason::Timeout, String::new());
+                      }
+                      dropped = true;
+                      break;
+                    }
+                }
+            }
+            delivery = outbox.recv() => match delivery {
+                Delivery::Packet(packet) => socket_writer.write_line(Arc::clone(&packet.line)).await,
+                Delivery::Gap(dropped) => socket_writer.write(Frame::Gap(dropped)).await,
+                Delivery::Overflow => {
+                    log(format!("{addr} gönderilen mesajlara yetişemedi, bağlantı sonlandırılıyor."), LogType::WARN);
+                    connection.departure = (QuitReason::Closed, String::from("SendQ exceeded"));
+                    connection.close(&mut socket_writer, Frame::Err(String::from("Your connection is too slow to keep up. The link will be terminated."))).await;
+                }
+                Delivery::Closed => break,
+            },
+        }
+    }
+
+    socket_writer.close().await;
+    let (reason, message) = connection.departure.clone();
+    match connection.session() {
+        Some((uuid, username))
+            if dropped && !stopping && server.attached(uuid, &connection.outbox) =>
+        {
+            log(
+                format!(
+                    "{addr} ({username}) bağlantısı koptu, oturum {} saniye boyunca devralınmayı bekleyecek.",
+                    server.settings().resume_grace.as_secs()
+                ),
+                LogType::STATUS,
+            );
+            server.suspend(uuid, reason, &message);
+        }
+        _ => connection.reset(reason, &message),
+    }
+}
@@ -0,0 +1,142 @@
+use crate::util::compare::same;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt;
+
+pub const ENV_FILE_PATH: &str = ".env";
+/// `RUSTIRC_OPER_<NAME> = <role>:<password>`. The password may also be given
+/// as `sha256:<hex digest>`.
+pub const PASSWORD_PREFIX: &str = "RUSTIRC_OPER_";
+/// `RUSTIRC_OPER_KEY_<NAME> = <key fingerprint>` grants the role without a
+/// password to whoever proves that key.
+pub const KEY_PREFIX: &str = "RUSTIRC_OPER_KEY_";
+
+/// Privilege levels, lowest first. Each one can do everything the ones below
+/// it can.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Kicks and mutes.
+    Moderator,
+    /// Also bans and lifts bans.
+    Operator,
+    /// Also shuts the server down.
+    Owner,
+}
+
+impl Role {
+    fn parse(role: &str) -> Option<Self> {
+        match role.trim().to_lowercase().as_str() {
+            "moderator" => Some(Role::Moderator),
+            "operator" => Some(Role::Operator),
+            "owner" => Some(Role::Owner),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Role::Moderator => "moderator",
+            Role::Operator => "operator",
+            Role::Owner => "owner",
+        })
+    }
+}
+
+struct Credential {
+    name: String,
+    role: Role,
+    password: Option<String>,
+    key: Option<String>,
+}
+
+/// The operator credentials, read from the environment and the env file.
+#[derive(Default)]
+pub struct Operators {
+    credentials: Vec<Credential>,
+}
+
+impl Operators {
+    /// Collects the operator entries from the environment, the env file at
+    /// `path`, if it exists, and `extra`, each winning over the one before.
+    /// The file is parsed on its own rather than loaded into the environment,
+    /// so every call sees its current contents.
+    // `from_filename_iter` is deprecated in favour of loading the file into
+    // the environment, which would pin the first values read.
+    #[allow(deprecated)]
+    pub fn load(
+        path: &str,
+        extra: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Self, String> {
+        let mut vars: HashMap<String, String> = std::env::vars().collect();
+        match dotenv::from_filename_iter(path) {
+            Err(dotenv::Error::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(format!("{path}: {e}")),
+            Ok(entries) => {
+                for entry in entries {
+                    let (name, value) = entry.map_err(|e| format!("{path}: {e}"))?;
+                    vars.insert(name, value);
+                }
+            }
+        }
+        vars.extend(extra);
+        let mut operators = Self::default();
+        let mut keys = Vec::new();
+        for (name, value) in vars {
+            if let Some(name) = name.strip_prefix(KEY_PREFIX) {
+                keys.push((name.to_lowercase(), value.trim().to_lowercase()));
+            } else if let Some(name) = name.strip_prefix(PASSWORD_PREFIX) {
+                let (role, password) = value.split_once(':').unwrap_or((&value, ""));
+                let role = Role::parse(role)
+                    .ok_or_else(|| format!("{PASSWORD_PREFIX}{name}: unknown role {role}"))?;
+                operators.credentials.push(Credential {
+                    name: name.to_lowercase(),
+                    role,
+                    password: Some(password.trim().to_string()).filter(|p| !p.is_empty()),
+                    key: None,
+                });
+            }
+        }
+        for (name, key) in keys {
+            let credential = operators
+                .credentials
+                .iter_mut()
+                .find(|credential| credential.name == name)
+                .ok_or_else(|| format!("{KEY_PREFIX}{name}: no role for {name}"))?;
+            credential.key = Some(key);
+        }
+        Ok(operators)
+    }
+
+    pub fn count(&self) -> usize {
+        self.credentials.len()
+    }
+
+    /// The role behind an OPER attempt, if the password is right.
+    pub fn authenticate(&self, name: &str, password: &str) -> Option<Role> {
+        let credential = self
+            .credentials
+            .iter()
+            .find(|credential| credential.name.eq_ignore_ascii_case(name))?;
+        let expected = credential.password.as_deref()?;
+        let matches = match expected.strip_prefix("sha256:") {
+            Some(digest) => same(
+                &hex::encode(Sha256::digest(password)),
+                &digest.to_lowercase(),
+            ),
+            None => same(expected, password),
+        };
+        matches.then_some(credential.role)
+    }
+
+    /// The operator name and role tied to a key fingerprint.
+    pub fn by_key(&self, key: &str) -> Option<(&str, Role)> {
+        self.credentials
+            .iter()
+            .find(|credential| !key.is_empty() && credential.key.as_deref() == Some(key))
+            .map(|credential| (credential.name.as_str(), credential.role))
+    }
+}
@@ -0,0 +1,152 @@
+use super::{SessionStore, User};
+use crate::account::Account;
+use crate::util::log::{log, LogType};
+use async_trait::async_trait;
+use std::io::ErrorKind;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+
+const ACCOUNTS_FOLDER: &str = "accounts";
+
+/// One `{uuid}.dat` file per profile, holding its JSON hex-encoded. Accounts
+/// go in the `accounts` subfolder, named after the hex of the nickname so
+/// any nickname makes a safe file name.
+pub struct DirectoryStore {
+    folder: String,
+}
+
+impl DirectoryStore {
+    pub async fn open(folder: &str) -> Result<Self, String> {
+        let accounts = format!("{folder}/{ACCOUNTS_FOLDER}");
+        fs::create_dir_all(&accounts)
+            .await
+            .map_err(|e| format!("{accounts}: {e}"))?;
+        Ok(Self {
+            folder: folder.to_string(),
+        })
+    }
+
+    fn path(&self, uuid: &str) -> String {
+        format!("{}/{uuid}.dat", self.folder)
+    }
+
+    fn account_path(&self, name: &str) -> String {
+        format!(
+            "{}/{ACCOUNTS_FOLDER}/{}.dat",
+            self.folder,
+            hex::encode(name.to_lowercase())
+        )
+    }
+
+    async fn read(&self, path: &str) -> Result<User, String> {
+        let user_bytes = fs::read_to_string(path)
+            .await
+            .map_err(|e| format!("{path}: {e}"))?;
+        let user_json = decode(&user_bytes).map_err(|e| format!("{path}: {e}"))?;
+        serde_json::from_str(&user_json).map_err(|e| format!("{path}: {e}"))
+    }
+
+    async fn write(&self, user: &User) -> Result<(), String> {
+        let user_json = serde_json::to_string(user).map_err(|e| e.to_string())?;
+        let path = self.path(&user.uuid);
+        fs::write(&path, hex::encode(user_json))
+            .await
+            .map_err(|e| format!("{path}: {e}"))
+    }
+}
+
+#[async_trait]
+impl SessionStore for DirectoryStore {
+    async fn create(&self, user: &User) -> Result<(), String> {
+        let path = self.path(&user.uuid);
+        if fs::try_exists(&path).await.unwrap_or(false) {
+            return Err(format!("{path}: session already exists"));
+        }
+        self.write(user).await
+    }
+
+    async fn load(&self, uuid: &str) -> Result<Option<User>, String> {
+        let path = self.path(uuid);
+        if !fs::try_exists(&path).await.unwrap_or(false) {
+            return Ok(None);
+        }
+        self.read(&path).await.map(Some)
+    }
+
+    async fn update(&self, user: &User) -> Result<(), String> {
+        let path = self.path(&user.uuid);
+        if !fs::try_exists(&path).await.unwrap_or(false) {
+            return Err(format!("{path}: no such session"));
+        }
+        self.write(user).await
+    }
+
+    async fn delete(&self, uuid: &str) -> Result<(), String> {
+        let path = self.path(uuid);
+        match fs::remove_file(&path).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(format!("{path}: {e}")),
+            _ => Ok(()),
+        }
+    }
+
+    async fn list(&self) -> Result<Vec<User>, String> {
+        let mut entries = fs::read_dir(&self.folder)
+            .await
+            .map_err(|e| format!("{}: {e}", self.folder))?;
+        let mut users = Vec::new();
+        while let Some(entry) = entries.next_entry().await.map_err(|e| e.to_string())? {
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "dat") {
+                // One damaged file shouldn't keep every other profile from
+                // loading.
+                match self.read(&path.to_string_lossy()).await {
+                    Ok(user) => users.push(user),
+                    Err(e) => log(
+                        format!("Oturum dosyası okunamadı, atlanıyor: {e}"),
+                        LogType::WARN,
+                    ),
+                }
+            }
+        }
+        Ok(users)
+    }
+
+    async fn create_account(&self, account: &Account) -> Result<(), String> {
+        let path = self.account_path(&account.name);
+        let account_json = serde_json::to_string(account).map_err(|e| e.to_string())?;
+        let mut file = match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .await
+        {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                return Err(format!("{}: account already exists", account.name));
+            }
+            Err(e) => return Err(format!("{path}: {e}")),
+        };
+        file.write_all(hex::encode(account_json).as_bytes())
+            .await
+            .map_err(|e| format!("{path}: {e}"))
+    }
+
+    async fn load_account(&self, name: &str) -> Result<Option<Account>, String> {
+        let path = self.account_path(name);
+        let account_bytes = match fs::read_to_string(&path).await {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("{path}: {e}")),
+        };
+        let account_json = decode(&account_bytes).map_err(|e| format!("{path}: {e}"))?;
+        serde_json::from_str(&account_json)
+            .map(Some)
+            .map_err(|e| format!("{path}: {e}"))
+    }
+}
+
+/// Reads back what `hex::encode` wrote: the UTF-8 bytes of the JSON.
+fn decode(bytes: &str) -> Result<String, String> {
+    let bytes = hex::decode(bytes.trim()).map_err(|e| e.to_string())?;
+    String::from_utf8(bytes).map_err(|e| e.to_string())
+}
@@ -0,0 +1,66 @@
+use super::{SessionStore, User};
+use crate::account::Account;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Keeps profiles for as long as the process lives. Meant for tests and
+/// throwaway servers.
+#[derive(Default)]
+pub struct MemoryStore {
+    users: Mutex<HashMap<String, User>>,
+    accounts: Mutex<HashMap<String, Account>>,
+}
+
+#[async_trait]
+impl SessionStore for MemoryStore {
+    async fn create(&self, user: &User) -> Result<(), String> {
+        let mut users = self.users.lock().unwrap();
+        if users.contains_key(&user.uuid) {
+            return Err(format!("{}: session already exists", user.uuid));
+        }
+        users.insert(user.uuid.clone(), user.clone());
+        Ok(())
+    }
+
+    async fn load(&self, uuid: &str) -> Result<Option<User>, String> {
+        Ok(self.users.lock().unwrap().get(uuid).cloned())
+    }
+
+    async fn update(&self, user: &User) -> Result<(), String> {
+        match self.users.lock().unwrap().get_mut(&user.uuid) {
+            Some(stored) => {
+                *stored = user.clone();
+                Ok(())
+            }
+            None => Err(format!("{}: no such session", user.uuid)),
+        }
+    }
+
+    async fn delete(&self, uuid: &str) -> Result<(), String> {
+        self.users.lock().unwrap().remove(uuid);
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Vec<User>, String> {
+        Ok(self.users.lock().unwrap().values().cloned().collect())
+    }
+
+    async fn create_account(&self, account: &Account) -> Result<(), String> {
+        let mut accounts = self.accounts.lock().unwrap();
+        if accounts.contains_key(&account.name) {
+            return Err(format!("{}: account already exists", account.name));
+        }
+        accounts.insert(account.name.clone(), account.clone());
+        Ok(())
+    }
+
+    async fn load_account(&self, name: &str) -> Result<Option<Account>, String> {
+        Ok(self
+            .accounts
+            .lock()
+            .unwrap()
+            .get(&name.to_lowercase())
+            .cloned())
+    }
+}
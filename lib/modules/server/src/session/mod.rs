@@ -0,0 +1,232 @@
+pub mod directory;
+pub mod memory;
+pub mod redis;
+
+use crate::account::Account;
+use crate::util::generate_uuid::generate_session_key;
+use async_trait::async_trait;
+use clap::ValueEnum;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+pub const SESSION_FOLDER_PATH: &str = "sessions";
+pub const REDIS_URL: &str = "redis://127.0.0.1/";
+const RESUME_SECRET_LENGTH: usize = 32;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct User {
+    pub username: String,
+    pub pem: String,
+    pub uuid: String,
+    pub addr: String,
+    pub banned: bool,
+    /// SHA-256 of the secret half of the latest resume token, in hex.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub resume: String,
+}
+
+impl User {
+    /// A new profile under a freshly generated UUID.
+    pub fn new(username: &str, pem: &str, addr: &str) -> Self {
+        Self {
+            username: username.to_string(),
+            pem: pem.to_string(),
+            uuid: generate_session_key(),
+            addr: addr.to_string(),
+            banned: false,
+            resume: String::new(),
+        }
+    }
+
+    /// Replaces the resume token with a new one. The token is
+    /// `<uuid>.<secret>`; only a digest of the secret is kept.
+    fn issue_token(&mut self) -> String {
+        let mut secret = [0u8; RESUME_SECRET_LENGTH];
+        rand::thread_rng().fill_bytes(&mut secret);
+        let secret = hex::encode(secret);
+        self.resume = hex::encode(Sha256::digest(&secret));
+        format!("{}.{secret}", self.uuid)
+    }
+}
+
+/// Where user profiles are kept between connections.
+#[async_trait]
+pub trait SessionStore: Send + Sync {
+    /// Stores a new profile. Fails if the UUID is already taken.
+    async fn create(&self, user: &User) -> Result<(), String>;
+    async fn load(&self, uuid: &str) -> Result<Option<User>, String>;
+    /// Overwrites an existing profile. Fails if there is none.
+    async fn update(&self, user: &User) -> Result<(), String>;
+    async fn delete(&self, uuid: &str) -> Result<(), String>;
+    async fn list(&self) -> Result<Vec<User>, String>;
+    /// Stores a new account. Fails if the nickname is already registered.
+    async fn create_account(&self, account: &Account) -> Result<(), String>;
+    async fn load_account(&self, name: &str) -> Result<Option<Account>, String>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Backend {
+    Directory,
+    Memory,
+    Redis,
+}
+
+/// Opens the configured backend. `location` is the folder for `Directory`
+/// and the connection URL for `Redis`.
+pub async fn open(backend: Backend, location: &str) -> Result<Box<dyn SessionStore>, String> {
+    Ok(match backend {
+        Backend::Directory => Box::new(directory::DirectoryStore::open(location).await?),
+        Backend::Memory => Box::new(memory::MemoryStore::default()),
+        Backend::Redis => Box::new(redis::RedisStore::connect(location).await?),
+    })
+}
+
+/// Creates a profile for a connection that has just identified. Returns it
+/// with its first resume token.
+pub async fn create_session(
+    store: &dyn SessionStore,
+    username: &str,
+    pem: &str,
+    addr: &str,
+) -> Result<(User, String), String> {
+    let mut user = User::new(username, pem, addr);
+    let token = user.issue_token();
+    store.create(&user).await?;
+    Ok((user, token))
+}
+
+/// The profile a resume token was issued for, if the token is still the
+/// latest one.
+pub async fn find_session(store: &dyn SessionStore, token: &str) -> Result<Option<User>, String> {
+    let Some((uuid, secret)) = token.split_once('.') else {
+        return Ok(None);
+    };
+    let Some(user) = store.load(uuid).await? else {
+        return Ok(None);
+    };
+    let digest = hex::encode(Sha256::digest(secret));
+    Ok((!user.resume.is_empty() && user.resume == digest).then_some(user))
+}
+
+/// Issues a new resume token for a profile that has just been taken back,
+/// so the old one cannot be used twice.
+pub async fn renew_session(
+    store: &dyn SessionStore,
+    user: &mut User,
+    addr: &str,
+) -> Result<String, String> {
+    let token = user.issue_token();
+    user.addr = addr.to_string();
+    store.update(user).await?;
+    Ok(token)
+}
+
+/// Writes the nickname a connection ended up with back to its profile.
+pub async fn flush_session(
+    store: &dyn SessionStore,
+    uuid: &str,
+    username: &str,
+) -> Result<(), String> {
+    let Some(mut user) = store.load(uuid).await? else {
+        return Err(format!("{uuid}: no such session"));
+    };
+    if user.username == username {
+        return Ok(());
+    }
+    user.username = username.to_string();
+    store.update(&user).await
+}
+
+/// Flags or clears a profile as permanently banned.
+pub async fn mark_banned(store: &dyn SessionStore, uuid: &str, banned: bool) -> Result<(), String> {
+    let Some(mut user) = store.load(uuid).await? else {
+        return Err(format!("{uuid}: no such session"));
+    };
+    if user.banned == banned {
+        return Ok(());
+    }
+    user.banned = banned;
+    store.update(&user).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::directory::DirectoryStore;
+    use super::memory::MemoryStore;
+    use super::redis::RedisStore;
+    use super::*;
+
+    /// What every backend has to agree on. Names are made unique so a shared
+    /// Redis can be used more than once.
+    async fn contract(store: &dyn SessionStore) {
+        let (mut user, token) = create_session(store, "doğan", "pem", "127.0.0.1:1")
+            .await
+            .unwrap();
+        assert!(store.create(&user).await.is_err());
+        let loaded = store.load(&user.uuid).await.unwrap().unwrap();
+        assert_eq!(loaded.username, "doğan");
+        assert_eq!(loaded.pem, "pem");
+        let found = find_session(store, &token).await.unwrap();
+        assert_eq!(found.map(|found| found.uuid), Some(user.uuid.clone()));
+
+        let renewed = renew_session(store, &mut user, "127.0.0.1:2")
+            .await
+            .unwrap();
+        assert!(find_session(store, &token).await.unwrap().is_none());
+        assert!(find_session(store, &renewed).await.unwrap().is_some());
+        user.banned = true;
+        store.update(&user).await.unwrap();
+        let loaded = store.load(&user.uuid).await.unwrap().unwrap();
+        assert!(loaded.banned);
+        assert_eq!(loaded.addr, "127.0.0.1:2");
+        assert!(store.update(&User::new("nobody", "", "")).await.is_err());
+
+        let listed = store.list().await.unwrap();
+        assert!(listed.iter().any(|listed| listed.uuid == user.uuid));
+        store.delete(&user.uuid).await.unwrap();
+        store.delete(&user.uuid).await.unwrap();
+        assert!(store.load(&user.uuid).await.unwrap().is_none());
+        let listed = store.list().await.unwrap();
+        assert!(!listed.iter().any(|listed| listed.uuid == user.uuid));
+
+        let name = format!("Doğan-{}", &user.uuid[..8]);
+        let account = Account::new(&name, "", "fingerprint", &user.uuid)
+            .await
+            .unwrap();
+        store.create_account(&account).await.unwrap();
+        assert!(store.create_account(&account).await.is_err());
+        let loaded = store
+            .load_account(&name.to_uppercase())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(loaded.name, name.to_lowercase());
+        assert!(loaded.owns_key("fingerprint"));
+        assert_eq!(loaded.owner, user.uuid);
+        let missing = format!("nobody-{}", &user.uuid[..8]);
+        assert!(store.load_account(&missing).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn memory_store() {
+        contract(&MemoryStore::default()).await;
+    }
+
+    #[tokio::test]
+    async fn directory_store() {
+        let folder = std::env::temp_dir().join(format!("rustirc-{}", uuid::Uuid::new_v4()));
+        let folder = folder.to_string_lossy().to_string();
+        let store = DirectoryStore::open(&folder).await.unwrap();
+        contract(&store).await;
+        std::fs::write(format!("{folder}/damaged.dat"), "not hex").unwrap();
+        assert!(store.list().await.unwrap().is_empty());
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Redis server at REDIS_URL"]
+    async fn redis_store() {
+        contract(&RedisStore::connect(REDIS_URL).await.unwrap()).await;
+    }
+}
@@ -0,0 +1,138 @@
+use super::{SessionStore, User};
+use crate::account::Account;
+use async_trait::async_trait;
+use redis::aio::MultiplexedConnection;
+use redis::{AsyncCommands, ExistenceCheck, SetOptions};
+
+const KEY_PREFIX: &str = "rustirc:session:";
+const INDEX_KEY: &str = "rustirc:sessions";
+const ACCOUNT_PREFIX: &str = "rustirc:account:";
+
+/// Profiles as JSON strings under `rustirc:session:{uuid}`, with the set
+/// `rustirc:sessions` indexing every UUID so they can be listed without a
+/// keyspace scan. Accounts live under `rustirc:account:{nickname}`.
+pub struct RedisStore {
+    connection: MultiplexedConnection,
+}
+
+impl RedisStore {
+    pub async fn connect(url: &str) -> Result<Self, String> {
+        let client = redis::Client::open(url).map_err(|e| format!("{url}: {e}"))?;
+        let connection = client
+            .get_multiplexed_tokio_connection()
+            .await
+            .map_err(|e| format!("{url}: {e}"))?;
+        Ok(Self { connection })
+    }
+
+    fn key(uuid: &str) -> String {
+        format!("{KEY_PREFIX}{uuid}")
+    }
+
+    async fn set(&self, user: &User, check: ExistenceCheck) -> Result<bool, String> {
+        let user_json = serde_json::to_string(user).map_err(|e| e.to_string())?;
+        let mut connection = self.connection.clone();
+        let stored: Option<String> = connection
+            .set_options(
+                Self::key(&user.uuid),
+                user_json,
+                SetOptions::default().conditional_set(check),
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(stored.is_some())
+    }
+}
+
+#[async_trait]
+impl SessionStore for RedisStore {
+    async fn create(&self, user: &User) -> Result<(), String> {
+        if !self.set(user, ExistenceCheck::NX).await? {
+            return Err(format!("{}: session already exists", user.uuid));
+        }
+        let mut connection = self.connection.clone();
+        connection
+            .sadd::<_, _, ()>(INDEX_KEY, &user.uuid)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn load(&self, uuid: &str) -> Result<Option<User>, String> {
+        let mut connection = self.connection.clone();
+        let user_json: Option<String> = connection
+            .get(Self::key(uuid))
+            .await
+            .map_err(|e| e.to_string())?;
+        user_json
+            .map(|json| serde_json::from_str(&json).map_err(|e| format!("{uuid}: {e}")))
+            .transpose()
+    }
+
+    async fn update(&self, user: &User) -> Result<(), String> {
+        if !self.set(user, ExistenceCheck::XX).await? {
+            return Err(format!("{}: no such session", user.uuid));
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, uuid: &str) -> Result<(), String> {
+        let mut connection = self.connection.clone();
+        redis::pipe()
+            .atomic()
+            .del(Self::key(uuid))
+            .srem(INDEX_KEY, uuid)
+            .query_async::<()>(&mut connection)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn list(&self) -> Result<Vec<User>, String> {
+        let mut connection = self.connection.clone();
+        let uuids: Vec<String> = connection
+            .smembers(INDEX_KEY)
+            .await
+            .map_err(|e| e.to_string())?;
+        if uuids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let keys: Vec<String> = uuids.iter().map(|uuid| Self::key(uuid)).collect();
+        let users: Vec<Option<String>> = redis::cmd("MGET")
+            .arg(keys)
+            .query_async(&mut connection)
+            .await
+            .map_err(|e| e.to_string())?;
+        users
+            .into_iter()
+            .flatten()
+            .map(|json| serde_json::from_str(&json).map_err(|e| e.to_string()))
+            .collect()
+    }
+
+    async fn create_account(&self, account: &Account) -> Result<(), String> {
+        let account_json = serde_json::to_string(account).map_err(|e| e.to_string())?;
+        let mut connection = self.connection.clone();
+        let stored: Option<String> = connection
+            .set_options(
+                format!("{ACCOUNT_PREFIX}{}", account.name),
+                account_json,
+                SetOptions::default().conditional_set(ExistenceCheck::NX),
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+        match stored {
+            Some(_) => Ok(()),
+            None => Err(format!("{}: account already exists", account.name)),
+        }
+    }
+
+    async fn load_account(&self, name: &str) -> Result<Option<Account>, String> {
+        let mut connection = self.connection.clone();
+        let account_json: Option<String> = connection
+            .get(format!("{ACCOUNT_PREFIX}{}", name.to_lowercase()))
+            .await
+            .map_err(|e| e.to_string())?;
+        account_json
+            .map(|json| serde_json::from_str(&json).map_err(|e| format!("{name}: {e}")))
+            .transpose()
+    }
+}
@@ -0,0 +1,754 @@
+mod moderation;
+pub mod queue;
+mod remote;
+pub mod resume;
+
+use crate::account::{guest_name, DEFAULT_NICK_GRACE};
+use crate::admission::{self, Admission};
+use crate::cluster::{Event, Relay};
+use crate::flood::{self, Flood};
+use crate::history::{History, DEFAULT_REPLAY};
+use crate::irc::NICKSERV;
+use crate::moderation::Moderation;
+use crate::oper::{Operators, Role};
+use crate::session::SessionStore;
+use crate::util::log::{log, LogType};
+use chrono::{DateTime, Local};
+use protocol::{
+    Departure, Frame, FrameLimits, Membership, Message, Presence, QuitReason, Roster, Status,
+};
+use queue::{Outbox, Overflow, Packet, Queue};
+use resume::{Suspended, DEFAULT_RESUME_GRACE};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, OnceLock, RwLock, RwLockReadGuard};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+pub const SERVER_NAME: &str = "rustirc";
+pub const SERVER_VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const SHUTDOWN_MESSAGE: &str = "Server shutting down";
+/// Guest nicknames tried before a rename off a registered one is put off.
+const GUEST_ATTEMPTS: usize = 5;
+
+/// What can change while the server runs, on a configuration reload.
+pub struct Settings {
+    pub operators: Operators,
+    pub limits: FrameLimits,
+    pub flood: flood::Limits,
+    pub admission: admission::Limits,
+    /// Messages per channel kept for replay.
+    pub replay: usize,
+    /// How long a dropped connection's user is kept online for it to resume.
+    pub resume_grace: Duration,
+    /// How long a user may hold a registered nickname without logging in.
+    pub nick_grace: Duration,
+    /// The message of the day, one entry per line.
+    pub motd: Vec<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            operators: Operators::default(),
+            limits: FrameLimits::default(),
+            flood: flood::Limits::default(),
+            admission: admission::Limits::default(),
+            replay: DEFAULT_REPLAY,
+            resume_grace: Duration::from_secs(DEFAULT_RESUME_GRACE),
+            nick_grace: Duration::from_secs(DEFAULT_NICK_GRACE),
+            motd: Vec::new(),
+        }
+    }
+}
+
+struct Channel {
+    members: HashMap<String, String>,
+}
+
+/// How frames reach a user: through its own queue, or through whichever
+/// node it is connected to.
+#[derive(Clone)]
+enum Route {
+    Local(Outbox),
+    /// On the listener with this node id, sharing the same Redis.
+    Cluster(String),
+    /// Somewhere behind the named server link.
+    Link(String),
+}
+
+struct Connected {
+    username: String,
+    away: String,
+    route: Route,
+    role: Option<Role>,
+    /// The account the user logged into, by nickname.
+    account: Option<String>,
+    /// Only known for users connected here.
+    ip: String,
+    key: String,
+    /// When it registered, in milliseconds since the epoch.
+    since: i64,
+}
+
+impl Connected {
+    fn is_local(&self) -> bool {
+        matches!(self.route, Route::Local(_))
+    }
+
+    /// Queues the packet if the user is connected here. Remote users are
+    /// served by their own node, which sees the same event.
+    fn push(&self, packet: &Arc<Packet>) {
+        if let Route::Local(outbox) = &self.route {
+            outbox.push(Arc::clone(packet));
+        }
+    }
+
+    fn presence(&self, uuid: &str) -> Presence {
+        Presence {
+            username: self.username.clone(),
+            uuid: uuid.to_string(),
+            status: if self.away.is_empty() {
+                Status::Online
+            } else {
+                Status::Away
+            },
+            away: self.away.clone(),
+            operator: self.role.is_some(),
+            previous: String::new(),
+        }
+    }
+}
+
+pub struct Server {
+    pub name: String,
+    pub created: DateTime<Local>,
+    queue_size: usize,
+    overflow: Overflow,
+    channels: Mutex<HashMap<String, Channel>>,
+    users: Mutex<HashMap<String, Connected>>,
+    history: History,
+    store: Box<dyn SessionStore>,
+    moderation: Moderation,
+    settings: RwLock<Settings>,
+    pub flood: Flood,
+    pub admission: Admission,
+    suspended: Mutex<HashMap<String, Suspended>>,
+    relay: OnceLock<Relay>,
+    links: Mutex<HashMap<String, mpsc::UnboundedSender<Event>>>,
+    servers: Mutex<HashMap<String, String>>,
+    stopping: CancellationToken,
+}
+
+impl Server {
+    pub fn new(
+        queue_size: usize,
+        overflow: Overflow,
+        history: History,
+        store: Box<dyn SessionStore>,
+        moderation: Moderation,
+    ) -> Self {
+        Self {
+            name: SERVER_NAME.to_string(),
+            created: Local::now(),
+            queue_size,
+            overflow,
+            channels: Mutex::new(HashMap::new()),
+            users: Mutex::new(HashMap::new()),
+            history,
+            store,
+            moderation,
+            settings: RwLock::new(Settings::default()),
+            flood: Flood::default(),
+            admission: Admission::default(),
+            suspended: Mutex::new(HashMap::new()),
+            relay: OnceLock::new(),
+            links: Mutex::new(HashMap::new()),
+            servers: Mutex::new(HashMap::new()),
+            stopping: CancellationToken::new(),
+        }
+    }
+
+    pub fn settings(&self) -> RwLockReadGuard<'_, Settings> {
+        self.settings.read().unwrap()
+    }
+
+    /// Swaps in new settings. Open connections keep the frame limits they
+    /// started with; everything else applies right away.
+    pub fn reconfigure(&self, settings: Settings) {
+        self.flood.reconfigure(settings.flood);
+        self.admission.reconfigure(settings.admission);
+        self.history.resize(settings.replay);
+        *self.settings.write().unwrap() = settings;
+    }
+
+    /// Starts announcing local changes to the other nodes.
+    pub fn link(&self, relay: Relay) {
+        let _ = self.relay.set(relay);
+    }
+
+    /// Announces a local change to the cluster and to every linked server.
+    fn relay(&self, event: Event) {
+        self.forward("", &event);
+        if let Some(relay) = self.relay.get() {
+            relay.send(event);
+        }
+    }
+
+    /// Tells every connection handler to say goodbye and wind down.
+    pub fn shutdown(&self) {
+        self.stopping.cancel();
+    }
+
+    /// Resolves once `shutdown` has been called.
+    pub async fn stopping(&self) {
+        self.stopping.cancelled().await
+    }
+
+    /// Where user profiles are persisted.
+    pub fn store(&self) -> &dyn SessionStore {
+        self.store.as_ref()
+    }
+
+    /// The UUID and current nickname of every connection identified on
+    /// this node.
+    pub fn connected(&self) -> Vec<(String, String)> {
+        let users = self.users.lock().unwrap();
+        users
+            .iter()
+            .filter(|(_, user)| user.is_local())
+            .map(|(uuid, user)| (uuid.clone(), user.username.clone()))
+            .collect()
+    }
+
+    /// A fresh outbound queue sized by the server's overflow settings.
+    pub fn outbox(&self) -> Outbox {
+        Queue::new(self.queue_size, self.overflow)
+    }
+
+    /// Whether a user other than `uuid` goes by `username`. Nicknames are
+    /// compared the way [`Server::lookup`] resolves them.
+    pub fn taken(&self, username: &str, uuid: &str) -> bool {
+        held(&self.users.lock().unwrap(), username, uuid)
+    }
+
+    /// Makes an identified connection reachable for channel and private
+    /// messages. `key` is the fingerprint of the key it proved, if any.
+    /// Returns `false`, registering nothing, if the nickname is taken.
+    pub fn register(
+        &self,
+        uuid: &str,
+        username: &str,
+        ip: &str,
+        key: &str,
+        outbox: Outbox,
+    ) -> bool {
+        let mut users = self.users.lock().unwrap();
+        if held(&users, username, uuid) {
+            return false;
+        }
+        let since = Local::now().timestamp_millis();
+        users.insert(
+            uuid.to_string(),
+            Connected {
+                username: username.to_string(),
+                away: String::new(),
+                route: Route::Local(outbox),
+                role: None,
+                account: None,
+                ip: ip.to_string(),
+                key: key.to_string(),
+                since,
+            },
+        );
+        drop(users);
+        self.relay(Event::Register {
+            uuid: uuid.to_string(),
+            username: username.to_string(),
+            away: String::new(),
+            since,
+        });
+        true
+    }
+
+    /// Forgets the connection, drops it from every channel and tells each user
+    /// who shared one of them, once, why it left.
+    pub fn unregister(&self, uuid: &str, reason: QuitReason, message: &str) {
+        if self.forget(uuid, reason, message) {
+            self.relay(Event::Unregister {
+                uuid: uuid.to_string(),
+                reason,
+                message: message.to_string(),
+            });
+        }
+    }
+
+    fn forget(&self, uuid: &str, reason: QuitReason, message: &str) -> bool {
+        let Some(user) = self.users.lock().unwrap().remove(uuid) else {
+            return false;
+        };
+        self.suspended.lock().unwrap().remove(uuid);
+        let peers = self.leave_all(uuid);
+        let frame = Frame::Uquit(Departure {
+            username: user.username,
+            uuid: uuid.to_string(),
+            reason,
+            message: message.to_string(),
+        });
+        self.fan_out(&peers, frame);
+        true
+    }
+
+    /// Sets or, with an empty message, clears the away message and tells the
+    /// user and everyone sharing a channel with it.
+    pub fn set_away(&self, uuid: &str, message: &str) {
+        if self.mark_away(uuid, message) {
+            self.relay(Event::Away {
+                uuid: uuid.to_string(),
+                message: message.to_string(),
+            });
+        }
+    }
+
+    fn mark_away(&self, uuid: &str, message: &str) -> bool {
+        self.present(uuid, |user| user.away = message.to_string())
+    }
+
+    /// Grants the user a role and shows it to everyone sharing a channel
+    /// with it.
+    pub fn oper(&self, uuid: &str, role: Role) {
+        if self.promote(uuid, role) {
+            self.relay(Event::Oper {
+                uuid: uuid.to_string(),
+                role,
+            });
+        }
+    }
+
+    fn promote(&self, uuid: &str, role: Role) -> bool {
+        self.present(uuid, |user| user.role = Some(role))
+    }
+
+    /// Marks the user as logged into the account.
+    pub fn login(&self, uuid: &str, account: &str) {
+        if let Some(user) = self.users.lock().unwrap().get_mut(uuid) {
+            user.account = Some(account.to_lowercase());
+        }
+    }
+
+    /// The account the user is logged into, if any.
+    pub fn account(&self, uuid: &str) -> Option<String> {
+        let users = self.users.lock().unwrap();
+        users.get(uuid).and_then(|user| user.account.clone())
+    }
+
+    /// The role a user was granted, if any.
+    pub fn role(&self, uuid: &str) -> Option<Role> {
+        let users = self.users.lock().unwrap();
+        users.get(uuid).and_then(|user| user.role)
+    }
+
+    /// Applies a change to the user's presence and sends the result to the
+    /// user and its peers.
+    fn present(&self, uuid: &str, change: impl FnOnce(&mut Connected)) -> bool {
+        let peers = self.peers(uuid);
+        let mut users = self.users.lock().unwrap();
+        let Some(user) = users.get_mut(uuid) else {
+            return false;
+        };
+        change(user);
+        let presence = user.presence(uuid);
+        announce(&users, uuid, &peers, presence);
+        true
+    }
+
+    pub fn away(&self, uuid: &str) -> Option<String> {
+        let users = self.users.lock().unwrap();
+        users
+            .get(uuid)
+            .map(|user| user.away.clone())
+            .filter(|away| !away.is_empty())
+    }
+
+    /// Changes the nickname and shows the new one to everyone sharing a
+    /// channel with the user. Returns `false` if the nickname is taken.
+    pub fn rename(&self, uuid: &str, username: &str) -> bool {
+        if !self.change_name(uuid, username) {
+            return false;
+        }
+        self.relay(Event::Rename {
+            uuid: uuid.to_string(),
+            username: username.to_string(),
+        });
+        true
+    }
+
+    /// Renames the user to the first free guest nickname. Returns `None` if
+    /// every one tried was taken, or the user is gone.
+    pub fn rename_guest(&self, uuid: &str) -> Option<String> {
+        (0..GUEST_ATTEMPTS)
+            .map(|_| guest_name())
+            .find(|guest| self.rename(uuid, guest))
+    }
+
+    /// The nickname is checked and claimed under one lock, so two users
+    /// can't both rename to the same free one.
+    fn change_name(&self, uuid: &str, username: &str) -> bool {
+        let peers = self.peers(uuid);
+        {
+            let mut users = self.users.lock().unwrap();
+            if held(&users, username, uuid) {
+                return false;
+            }
+            let Some(user) = users.get_mut(uuid) else {
+                return false;
+            };
+            let previous = std::mem::replace(&mut user.username, username.to_string());
+            let presence = Presence {
+                previous,
+                ..user.presence(uuid)
+            };
+            announce(&users, uuid, &peers, presence);
+        }
+        let mut channels = self.channels.lock().unwrap();
+        for entry in channels.values_mut() {
+            if let Some(member) = entry.members.get_mut(uuid) {
+                *member = username.to_string();
+            }
+        }
+        true
+    }
+
+    /// Resolves a target by UUID first, then by nickname. Nicknames are
+    /// unique among the users this node knows of.
+    pub fn lookup(&self, target: &str) -> Option<(String, String)> {
+        let users = self.users.lock().unwrap();
+        if let Some(user) = users.get(target) {
+            return Some((target.to_string(), user.username.clone()));
+        }
+        users
+            .iter()
+            .find(|(_, user)| user.username.eq_ignore_ascii_case(target))
+            .map(|(uuid, user)| (uuid.clone(), user.username.clone()))
+    }
+
+    pub fn deliver(&self, uuid: &str, frame: Frame) -> bool {
+        let users = self.users.lock().unwrap();
+        match users.get(uuid).map(|user| &user.route) {
+            Some(Route::Local(outbox)) => {
+                outbox.push(Packet::new(frame));
+                true
+            }
+            Some(route) => {
+                let route = route.clone();
+                drop(users);
+                self.route(
+                    &route,
+                    Event::Deliver {
+                        uuid: uuid.to_string(),
+                        frame: frame.to_string(),
+                    },
+                );
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Queues the frame for every listed user. It is rendered only once.
+    fn fan_out<'a>(&self, uuids: impl IntoIterator<Item = &'a String>, frame: Frame) {
+        let packet = Packet::new(frame);
+        let users = self.users.lock().unwrap();
+        for uuid in uuids {
+            if let Some(user) = users.get(uuid) {
+                user.push(&packet);
+            }
+        }
+    }
+
+    /// Adds the user to the channel, creating it on first join, and announces
+    /// the join to every member. Returns `false` if the user is already there.
+    pub fn join(&self, channel: &str, uuid: &str, username: &str) -> bool {
+        let joined = self.add_member(channel, uuid, username);
+        if joined {
+            self.relay(Event::Join {
+                channel: channel.to_lowercase(),
+                uuid: uuid.to_string(),
+                username: username.to_string(),
+            });
+        }
+        joined
+    }
+
+    fn add_member(&self, channel: &str, uuid: &str, username: &str) -> bool {
+        let channel = channel.to_lowercase();
+        let members: Vec<String> = {
+            let mut channels = self.channels.lock().unwrap();
+            let entry = channels.entry(channel.clone()).or_insert_with(|| Channel {
+                members: HashMap::new(),
+            });
+            if entry.members.contains_key(uuid) {
+                return false;
+            }
+            entry.members.insert(uuid.to_string(), username.to_string());
+            entry.members.keys().cloned().collect()
+        };
+        self.fan_out(&members, Frame::Join(Membership::new(&channel, username)));
+        true
+    }
+
+    /// Removes the user from the channel and announces the part to the
+    /// remaining members. Empty channels are dropped.
+    pub fn part(&self, channel: &str, uuid: &str, reason: &str) -> bool {
+        let parted = self.remove_member(channel, uuid, reason);
+        if parted {
+            self.relay(Event::Part {
+                channel: channel.to_lowercase(),
+                uuid: uuid.to_string(),
+                reason: reason.to_string(),
+            });
+        }
+        parted
+    }
+
+    fn remove_member(&self, channel: &str, uuid: &str, reason: &str) -> bool {
+        let channel = channel.to_lowercase();
+        let (username, members): (String, Vec<String>) = {
+            let mut channels = self.channels.lock().unwrap();
+            let Some(entry) = channels.get_mut(&channel) else {
+                return false;
+            };
+            let Some(username) = entry.members.remove(uuid) else {
+                return false;
+            };
+            let members = entry.members.keys().cloned().collect();
+            if entry.members.is_empty() {
+                channels.remove(&channel);
+            }
+            (username, members)
+        };
+        self.fan_out(
+            &members,
+            Frame::Part(Membership::new(&channel, &username).because(reason)),
+        );
+        true
+    }
+
+    /// Everyone other than the user on at least one of its channels.
+    fn peers(&self, uuid: &str) -> HashSet<String> {
+        let channels = self.channels.lock().unwrap();
+        channels
+            .values()
+            .filter(|entry| entry.members.contains_key(uuid))
+            .flat_map(|entry| entry.members.keys())
+            .filter(|member| *member != uuid)
+            .cloned()
+            .collect()
+    }
+
+    /// Removes the user from every channel and returns whoever is left in
+    /// the channels it was on.
+    fn leave_all(&self, uuid: &str) -> HashSet<String> {
+        let mut peers = HashSet::new();
+        let mut channels = self.channels.lock().unwrap();
+        channels.retain(|_, entry| {
+            if entry.members.remove(uuid).is_some() {
+                peers.extend(entry.members.keys().cloned());
+            }
+            !entry.members.is_empty()
+        });
+        peers
+    }
+
+    pub fn send(&self, channel: &str, frame: Frame) -> bool {
+        let members: Vec<String> = {
+            let channels = self.channels.lock().unwrap();
+            match channels.get(&channel.to_lowercase()) {
+                Some(entry) => entry.members.keys().cloned().collect(),
+                None => return false,
+            }
+        };
+        self.fan_out(&members, frame);
+        true
+    }
+
+    /// Records a channel message and sends it, stamped, to every member.
+    pub fn publish(&self, message: Message) -> bool {
+        if !self.exists(&message.channel) {
+            return false;
+        }
+        let message = match self.history.record(message.clone()) {
+            Ok(message) => message,
+            Err(e) => {
+                log(
+                    format!("Mesaj geçmişe kaydedilirken bir hata oluştu: {e}"),
+                    LogType::ERROR,
+                );
+                message
+            }
+        };
+        self.relay(Event::Message {
+            message: message.clone(),
+        });
+        let channel = message.channel.clone();
+        self.send(&channel, Frame::Umsg(message))
+    }
+
+    /// The most recent messages of a channel, oldest first.
+    pub fn history(&self, channel: &str) -> Vec<Message> {
+        self.history.recent(channel)
+    }
+
+    pub fn exists(&self, channel: &str) -> bool {
+        let channels = self.channels.lock().unwrap();
+        channels.contains_key(&channel.to_lowercase())
+    }
+
+    /// Lists the members of a channel, or every connected user when `channel`
+    /// is empty, sorted by nickname.
+    pub fn roster(&self, channel: &str) -> Roster {
+        let channel = channel.to_lowercase();
+        let members: Option<Vec<String>> = if channel.is_empty() {
+            None
+        } else {
+            let channels = self.channels.lock().unwrap();
+            Some(
+                channels
+                    .get(&channel)
+                    .map(|entry| entry.members.keys().cloned().collect())
+                    .unwrap_or_default(),
+            )
+        };
+        let users = self.users.lock().unwrap();
+        let mut list: Vec<Presence> = match members {
+            None => users
+                .iter()
+                .map(|(uuid, user)| user.presence(uuid))
+                .collect(),
+            Some(members) => members
+                .iter()
+                .filter_map(|uuid| users.get(uuid).map(|user| user.presence(uuid)))
+                .collect(),
+        };
+        list.sort_by_key(|user| user.username.to_lowercase());
+        Roster {
+            channel,
+            users: list,
+        }
+    }
+}
+
+/// Sends the user's presence to it and to everyone sharing a channel with it.
+fn announce(
+    users: &HashMap<String, Connected>,
+    uuid: &str,
+    peers: &HashSet<String>,
+    presence: Presence,
+) {
+    let packet = Packet::new(Frame::Presence(presence));
+    for peer in peers.iter().map(String::as_str).chain([uuid]) {
+        if let Some(user) = users.get(peer) {
+            user.push(&packet);
+        }
+    }
+}
+
+#[cfg(test)]
+impl Server {
+    /// A server keeping history and profiles in memory, with bans written to
+    /// a file of its own under the temporary directory.
+    pub fn scratch() -> Self {
+        let bans = std::env::temp_dir()
+            .join(format!("rustirc-{}", uuid::Uuid::new_v4()))
+            .join("bans.json");
+        Self::new(
+            queue::DEFAULT_CAPACITY,
+            Overflow::DropOldest,
+            History::memory(DEFAULT_REPLAY),
+            Box::new(crate::session::memory::MemoryStore::default()),
+            Moderation::open(&bans.to_string_lossy()).unwrap(),
+        )
+    }
+}
+
+/// Whether a user other than `uuid` goes by `username`.
+fn held(users: &HashMap<String, Connected>, username: &str, uuid: &str) -> bool {
+    users
+        .iter()
+        .any(|(holder, user)| holder != uuid && user.username.eq_ignore_ascii_case(username))
+}
+
+pub fn valid_channel(channel: &str) -> bool {
+    channel.len() > 1
+        && channel.len() <= 50
+        && channel.starts_with('#')
+        && !channel.contains([' ', ',', '\x07'])
+}
+
+/// Channel prefixes, characters IRC gives a meaning to, control characters
+/// and the names of services are refused, so the nickname can be routed to
+/// and shown on both fronts.
+pub fn valid_nick(nick: &str) -> bool {
+    !nick.is_empty()
+        && nick.len() <= 32
+        && !nick.starts_with(['#', ':'])
+        && !nick.eq_ignore_ascii_case(NICKSERV)
+        && !nick.chars().any(|c| {
+            c.is_control() || c.is_whitespace() || matches!(c, ',' | '!' | '@' | '*' | '?')
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nicknames_are_checked_for_shape() {
+        for nick in ["alice", "Doğan", "[bot]", "a_b-c", "Guest01234"] {
+            assert!(valid_nick(nick), "{nick} should be accepted");
+        }
+        let long = "a".repeat(33);
+        for nick in [
+            "",
+            "#rust",
+            ":alice",
+            "al ice",
+            "al\tice",
+            "alice\r\n",
+            "a,b",
+            "a!b",
+            "a@b",
+            "a*",
+            "NickServ",
+            "nickserv",
+            &long,
+        ] {
+            assert!(!valid_nick(nick), "{nick:?} should be refused");
+        }
+    }
+
+    #[test]
+    fn logins_stay_with_the_user() {
+        let server = Server::scratch();
+        assert!(server.register("u1", "alice", "", "", server.outbox()));
+        assert_eq!(server.account("u1"), None);
+        server.login("u1", "Alice");
+        assert_eq!(server.account("u1").as_deref(), Some("alice"));
+        assert!(server.rename("u1", "alice2"));
+        assert_eq!(server.account("u1").as_deref(), Some("alice"));
+        server.login("gone", "bob");
+        assert_eq!(server.account("gone"), None);
+    }
+
+    #[test]
+    fn enforcement_moves_the_user_to_a_free_guest_nickname() {
+        let server = Server::scratch();
+        assert!(server.register("u1", "alice", "", "", server.outbox()));
+        let guest = server.rename_guest("u1").unwrap();
+        assert!(guest.starts_with("Guest"));
+        assert_eq!(server.lookup(&guest).unwrap().0, "u1");
+        assert!(!server.taken("alice", ""));
+        assert!(server.register("u2", "alice", "", "", server.outbox()));
+        assert_eq!(server.rename_guest("gone"), None);
+    }
+}
@@ -0,0 +1,203 @@
+use super::queue::Packet;
+use super::{Connected, Route, Server};
+use crate::cluster::Event;
+use crate::moderation::{Ban, Subject};
+use crate::oper::Role;
+use crate::util::log::{log, LogType};
+use protocol::{BanKind, Frame, QuitReason, Sanction};
+
+impl Server {
+    /// The ban in force, if any, that keeps the subject out.
+    pub fn banned(&self, subject: &Subject) -> Option<Ban> {
+        self.moderation.find(subject)
+    }
+
+    /// The UUID, IP address and key fingerprint of a user connected here,
+    /// found by UUID or nickname.
+    pub fn origin(&self, target: &str) -> Option<(String, String, String)> {
+        let (uuid, _) = self.lookup(target)?;
+        let users = self.users.lock().unwrap();
+        let user = users.get(&uuid).filter(|user| user.is_local())?;
+        Some((uuid, user.ip.clone(), user.key.clone()))
+    }
+
+    /// Disconnects the user, asking the node it is connected to if that is
+    /// not this one.
+    pub fn kick(&self, uuid: &str, sanction: Sanction) {
+        if !self.disconnect(uuid, &sanction) {
+            self.relay(Event::Kick {
+                uuid: uuid.to_string(),
+                sanction,
+            });
+        }
+    }
+
+    /// Stores the ban, announces it to the other nodes and disconnects the
+    /// users it matches here. Returns how many were disconnected.
+    pub fn ban(&self, ban: Ban) -> Result<usize, String> {
+        self.moderation.ban(ban.clone())?;
+        self.relay(Event::Ban { ban: ban.clone() });
+        Ok(self.enforce(&ban))
+    }
+
+    /// Whether the ban would catch a user, here or on another node, who
+    /// holds `role` or a higher one.
+    pub fn shields(&self, ban: &Ban, role: Role) -> bool {
+        let users = self.users.lock().unwrap();
+        users.iter().any(|(uuid, user)| {
+            user.role.is_some_and(|theirs| theirs >= role) && ban.matches(&subject(uuid, user))
+        })
+    }
+
+    /// Lifts the ban here and on the other nodes. Returns `false` if this
+    /// node did not know about it.
+    pub fn unban(&self, kind: BanKind, mask: &str) -> Result<bool, String> {
+        let lifted = self.moderation.unban(kind, mask)?;
+        self.relay(Event::Unban {
+            kind,
+            mask: mask.to_string(),
+        });
+        Ok(lifted)
+    }
+
+    /// Makes the user read-only until the sanction runs out.
+    pub fn mute(&self, uuid: &str, sanction: Sanction) {
+        self.silence(uuid, sanction.clone());
+        self.relay(Event::Mute {
+            uuid: uuid.to_string(),
+            sanction,
+        });
+    }
+
+    /// Returns `false` if the user was not muted.
+    pub fn unmute(&self, uuid: &str) -> bool {
+        let released = self.release(uuid);
+        self.relay(Event::Unmute {
+            uuid: uuid.to_string(),
+        });
+        released
+    }
+
+    pub fn muted(&self, uuid: &str) -> bool {
+        self.moderation.muted(uuid)
+    }
+
+    pub(super) fn silence(&self, uuid: &str, sanction: Sanction) {
+        self.moderation.mute(uuid, sanction.until.clone());
+        self.push_local(uuid, Frame::Muted(sanction));
+    }
+
+    pub(super) fn release(&self, uuid: &str) -> bool {
+        let released = self.moderation.unmute(uuid);
+        if released {
+            self.push_local(uuid, Frame::Ok(String::from("You are no longer muted.")));
+        }
+        released
+    }
+
+    fn push_local(&self, uuid: &str, frame: Frame) {
+        let users = self.users.lock().unwrap();
+        if let Some(user) = users.get(uuid) {
+            user.push(&Packet::new(frame));
+        }
+    }
+
+    /// Sends the sanction to a user connected here, closes its queue and
+    /// tells everyone it is gone. Returns `false` for users connected
+    /// elsewhere.
+    pub(super) fn disconnect(&self, uuid: &str, sanction: &Sanction) -> bool {
+        let outbox = {
+            let users = self.users.lock().unwrap();
+            match users.get(uuid).map(|user| &user.route) {
+                Some(Route::Local(outbox)) => outbox.clone(),
+                _ => return false,
+            }
+        };
+        outbox.push(Packet::new(Frame::Kicked(sanction.clone())));
+        outbox.close();
+        let reason = if sanction.ban {
+            QuitReason::Banned
+        } else {
+            QuitReason::Kicked
+        };
+        let message = if sanction.reason.is_empty() {
+            format!("by {}", sanction.by)
+        } else {
+            format!("by {} ({})", sanction.by, sanction.reason)
+        };
+        self.unregister(uuid, reason, &message);
+        true
+    }
+
+    /// Disconnects every user connected here that the ban matches.
+    pub(super) fn enforce(&self, ban: &Ban) -> usize {
+        let matched: Vec<String> = {
+            let users = self.users.lock().unwrap();
+            users
+                .iter()
+                .filter(|(uuid, user)| user.is_local() && ban.matches(&subject(uuid, user)))
+                .map(|(uuid, _)| uuid.clone())
+                .collect()
+        };
+        for uuid in &matched {
+            log(
+                format!("{uuid} {} yasağı nedeniyle bağlantısı kesiliyor.", ban.kind),
+                LogType::WARN,
+            );
+            self.disconnect(uuid, &ban.sanction());
+        }
+        matched.len()
+    }
+}
+
+fn subject<'a>(uuid: &'a str, user: &'a Connected) -> Subject<'a> {
+    Subject {
+        uuid,
+        nick: &user.username,
+        ip: &user.ip,
+        key: &user.key,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::queue::Delivery;
+    use super::*;
+
+    fn ban(kind: BanKind, mask: &str) -> Ban {
+        Ban::new(kind, mask, "oper", "", None)
+    }
+
+    #[test]
+    fn bans_cannot_catch_equal_or_higher_roles() {
+        let server = Server::scratch();
+        assert!(server.register("owner", "Root", "10.0.0.1", "k1", server.outbox()));
+        assert!(server.register("plain", "Rooted", "10.0.0.2", "k2", server.outbox()));
+        server.oper("owner", Role::Owner);
+
+        let everyone = ban(BanKind::Nick, "roo*");
+        assert!(server.shields(&everyone, Role::Operator));
+        assert!(server.shields(&everyone, Role::Owner));
+        assert!(!server.shields(&ban(BanKind::Nick, "rooted"), Role::Operator));
+        assert!(server.shields(&ban(BanKind::Ip, "10.0.0.*"), Role::Operator));
+        assert!(!server.shields(&ban(BanKind::Ip, "10.0.0.2"), Role::Operator));
+        assert!(server.shields(&ban(BanKind::Key, "k1"), Role::Operator));
+        assert!(server.shields(&ban(BanKind::Uuid, "owner"), Role::Operator));
+    }
+
+    #[tokio::test]
+    async fn bans_disconnect_the_local_users_they_match() {
+        let server = Server::scratch();
+        let outbox = server.outbox();
+        assert!(server.register("a", "alice", "10.0.0.1", "", outbox.clone()));
+        assert!(server.register("b", "bob", "10.0.0.2", "", server.outbox()));
+        assert_eq!(server.enforce(&ban(BanKind::Nick, "al*")), 1);
+        assert!(matches!(
+            outbox.recv().await,
+            Delivery::Packet(packet) if matches!(packet.frame, Frame::Kicked(_))
+        ));
+        assert!(matches!(outbox.recv().await, Delivery::Closed));
+        assert!(server.lookup("alice").is_none());
+        assert!(server.lookup("bob").is_some());
+    }
+}
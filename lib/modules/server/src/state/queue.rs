@@ -0,0 +1,239 @@
+use clap::ValueEnum;
+use protocol::Frame;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+pub const DEFAULT_CAPACITY: usize = 256;
+
+/// What happens when a connection cannot keep up with what is sent to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Overflow {
+    /// Forget the oldest queued frames and tell the client how many it lost.
+    DropOldest,
+    /// Drop the connection.
+    Disconnect,
+}
+
+/// A frame rendered once and shared by every queue it is pushed to.
+#[derive(Debug)]
+pub struct Packet {
+    pub frame: Frame,
+    pub line: Arc<str>,
+}
+
+impl Packet {
+    pub fn new(frame: Frame) -> Arc<Self> {
+        let line = Arc::from(frame.to_string());
+        Arc::new(Self { frame, line })
+    }
+}
+
+pub enum Delivery {
+    Packet(Arc<Packet>),
+    Gap(usize),
+    Overflow,
+    /// The queue was closed and everything pushed before that is written.
+    Closed,
+}
+
+#[derive(Default)]
+struct Inner {
+    packets: VecDeque<Arc<Packet>>,
+    /// Packets lost since the last gap was reported, counted on an
+    /// overflowed queue too in case a resumed session adopts it.
+    dropped: usize,
+    overflowed: bool,
+    closed: bool,
+}
+
+/// Bounded outbound queue of a single connection. Pushing never waits, so a
+/// slow reader can only ever hurt itself.
+pub struct Queue {
+    inner: Mutex<Inner>,
+    notify: Notify,
+    capacity: usize,
+    overflow: Overflow,
+}
+
+pub type Outbox = Arc<Queue>;
+
+impl Queue {
+    pub fn new(capacity: usize, overflow: Overflow) -> Outbox {
+        Arc::new(Self {
+            inner: Mutex::new(Inner::default()),
+            notify: Notify::new(),
+            capacity: capacity.max(1),
+            overflow,
+        })
+    }
+
+    pub fn push(&self, packet: Arc<Packet>) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.closed {
+            return;
+        }
+        if inner.overflowed {
+            inner.dropped += 1;
+            return;
+        }
+        if inner.packets.len() >= self.capacity {
+            match self.overflow {
+                Overflow::DropOldest => {
+                    inner.packets.pop_front();
+                    inner.dropped += 1;
+                }
+                Overflow::Disconnect => {
+                    inner.overflowed = true;
+                    inner.dropped += inner.packets.len() + 1;
+                    inner.packets.clear();
+                    drop(inner);
+                    self.notify.notify_one();
+                    return;
+                }
+            }
+        }
+        inner.packets.push_back(packet);
+        drop(inner);
+        self.notify.notify_one();
+    }
+
+    /// Moves everything still queued on `old` in front of this queue's own
+    /// packets and closes `old`. Whatever `old` lost, even by overflowing,
+    /// is reported here as a gap rather than ending the new connection.
+    pub fn adopt(&self, old: &Queue) {
+        let (mut packets, dropped) = {
+            let mut old = old.inner.lock().unwrap();
+            old.closed = true;
+            (
+                std::mem::take(&mut old.packets),
+                std::mem::take(&mut old.dropped),
+            )
+        };
+        old.notify.notify_one();
+        let mut inner = self.inner.lock().unwrap();
+        packets.append(&mut inner.packets);
+        while packets.len() > self.capacity {
+            packets.pop_front();
+            inner.dropped += 1;
+        }
+        inner.packets = packets;
+        inner.dropped += dropped;
+        drop(inner);
+        self.notify.notify_one();
+    }
+
+    /// Refuses further packets. Whatever is already queued is still handed
+    /// out, followed by `Delivery::Closed`.
+    pub fn close(&self) {
+        self.inner.lock().unwrap().closed = true;
+        self.notify.notify_one();
+    }
+
+    /// Waits for the next thing to write. A gap is reported before the first
+    /// packet that follows the dropped ones.
+    pub async fn recv(&self) -> Delivery {
+        loop {
+            {
+                let mut inner = self.inner.lock().unwrap();
+                if inner.overflowed {
+                    return Delivery::Overflow;
+                }
+                if inner.dropped > 0 {
+                    return Delivery::Gap(std::mem::take(&mut inner.dropped));
+                }
+                if let Some(packet) = inner.packets.pop_front() {
+                    return Delivery::Packet(packet);
+                }
+                if inner.closed {
+                    return Delivery::Closed;
+                }
+            }
+            self.notify.notified().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(text: &str) -> Arc<Packet> {
+        Packet::new(Frame::Ok(text.to_string()))
+    }
+
+    async fn line(queue: &Queue) -> String {
+        match queue.recv().await {
+            Delivery::Packet(packet) => packet.line.to_string(),
+            _ => panic!("expected a packet"),
+        }
+    }
+
+    #[tokio::test]
+    async fn dropping_the_oldest_reports_a_gap_first() {
+        let queue = Queue::new(2, Overflow::DropOldest);
+        for text in ["a", "b", "c", "d"] {
+            queue.push(packet(text));
+        }
+        assert!(matches!(queue.recv().await, Delivery::Gap(2)));
+        assert_eq!(line(&queue).await, "OK::c");
+        assert_eq!(line(&queue).await, "OK::d");
+        // The gap is reported once; what follows arrives in order.
+        queue.push(packet("e"));
+        assert_eq!(line(&queue).await, "OK::e");
+    }
+
+    #[tokio::test]
+    async fn a_full_queue_overflows_when_disconnecting() {
+        let queue = Queue::new(2, Overflow::Disconnect);
+        queue.push(packet("a"));
+        queue.push(packet("b"));
+        assert_eq!(line(&queue).await, "OK::a");
+        for text in ["c", "d", "e"] {
+            queue.push(packet(text));
+        }
+        assert!(matches!(queue.recv().await, Delivery::Overflow));
+        // Nothing pushed afterwards gets through.
+        queue.push(packet("f"));
+        assert!(matches!(queue.recv().await, Delivery::Overflow));
+    }
+
+    #[tokio::test]
+    async fn a_closed_queue_hands_out_what_is_left() {
+        let queue = Queue::new(2, Overflow::DropOldest);
+        queue.push(packet("a"));
+        queue.close();
+        queue.push(packet("b"));
+        assert_eq!(line(&queue).await, "OK::a");
+        assert!(matches!(queue.recv().await, Delivery::Closed));
+    }
+
+    #[tokio::test]
+    async fn adopting_carries_the_gap_over() {
+        let old = Queue::new(2, Overflow::DropOldest);
+        for text in ["a", "b", "c"] {
+            old.push(packet(text));
+        }
+        let new = Queue::new(2, Overflow::DropOldest);
+        new.push(packet("d"));
+        new.adopt(&old);
+        assert!(matches!(new.recv().await, Delivery::Gap(2)));
+        assert_eq!(line(&new).await, "OK::c");
+        assert_eq!(line(&new).await, "OK::d");
+        assert!(matches!(old.recv().await, Delivery::Closed));
+    }
+
+    #[tokio::test]
+    async fn adopting_an_overflowed_queue_reports_a_gap() {
+        let old = Queue::new(2, Overflow::Disconnect);
+        for text in ["a", "b", "c", "d"] {
+            old.push(packet(text));
+        }
+        assert!(matches!(old.recv().await, Delivery::Overflow));
+        let new = Queue::new(2, Overflow::Disconnect);
+        new.adopt(&old);
+        new.push(packet("e"));
+        assert!(matches!(new.recv().await, Delivery::Gap(4)));
+        assert_eq!(line(&new).await, "OK::e");
+    }
+}
@@ -0,0 +1,474 @@
+use super::queue::Packet;
+use super::{Connected, Route, Server};
+use crate::cluster::Event;
+use crate::util::log::{log, LogType};
+use protocol::{Frame, QuitReason, Sanction};
+use std::collections::HashSet;
+use tokio::sync::mpsc;
+
+const COLLISION: &str = "Nickname collision";
+
+impl Server {
+    /// Replays a change announced by another node of the cluster, without
+    /// announcing it again.
+    pub fn apply(&self, node: &str, event: Event) {
+        if let Event::Hello = event {
+            self.reannounce();
+            return;
+        }
+        self.replay(event, Route::Cluster(node.to_string()));
+    }
+
+    /// Announces every user connected here, with its channels, to the
+    /// cluster.
+    pub fn reannounce(&self) {
+        let Some(relay) = self.relay.get() else {
+            return;
+        };
+        for event in self.snapshot(|route| matches!(route, Route::Local(_))) {
+            relay.send(event);
+        }
+    }
+
+    /// Forgets the users of a cluster node that is gone, telling the local
+    /// users they split off. Returns how many there were.
+    pub fn drop_node(&self, node: &str) -> usize {
+        let lost: Vec<String> = {
+            let users = self.users.lock().unwrap();
+            users
+                .iter()
+                .filter(|(_, user)| matches!(&user.route, Route::Cluster(from) if from == node))
+                .map(|(uuid, _)| uuid.clone())
+                .collect()
+        };
+        let message = format!("{} {node}", self.name);
+        for uuid in &lost {
+            self.forget(uuid, QuitReason::Netsplit, &message);
+        }
+        lost.len()
+    }
+
+    /// Makes a server reachable through a freshly authenticated link. Fails
+    /// if the name is already known, which would close a loop.
+    pub fn attach(&self, name: &str, link: mpsc::UnboundedSender<Event>) -> Result<(), String> {
+        if name.eq_ignore_ascii_case(&self.name) {
+            return Err(format!("Server {name} already exists"));
+        }
+        let mut servers = self.servers.lock().unwrap();
+        if servers.contains_key(name) {
+            return Err(format!("Server {name} already exists"));
+        }
+        servers.insert(name.to_string(), name.to_string());
+        self.links.lock().unwrap().insert(name.to_string(), link);
+        Ok(())
+    }
+
+    /// What a newly linked server needs to learn about our side of the
+    /// network: the servers and users not behind that link, and their
+    /// channels.
+    pub fn burst(&self, link: &str) -> Vec<Event> {
+        let mut events: Vec<Event> = {
+            let servers = self.servers.lock().unwrap();
+            servers
+                .iter()
+                .filter(|(_, via)| *via != link)
+                .map(|(name, _)| Event::Server { name: name.clone() })
+                .collect()
+        };
+        events.extend(self.snapshot(|route| match route {
+            Route::Local(_) => true,
+            Route::Link(via) => via != link,
+            Route::Cluster(_) => false,
+        }));
+        events
+    }
+
+    /// Applies an event that arrived over a server link and passes it on to
+    /// the other links. Nothing crosses between links and the Redis cluster,
+    /// so a node should take part in one or the other.
+    pub fn receive(&self, link: &str, event: Event) -> Result<(), String> {
+        match &event {
+            Event::Server { name } => {
+                let mut servers = self.servers.lock().unwrap();
+                if name.eq_ignore_ascii_case(&self.name) || servers.contains_key(name) {
+                    return Err(format!("Server {name} already exists"));
+                }
+                servers.insert(name.clone(), link.to_string());
+            }
+            Event::Squit { name } => {
+                self.servers.lock().unwrap().remove(name);
+            }
+            Event::Deliver { .. } => {
+                self.replay(event, Route::Link(link.to_string()));
+                return Ok(());
+            }
+            _ => self.replay(event.clone(), Route::Link(link.to_string())),
+        }
+        self.forward(link, &event);
+        Ok(())
+    }
+
+    /// Drops a link along with every server and user behind it, telling the
+    /// local users and the rest of the network that they split off.
+    pub fn split(&self, link: &str) -> (usize, usize) {
+        if self.links.lock().unwrap().remove(link).is_none() {
+            return (0, 0);
+        }
+        let lost_servers: Vec<String> = {
+            let mut servers = self.servers.lock().unwrap();
+            let lost = servers
+                .iter()
+                .filter(|(_, via)| *via == link)
+                .map(|(name, _)| name.clone())
+                .collect();
+            servers.retain(|_, via| via != link);
+            lost
+        };
+        let lost_users: Vec<String> = {
+            let users = self.users.lock().unwrap();
+            users
+                .iter()
+                .filter(|(_, user)| matches!(&user.route, Route::Link(via) if via == link))
+                .map(|(uuid, _)| uuid.clone())
+                .collect()
+        };
+        let message = format!("{} {link}", self.name);
+        for uuid in &lost_users {
+            self.forget(uuid, QuitReason::Netsplit, &message);
+            self.forward(
+                link,
+                &Event::Unregister {
+                    uuid: uuid.clone(),
+                    reason: QuitReason::Netsplit,
+                    message: message.clone(),
+                },
+            );
+        }
+        for name in &lost_servers {
+            self.forward(link, &Event::Squit { name: name.clone() });
+        }
+        (lost_servers.len(), lost_users.len())
+    }
+
+    /// Sends the event to every linked server but the one it came from.
+    pub(super) fn forward(&self, from: &str, event: &Event) {
+        let links = self.links.lock().unwrap();
+        for (name, link) in links.iter() {
+            if name != from {
+                let _ = link.send(event.clone());
+            }
+        }
+    }
+
+    /// Sends an event meant for a single remote user the way it is reached.
+    pub(super) fn route(&self, route: &Route, event: Event) {
+        match route {
+            Route::Local(_) => {}
+            Route::Cluster(_) => {
+                if let Some(relay) = self.relay.get() {
+                    relay.send(event);
+                }
+            }
+            Route::Link(via) => {
+                if let Some(link) = self.links.lock().unwrap().get(via) {
+                    let _ = link.send(event);
+                }
+            }
+        }
+    }
+
+    fn replay(&self, event: Event, route: Route) {
+        match event {
+            Event::Hello | Event::Heartbeat | Event::Server { .. } | Event::Squit { .. } => {}
+            Event::Kick { uuid, sanction } => {
+                self.disconnect(&uuid, &sanction);
+            }
+            Event::Ban { ban } => {
+                if let Err(e) = self.moderation.ban(ban.clone()) {
+                    log(
+                        format!("Yasak kaydedilirken bir hata oluştu: {e}"),
+                        LogType::ERROR,
+                    );
+                }
+                self.enforce(&ban);
+            }
+            Event::Unban { kind, mask } => {
+                if let Err(e) = self.moderation.unban(kind, &mask) {
+                    log(
+                        format!("Yasak kaldırılırken bir hata oluştu: {e}"),
+                        LogType::ERROR,
+                    );
+                }
+            }
+            Event::Mute { uuid, sanction } => self.silence(&uuid, sanction),
+            Event::Unmute { uuid } => {
+                self.release(&uuid);
+            }
+            Event::Register {
+                uuid,
+                username,
+                away,
+                since,
+            } => {
+                if !self.settle(&uuid, &username, since) {
+                    return;
+                }
+                let mut users = self.users.lock().unwrap();
+                users.entry(uuid).or_insert(Connected {
+                    username,
+                    away,
+                    route,
+                    role: None,
+                    account: None,
+                    ip: String::new(),
+                    key: String::new(),
+                    since,
+                });
+            }
+            Event::Unregister {
+                uuid,
+                reason,
+                message,
+            } => {
+                self.forget(&uuid, reason, &message);
+            }
+            Event::Rename { uuid, username } => {
+                self.change_name(&uuid, &username);
+            }
+            Event::Away { uuid, message } => {
+                self.mark_away(&uuid, &message);
+            }
+            Event::Oper { uuid, role } => {
+                self.promote(&uuid, role);
+            }
+            Event::Join {
+                channel,
+                uuid,
+                username,
+            } => {
+                // Users that lost a nickname collision here are never added.
+                if self.users.lock().unwrap().contains_key(&uuid) {
+                    self.add_member(&channel, &uuid, &username);
+                }
+            }
+            Event::Part {
+                channel,
+                uuid,
+                reason,
+            } => {
+                self.remove_member(&channel, &uuid, &reason);
+            }
+            Event::Message { message } => {
+                if let Err(e) = self.history.append(&message) {
+                    log(
+                        format!("Mesaj geçmişe kaydedilirken bir hata oluştu: {e}"),
+                        LogType::ERROR,
+                    );
+                }
+                let channel = message.channel.clone();
+                self.send(&channel, Frame::Umsg(message));
+            }
+            Event::Deliver { uuid, frame } => {
+                let target = self
+                    .users
+                    .lock()
+                    .unwrap()
+                    .get(&uuid)
+                    .map(|user| user.route.clone());
+                match target {
+                    Some(Route::Local(outbox)) => match frame.parse::<Frame>() {
+                        Ok(frame) => outbox.push(Packet::new(frame)),
+                        Err(e) => log(
+                            format!("Başka bir sunucudan gelen çerçeve çözülemedi: {e}"),
+                            LogType::WARN,
+                        ),
+                    },
+                    Some(Route::Link(via)) if !matches!(&route, Route::Link(from) if *from == via) =>
+                    {
+                        self.route(&Route::Link(via), Event::Deliver { uuid, frame });
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Settles a remote user taking a nickname someone else holds the way
+    /// `register` does locally: the earlier registration keeps it. A local
+    /// holder that lost is disconnected, a remote one is forgotten and left
+    /// to its own server, which sees the same collision. Returns whether the
+    /// remote user may be added.
+    fn settle(&self, uuid: &str, username: &str, since: i64) -> bool {
+        let holder = {
+            let users = self.users.lock().unwrap();
+            users
+                .iter()
+                .find(|(holder, user)| {
+                    *holder != uuid && user.username.eq_ignore_ascii_case(username)
+                })
+                .map(|(holder, user)| (holder.clone(), user.since, user.is_local()))
+        };
+        let Some((holder, held_since, local)) = holder else {
+            return true;
+        };
+        if (held_since, holder.as_str()) <= (since, uuid) {
+            log(
+                format!("{username} takma adı için çakışma: önce kaydolan {holder} adı koruyor, {uuid} eklenmiyor."),
+                LogType::WARN,
+            );
+            return false;
+        }
+        log(
+            format!("{username} takma adı için çakışma: önce kaydolan {uuid} adı alıyor, {holder} ayrılıyor."),
+            LogType::WARN,
+        );
+        if local {
+            let sanction = Sanction {
+                by: self.name.clone(),
+                reason: String::from(COLLISION),
+                ban: false,
+                until: None,
+            };
+            self.disconnect(&holder, &sanction);
+        } else {
+            self.forget(&holder, QuitReason::Kicked, COLLISION);
+        }
+        true
+    }
+
+    /// Registrations and channel memberships of the users whose route
+    /// matches.
+    fn snapshot(&self, include: impl Fn(&Route) -> bool) -> Vec<Event> {
+        let mut events = Vec::new();
+        let mut known = HashSet::new();
+        {
+            let users = self.users.lock().unwrap();
+            for (uuid, user) in users.iter().filter(|(_, user)| include(&user.route)) {
+                known.insert(uuid.clone());
+                events.push(Event::Register {
+                    uuid: uuid.clone(),
+                    username: user.username.clone(),
+                    away: user.away.clone(),
+                    since: user.since,
+                });
+                if let Some(role) = user.role {
+                    events.push(Event::Oper {
+                        uuid: uuid.clone(),
+                        role,
+                    });
+                }
+            }
+        }
+        let channels = self.channels.lock().unwrap();
+        for (channel, entry) in channels.iter() {
+            for (uuid, username) in entry
+                .members
+                .iter()
+                .filter(|(uuid, _)| known.contains(*uuid))
+            {
+                events.push(Event::Join {
+                    channel: channel.clone(),
+                    uuid: uuid.clone(),
+                    username: username.clone(),
+                });
+            }
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::queue::Delivery;
+    use super::*;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    fn register(server: &Server, node: &str, uuid: &str, username: &str) {
+        announce(server, node, uuid, username, 0);
+    }
+
+    fn announce(server: &Server, node: &str, uuid: &str, username: &str, since: i64) {
+        let register = Event::Register {
+            uuid: uuid.to_string(),
+            username: username.to_string(),
+            away: String::new(),
+            since,
+        };
+        server.apply(node, register);
+        let join = Event::Join {
+            channel: String::from("#rust"),
+            uuid: uuid.to_string(),
+            username: username.to_string(),
+        };
+        server.apply(node, join);
+    }
+
+    #[tokio::test]
+    async fn a_lost_node_takes_only_its_own_users() {
+        let server = Server::scratch();
+        let outbox = server.outbox();
+        assert!(server.register("local", "alice", "", "", outbox.clone()));
+        server.join("#rust", "local", "alice");
+        register(&server, "node-a", "a1", "bob");
+        register(&server, "node-a", "a2", "carol");
+        register(&server, "node-b", "b1", "dave");
+        // Skip the joins announced so far.
+        let quiet = Duration::from_millis(10);
+        while let Ok(Delivery::Packet(_)) = timeout(quiet, outbox.recv()).await {}
+
+        assert_eq!(server.drop_node("node-a"), 2);
+        assert!(server.lookup("bob").is_none());
+        assert!(server.lookup("carol").is_none());
+        assert!(server.lookup("dave").is_some());
+        assert!(!server.taken("bob", ""));
+        for _ in 0..2 {
+            let Delivery::Packet(packet) = outbox.recv().await else {
+                panic!("expected a packet");
+            };
+            assert!(matches!(
+                &packet.frame,
+                Frame::Uquit(departure) if departure.reason == QuitReason::Netsplit
+            ));
+        }
+        assert_eq!(server.drop_node("node-a"), 0);
+    }
+
+    #[tokio::test]
+    async fn the_earlier_registration_wins_a_nickname_collision() {
+        let server = Server::scratch();
+        let outbox = server.outbox();
+        assert!(server.register("local", "alice", "", "", outbox.clone()));
+
+        // A user who registered later elsewhere does not get the nickname.
+        announce(&server, "node-a", "a1", "Alice", i64::MAX);
+        assert_eq!(server.lookup("alice").unwrap().0, "local");
+        assert!(server.lookup("a1").is_none());
+        assert!(!server.channels.lock().unwrap().contains_key("#rust"));
+
+        // One who registered earlier takes it and the local user is closed.
+        announce(&server, "node-b", "b1", "alice", 0);
+        assert_eq!(server.lookup("alice").unwrap().0, "b1");
+        assert!(matches!(
+            outbox.recv().await,
+            Delivery::Packet(packet)
+                if matches!(&packet.frame, Frame::Kicked(sanction) if sanction.reason == COLLISION)
+        ));
+        assert!(matches!(outbox.recv().await, Delivery::Closed));
+    }
+
+    #[test]
+    fn a_remote_holder_that_lost_is_forgotten() {
+        let server = Server::scratch();
+        announce(&server, "node-a", "a1", "bob", 10);
+        announce(&server, "node-b", "b1", "bob", 5);
+        assert!(server.lookup("a1").is_none());
+
+        // Heard the other way round, it ends the same.
+        let server = Server::scratch();
+        announce(&server, "node-b", "b1", "bob", 5);
+        announce(&server, "node-a", "a1", "bob", 10);
+        assert_eq!(server.lookup("bob").unwrap().0, "b1");
+        assert!(server.lookup("a1").is_none());
+    }
+}
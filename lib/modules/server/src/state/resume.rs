@@ -0,0 +1,110 @@
+use super::queue::Outbox;
+use super::{Route, Server};
+use crate::util::log::{log, LogType};
+use protocol::QuitReason;
+use std::sync::Arc;
+use std::time::Instant;
+
+pub const DEFAULT_RESUME_GRACE: u64 = 120;
+
+/// A user whose connection dropped, kept online in case it comes back.
+pub(super) struct Suspended {
+    until: Instant,
+    reason: QuitReason,
+    message: String,
+}
+
+pub enum Reclaim {
+    /// The connection took the session over, along with its nickname and
+    /// channels.
+    Resumed {
+        username: String,
+        channels: Vec<String>,
+    },
+    /// The user is not online anywhere.
+    Offline,
+    /// The user is online on another node.
+    Elsewhere,
+}
+
+impl Server {
+    /// Keeps the user of a dropped connection online for the grace period,
+    /// collecting whatever is sent to it, before it is let go for `reason`.
+    pub fn suspend(self: &Arc<Self>, uuid: &str, reason: QuitReason, message: &str) {
+        let grace = self.settings().resume_grace;
+        if grace.is_zero() {
+            self.unregister(uuid, reason, message);
+            return;
+        }
+        self.suspended.lock().unwrap().insert(
+            uuid.to_string(),
+            Suspended {
+                until: Instant::now() + grace,
+                reason,
+                message: message.to_string(),
+            },
+        );
+        let server = Arc::clone(self);
+        let uuid = uuid.to_string();
+        tokio::spawn(async move {
+            tokio::time::sleep(grace).await;
+            server.expire(&uuid);
+        });
+    }
+
+    fn expire(&self, uuid: &str) {
+        let Some(Suspended {
+            reason, message, ..
+        }) = ({
+            let mut suspended = self.suspended.lock().unwrap();
+            match suspended.get(uuid) {
+                Some(entry) if entry.until <= Instant::now() => suspended.remove(uuid),
+                _ => None,
+            }
+        })
+        else {
+            return;
+        };
+        log(
+            format!("{uuid} oturumu zamanında devralınmadı, kullanıcı çıkarılıyor."),
+            LogType::STATUS,
+        );
+        self.unregister(uuid, reason, &message);
+    }
+
+    /// Moves the user, and whatever is still queued for it, over to a new
+    /// connection. A connection still holding the session is closed.
+    pub fn reclaim(&self, uuid: &str, outbox: &Outbox, ip: &str, key: &str) -> Reclaim {
+        let mut users = self.users.lock().unwrap();
+        let Some(user) = users.get_mut(uuid) else {
+            return Reclaim::Offline;
+        };
+        let Route::Local(old) = &user.route else {
+            return Reclaim::Elsewhere;
+        };
+        outbox.adopt(old);
+        user.route = Route::Local(Arc::clone(outbox));
+        user.ip = ip.to_string();
+        user.key = key.to_string();
+        let username = user.username.clone();
+        drop(users);
+        self.suspended.lock().unwrap().remove(uuid);
+        let channels = self.channels.lock().unwrap();
+        let channels = channels
+            .iter()
+            .filter(|(_, entry)| entry.members.contains_key(uuid))
+            .map(|(name, _)| name.clone())
+            .collect();
+        Reclaim::Resumed { username, channels }
+    }
+
+    /// Whether the user is still served through this outbox, and has not
+    /// been taken over by another connection.
+    pub fn attached(&self, uuid: &str, outbox: &Outbox) -> bool {
+        let users = self.users.lock().unwrap();
+        matches!(
+            users.get(uuid).map(|user| &user.route),
+            Some(Route::Local(current)) if Arc::ptr_eq(current, outbox)
+        )
+    }
+}
@@ -0,0 +1,171 @@
+use crate::util::log::{log, LogType};
+use std::fs;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::client::danger::{
+    HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
+};
+use tokio_rustls::rustls::crypto::{
+    ring, verify_tls12_signature, verify_tls13_signature, CryptoProvider,
+};
+use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use tokio_rustls::rustls::{
+    ClientConfig, ConnectionCommon, DigitallySignedStruct, Error, ServerConfig, SignatureScheme,
+};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+pub const CERT_FILE: &str = "config/cert.pem";
+pub const KEY_FILE: &str = "config/key.pem";
+const BINDING_LABEL: &[u8] = b"EXPORTER-rustirc-link";
+
+/// Anything a connection handler can talk through: a plain socket or a TLS
+/// session on top of one.
+pub trait Transport: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Transport for T {}
+
+/// Buffered so the front end can be detected without consuming the first
+/// line, whether or not the bytes came through TLS.
+pub type Stream = tokio::io::BufReader<Box<dyn Transport>>;
+
+/// Loads the certificate chain and private key, generating a self-signed
+/// pair first if neither file exists yet.
+pub fn acceptor(cert: &str, key: &str, host: &str) -> Result<TlsAcceptor, String> {
+    if !Path::new(cert).exists() && !Path::new(key).exists() {
+        generate(cert, key, host)?;
+        log(
+            format!("{cert} ve {key} bulunamadı, kendinden imzalı bir sertifika oluşturuldu."),
+            LogType::WARN,
+        );
+    }
+
+    let mut reader = BufReader::new(fs::File::open(cert).map_err(|e| format!("{cert}: {e}"))?);
+    let chain = rustls_pemfile::certs(&mut reader)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("{cert}: {e}"))?;
+    if chain.is_empty() {
+        return Err(format!("{cert}: no certificate found"));
+    }
+
+    let mut reader = BufReader::new(fs::File::open(key).map_err(|e| format!("{key}: {e}"))?);
+    let private = rustls_pemfile::private_key(&mut reader)
+        .map_err(|e| format!("{key}: {e}"))?
+        .ok_or_else(|| format!("{key}: no private key found"))?;
+
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(chain, private)
+        .map_err(|e| e.to_string())?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+fn generate(cert: &str, key: &str, host: &str) -> Result<(), String> {
+    let mut names = vec![String::from("localhost")];
+    if host != "0.0.0.0" && host != "localhost" {
+        names.push(host.to_string());
+    }
+    let certified = rcgen::generate_simple_self_signed(names).map_err(|e| e.to_string())?;
+
+    for path in [cert, key] {
+        if let Some(parent) = Path::new(path).parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+    }
+    fs::write(cert, certified.cert.pem()).map_err(|e| format!("{cert}: {e}"))?;
+    fs::write(key, certified.key_pair.serialize_pem()).map_err(|e| format!("{key}: {e}"))?;
+    Ok(())
+}
+
+/// Keying material both ends of a TLS session derive alike but a relay in
+/// the middle, holding two sessions, cannot share between them.
+pub fn binding<Data>(connection: &ConnectionCommon<Data>) -> Option<String> {
+    connection
+        .export_keying_material([0u8; 32], BINDING_LABEL, None)
+        .ok()
+        .map(hex::encode)
+}
+
+/// Takes whatever certificate a linked server presents. Links prove the
+/// shared secret over the session's [`binding`] instead, which no one in
+/// the middle could pass on.
+#[derive(Debug)]
+struct Unverified {
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for Unverified {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+/// Dials a linked server through TLS. Returns the stream with the session's
+/// binding.
+pub async fn connect(target: &str) -> Result<(Stream, Option<String>), String> {
+    let socket = TcpStream::connect(target)
+        .await
+        .map_err(|e| e.to_string())?;
+    let host = target
+        .rsplit_once(':')
+        .map_or(target, |(host, _)| host)
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+    let name = ServerName::try_from(host.to_string()).map_err(|e| e.to_string())?;
+    let provider = Arc::new(ring::default_provider());
+    let config = ClientConfig::builder_with_provider(Arc::clone(&provider))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| e.to_string())?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(Unverified { provider }))
+        .with_no_client_auth();
+    let stream = TlsConnector::from(Arc::new(config))
+        .connect(name, socket)
+        .await
+        .map_err(|e| e.to_string())?;
+    let binding = binding(stream.get_ref().1);
+    Ok((tokio::io::BufReader::new(Box::new(stream)), binding))
+}
@@ -44,9 +44,10 @@ pub fn log(message: String, t: LogType) {
         LogType::ERROR => write("[ERROR]", Color::Red, true),
     };
     print!("{message}");
-  println!("");
+  println!();
 }
 
+#[allow(clippy::upper_case_acronyms)]
 pub enum LogType {
     INFO,
     WARN,
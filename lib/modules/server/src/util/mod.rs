@@ -1,2 +1,4 @@
+pub mod compare;
 pub mod generate_uuid;
-pub mod log;
\ No newline at end of file
+pub mod log;
+pub mod signal;
\ No newline at end of file
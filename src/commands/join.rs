@@ -1,4 +1,5 @@
 use crate::commands::Command;
+use crate::util::identity::KEY_FILE;
 
 pub const COMMAND: Command = Command {
     name: "join",
@@ -6,7 +7,7 @@ pub const COMMAND: Command = Command {
     args: &["addr"],
     description: "Belirli bir sunucuya katılıp sohbet etmek için kullanılır.",
     exec:|args, user, _| {
-        if args.len() < 1 {
+        if args.is_empty() {
             println!("Kullanım: /join <host>:<port>");
             return;
         }
@@ -19,8 +20,8 @@ pub const COMMAND: Command = Command {
         let command = std::process::Command::new("cmd")
             .arg("/C")
             .arg(format!(
-                "start lib/modules/client/client.exe -H {} -p {} -u {}",
-                host, port, username
+                "start lib/modules/client/client.exe -H {} -p {} -u {} -k {}",
+                host, port, username, KEY_FILE
             ))
             .spawn();
 
@@ -34,7 +35,6 @@ pub const COMMAND: Command = Command {
         }
     } else { 
         println!("Kullanım: /join <host>:<port>");
-        return;
     }
     },
 };
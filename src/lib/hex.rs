@@ -5,7 +5,7 @@ pub fn to_hex(t: String, bytes: &mut String) {
 }
 
 pub fn from_hex(hex: &str) -> Result<String, &'static str> {
-    if hex.len() % 2 != 0 {
+    if !hex.len().is_multiple_of(2) {
         return Err("Invalid hexadecimal input: length must be even.");
     }
 
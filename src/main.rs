@@ -1,3 +1,4 @@
+#![allow(special_module_name)]
 mod commands;
 use commands::Command;
 use std::collections::HashMap;
@@ -12,6 +13,7 @@ use rand::Rng;
 use std::fs;
 use std::io::{self, Write};
 use util::host::Host;
+use util::identity::{ensure_keypair, KEY_FILE};
 use util::session::{Privacy, Session};
 
 fn main() {
@@ -54,7 +56,7 @@ fn run() -> Result<()> {
 
 fn create_config_file() -> Result<()> {
     let config_dir = "config";
-    if !fs::metadata(config_dir).is_ok() {
+    if fs::metadata(config_dir).is_err() {
         fs::create_dir_all(config_dir)
             .with_context(|| format!("Dizin oluşturulamadı: {config_dir}"))?;
     }
@@ -168,6 +170,8 @@ fn start_session(dat_file: &str) -> Result<()> {
         invalidate_session()?;
     }
 
+    ensure_keypair(KEY_FILE)?;
+
     app(session);
     Ok(())
 }
@@ -224,14 +228,14 @@ async fn app(user: Session) {
             .read_line(&mut command)
             .expect("An error occurred");
         command = command.trim().to_string();
-        if command.starts_with("/") {
-            let parts: Vec<String> = command[1..]
+        if let Some(command) = command.strip_prefix("/") {
+            let parts: Vec<String> = command
                 .split_whitespace()
                 .map(|s| s.to_string())
                 .collect();
             if let Some((cmd_name, args)) = parts.split_first() {
                 if let Some(cmd) = commands.get(cmd_name) {
-                    println!("");
+                    println!();
                     (cmd.exec)(args.to_vec(), &user, &host);
                 } else {
                     println!("Bilinmeyen komut: /{}", cmd_name);
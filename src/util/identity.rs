@@ -0,0 +1,19 @@
+use anyhow::{Context, Result};
+use rsa::pkcs8::{EncodePrivateKey, LineEnding};
+use rsa::RsaPrivateKey;
+use std::fs;
+
+pub const KEY_FILE: &str = "config/identity.pem";
+const KEY_BITS: usize = 2048;
+
+pub fn ensure_keypair(key_file: &str) -> Result<()> {
+    if fs::metadata(key_file).is_ok() {
+        return Ok(());
+    }
+    println!("Kimlik anahtarı bulunamadı, yeni bir RSA anahtar çifti oluşturuluyor...");
+    let key = RsaPrivateKey::new(&mut rand::thread_rng(), KEY_BITS)
+        .context("RSA anahtar çifti oluşturulurken bir hata oluştu.")?;
+    key.write_pkcs8_pem_file(key_file, LineEnding::LF)
+        .with_context(|| format!("Anahtar dosyasına yazılamadı: {key_file}"))?;
+    Ok(())
+}
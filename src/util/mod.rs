@@ -1,2 +1,3 @@
 pub mod session;
-pub mod host;
\ No newline at end of file
+pub mod host;
+pub mod identity;
\ No newline at end of file